@@ -290,16 +290,28 @@ where
                 input_defaults.push(orig_default);
             }
 
-            // Type check
-            for (input_default, expected_type_id) in input_defaults.iter().zip(t.input_types()) {
-                if let Some(input_default) = input_default {
-                    if input_default.variant_name() != expected_type_id.name() {
-                        return Err(ImportError::UnexpectedDefaultInputType {
-                            expected: expected_type_id.name(),
-                            got: input_default.variant_name(),
-                            transform_name: t.name(),
-                            transform_idx: t_idx,
-                        });
+            // Type check. Default values saved with an older version of a
+            // transform may have another type than the one now expected
+            // (e.g. a Float input that became a Quantity). Convert them if
+            // possible.
+            for (input_default, expected_type_id) in input_defaults.iter_mut().zip(t.input_types())
+            {
+                if let Some(ref mut input_default) = input_default {
+                    let got = input_default.variant_name();
+                    let expected = expected_type_id.name();
+                    if got != expected {
+                        let converted = T::convert(got, expected, input_default)
+                            .map(|value| match value {
+                                Bow::Borrowed(value) => value.clone(),
+                                Bow::Owned(value) => value,
+                            })
+                            .ok_or_else(|| ImportError::UnexpectedDefaultInputType {
+                                expected,
+                                got,
+                                transform_name: t.name(),
+                                transform_idx: t_idx,
+                            })?;
+                        *input_default = converted;
                     }
                 }
             }
@@ -189,3 +189,42 @@ fn test_remove_node() {
         vec
     });
 }
+
+#[test]
+fn test_import_convertible_default() {
+    // Default input saved when "divide_by_10" still took an Integer
+    let s = r#"(
+        transforms: [
+            ((1), (
+                t: Function("divide_by_10", 1, 0, 0),
+                input_defaults: [Some(Integer(20))],
+            )),
+        ],
+        edges: [],
+        outputs: [((1), Some((t_idx: (1), output_i: (0))))],
+    )"#;
+    let dst: DST<AlgoIO, E> = de::from_str(s).unwrap();
+    let (output_id, output) = dst.outputs_iter().next().unwrap();
+    let output = output.unwrap();
+    let defaults = dst.get_default_inputs(output.t_idx).unwrap();
+    assert_eq!(defaults[0], Some(AlgoIO::Float(20.0)));
+
+    let mut cache = Cache::new();
+    assert_output_eq!(dst, *output_id, AlgoIO::Float(2.0), &mut cache);
+}
+
+#[test]
+fn test_import_unconvertible_default() {
+    let s = r#"(
+        transforms: [
+            ((1), (
+                t: Function("divide_by_10", 1, 0, 0),
+                input_defaults: [Some(Image2d([]))],
+            )),
+        ],
+        edges: [],
+        outputs: [],
+    )"#;
+    let dst: Result<DST<AlgoIO, E>, _> = de::from_str(s);
+    assert!(dst.is_err());
+}
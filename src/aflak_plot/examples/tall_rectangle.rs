@@ -44,8 +44,8 @@ fn main() {
                 textures,
                 imgui::ImTexture::from(1),
                 "pixel",
-                Some(&AxisTransform::new("X Axis", "m", |x| x)),
-                Some(&AxisTransform::new("Y Axis", "m", |y| y)),
+                Some(&AxisTransform::new("X Axis", "m", |x, _| x)),
+                Some(&AxisTransform::new("Y Axis", "m", |_, y| y)),
                 &mut state,
             )
             .expect("Image2d failed");
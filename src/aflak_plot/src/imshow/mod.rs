@@ -25,6 +25,10 @@ pub type Textures = imgui::Textures<Texture2d>;
 impl<'ui> UiImage2d for Ui<'ui> {
     /// Show image given as input.
     ///
+    /// `xaxis` and `yaxis` transform the pixel coordinates `(x, y)` of a point
+    /// of the image to world coordinates, with `(0, 0)` at the bottom-left
+    /// corner of the image.
+    ///
     /// The mutable reference `state` contains the current state of the user
     /// interaction with the window.
     ///
@@ -61,8 +65,8 @@ impl<'ui> UiImage2d for Ui<'ui> {
     ///             textures,
     ///             texture_id,
     ///             "<unit>",
-    ///             AxisTransform::none(),
-    ///             AxisTransform::none(),
+    ///             AxisTransform::none2d(),
+    ///             AxisTransform::none2d(),
     ///             &mut state,
     ///         ) {
     ///             eprintln!("{:?}", e);
@@ -85,8 +89,8 @@ impl<'ui> UiImage2d for Ui<'ui> {
     ) -> Result<(), Error>
     where
         F: Facade,
-        FX: Fn(f32) -> f32,
-        FY: Fn(f32) -> f32,
+        FX: Fn(f32, f32) -> f32,
+        FY: Fn(f32, f32) -> f32,
         I: Borrow<ArrayD<f32>>,
     {
         let window_pos = self.get_window_pos();
@@ -141,7 +145,7 @@ pub trait UiImage2d {
     ) -> Result<(), Error>
     where
         F: Facade,
-        FX: Fn(f32) -> f32,
-        FY: Fn(f32) -> f32,
+        FX: Fn(f32, f32) -> f32,
+        FY: Fn(f32, f32) -> f32,
         I: Borrow<ArrayD<f32>>;
 }
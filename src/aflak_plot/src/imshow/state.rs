@@ -7,8 +7,8 @@ use ndarray::ArrayD;
 
 use super::image;
 use super::interactions::{
-    FinedGrainedROI, HorizontalLine, Interaction, InteractionIterMut, Interactions, ValueIter,
-    VerticalLine,
+    FinedGrainedROI, HorizontalLine, Interaction, InteractionIterMut, Interactions, RoiShape,
+    ShapeROI, ValueIter, VerticalLine,
 };
 use super::lut::{BuiltinLUT, ColorLUT};
 use super::ticks::XYTicks;
@@ -292,18 +292,29 @@ where
         max_size: (f32, f32),
     ) -> Result<([(f32, f32); 2], f32), Error>
     where
-        FX: Fn(f32) -> f32,
-        FY: Fn(f32) -> f32,
+        FX: Fn(f32, f32) -> f32,
+        FY: Fn(f32, f32) -> f32,
     {
         const IMAGE_TOP_PADDING: f32 = 0.0;
 
         let tex_size = self.image.tex_size();
+        // Ticks are drawn along the bottom and left borders of the image
+        let xaxis_ticks = xaxis.map(|axis| {
+            AxisTransform::new(axis.label(), axis.unit(), move |x| {
+                axis.pix2world_2d(x, 0.0)
+            })
+        });
+        let yaxis_ticks = yaxis.map(|axis| {
+            AxisTransform::new(axis.label(), axis.unit(), move |y| {
+                axis.pix2world_2d(0.0, y)
+            })
+        });
         let ticks = XYTicks::prepare(
             ui,
             (0.0, tex_size.0 as f32),
             (0.0, tex_size.1 as f32),
-            xaxis,
-            yaxis,
+            xaxis_ticks.as_ref(),
+            yaxis_ticks.as_ref(),
         );
         let x_labels_height = ticks.x_labels_height();
         let y_labels_width = ticks.y_labels_width();
@@ -341,11 +352,11 @@ where
                 let index = [self.image.dim().0 - 1 - y, x];
                 if let Some(val) = self.image.get(index) {
                     let x_measurement = xaxis.map(|axis| Measurement {
-                        v: axis.pix2world(x as f32),
+                        v: axis.pix2world_2d(x as f32, y as f32),
                         unit: axis.unit(),
                     });
                     let y_measurement = yaxis.map(|axis| Measurement {
-                        v: axis.pix2world(y as f32),
+                        v: axis.pix2world_2d(x as f32, y as f32),
                         unit: axis.unit(),
                     });
                     let text = self.make_tooltip(
@@ -386,9 +397,53 @@ where
                     Interaction::FinedGrainedROI(FinedGrainedROI::new(self.roi_input.gen_id()));
                 self.interactions.insert(new);
             }
+            ui.separator();
+            let center = self.mouse_pixel();
+            let radius = (tex_size.0.min(tex_size.1) / 10.0).max(1.0);
+            let mut new_shape = None;
+            if ui.menu_item(im_str!("Circle ROI")).build() {
+                new_shape = Some(RoiShape::Circle { center, radius });
+            }
+            if ui.menu_item(im_str!("Ellipse ROI")).build() {
+                new_shape = Some(RoiShape::Ellipse {
+                    center,
+                    radii: (radius, radius / 2.0),
+                    angle: 0.0,
+                });
+            }
+            if ui.menu_item(im_str!("Box ROI")).build() {
+                new_shape = Some(RoiShape::Rectangle {
+                    center,
+                    size: (2.0 * radius, radius),
+                    angle: 0.0,
+                });
+            }
+            if ui.menu_item(im_str!("Annulus ROI")).build() {
+                new_shape = Some(RoiShape::Annulus {
+                    center,
+                    inner: radius / 2.0,
+                    outer: radius,
+                });
+            }
+            if ui.menu_item(im_str!("Polygon ROI")).build() {
+                // Triangle pointing up, rows being numbered from the top
+                let vertices = (0..3)
+                    .map(|k| {
+                        let a = (90.0 + 120.0 * k as f32).to_radians();
+                        (center.0 + radius * a.cos(), center.1 - radius * a.sin())
+                    })
+                    .collect();
+                new_shape = Some(RoiShape::Polygon(vertices));
+            }
+            if let Some(shape) = new_shape {
+                let new = Interaction::ShapeROI(ShapeROI::new(self.roi_input.gen_id(), shape));
+                self.interactions.insert(new);
+            }
         });
 
+        let mouse_pixel = self.mouse_pixel();
         let mut line_marked_for_deletion = None;
+        let mut roi_marked_for_deletion = None;
         for (id, interaction) in self.interactions.iter_mut() {
             ui.push_id(id.id());
             const LINE_COLOR: u32 = 0xFFFF_FFFF;
@@ -508,6 +563,105 @@ where
                         }
                     }
                 }
+                Interaction::ShapeROI(ShapeROI {
+                    id: roi_id,
+                    shape,
+                    moving,
+                }) => {
+                    let selected = self.roi_input.is_selected(*roi_id);
+
+                    const ROI_LINE_COLOR_SELECTED: u32 = 0xFF00_FFFF;
+                    const ROI_LINE_COLOR_UNSELECTED: u32 = 0x8000_FFFF;
+                    const HANDLE_SIZE: f32 = 4.0;
+
+                    let roi_color = if selected {
+                        ROI_LINE_COLOR_SELECTED
+                    } else {
+                        ROI_LINE_COLOR_UNSELECTED
+                    };
+                    // Pixel centers are at integer coordinates, rows are
+                    // numbered from the top
+                    let to_screen = |(x, y): (f32, f32)| {
+                        [
+                            p.0 + (x + 0.5) / tex_size.0 as f32 * size.0,
+                            p.1 + (y + 0.5) / tex_size.1 as f32 * size.1,
+                        ]
+                    };
+
+                    for polyline in shape.outline() {
+                        for (k, a) in polyline.iter().enumerate() {
+                            let b = polyline[(k + 1) % polyline.len()];
+                            draw_list
+                                .add_line(to_screen(*a), to_screen(b), roi_color)
+                                .build();
+                        }
+                    }
+
+                    if selected {
+                        let is_polygon = if let RoiShape::Polygon(_) = shape {
+                            true
+                        } else {
+                            false
+                        };
+                        let mut insert_vertex = None;
+                        let mut remove_vertex = None;
+                        for (k, handle) in shape.handles().into_iter().enumerate() {
+                            let pos = to_screen(handle);
+                            ui.push_id(k as i32);
+                            ui.set_cursor_screen_pos([pos[0] - HANDLE_SIZE, pos[1] - HANDLE_SIZE]);
+                            ui.invisible_button(
+                                im_str!("roi-handle"),
+                                [2.0 * HANDLE_SIZE, 2.0 * HANDLE_SIZE],
+                            );
+                            if ui.is_item_hovered() {
+                                ui.imgui().set_mouse_cursor(ImGuiMouseCursor::ResizeAll);
+                                if ui.imgui().is_mouse_clicked(ImMouseButton::Left) {
+                                    *moving = Some(k);
+                                }
+                                if ui.imgui().is_mouse_clicked(ImMouseButton::Right) {
+                                    ui.open_popup(im_str!("edit-roi"))
+                                }
+                            }
+                            draw_list
+                                .add_rect(
+                                    [pos[0] - HANDLE_SIZE, pos[1] - HANDLE_SIZE],
+                                    [pos[0] + HANDLE_SIZE, pos[1] + HANDLE_SIZE],
+                                    roi_color,
+                                )
+                                .filled(true)
+                                .build();
+
+                            ui.popup(im_str!("edit-roi"), || {
+                                if k == 0 {
+                                    if ui.menu_item(im_str!("Delete ROI")).build() {
+                                        roi_marked_for_deletion = Some(*id);
+                                    }
+                                } else if is_polygon {
+                                    if ui.menu_item(im_str!("Insert vertex")).build() {
+                                        insert_vertex = Some(k - 1);
+                                    }
+                                    if ui.menu_item(im_str!("Delete vertex")).build() {
+                                        remove_vertex = Some(k - 1);
+                                    }
+                                }
+                            });
+                            ui.pop_id();
+                        }
+                        if let Some(index) = insert_vertex {
+                            shape.insert_vertex(index);
+                        }
+                        if let Some(index) = remove_vertex {
+                            shape.remove_vertex(index);
+                        }
+                    }
+
+                    if let Some(handle) = *moving {
+                        shape.move_handle(handle, mouse_pixel);
+                    }
+                    if !ui.imgui().is_mouse_down(ImMouseButton::Left) {
+                        *moving = None;
+                    }
+                }
             }
             ui.pop_id();
         }
@@ -515,6 +669,9 @@ where
         if let Some(line_id) = line_marked_for_deletion {
             self.interactions.remove(line_id);
         }
+        if let Some(roi_id) = roi_marked_for_deletion {
+            self.interactions.remove(roi_id);
+        }
 
         ticks.draw(&draw_list, p, size);
 
@@ -580,6 +737,16 @@ where
         }
     }
 
+    /// Get mouse position in pixel coordinates of the image, i.e. the index
+    /// of the column and the index of the row.
+    fn mouse_pixel(&self) -> (f32, f32) {
+        let tex_size = self.image.tex_size();
+        (
+            self.mouse_pos.0 - 0.5,
+            tex_size.1 as f32 - self.mouse_pos.1 - 0.5,
+        )
+    }
+
     fn make_tooltip(
         &self,
         (x_p, y_p): (usize, usize),
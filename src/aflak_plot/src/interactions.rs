@@ -11,6 +11,7 @@ pub enum Value {
     Float2([f32; 2]),
     Float3([f32; 3]),
     FinedGrainedROI(Vec<(usize, usize)>),
+    ShapeROI(RoiShape),
 }
 
 impl From<i64> for Value {
@@ -33,6 +34,11 @@ impl From<[f32; 3]> for Value {
         Value::Float3(v)
     }
 }
+impl From<RoiShape> for Value {
+    fn from(v: RoiShape) -> Self {
+        Value::ShapeROI(v)
+    }
+}
 
 /// Possible interactions with UI.
 #[derive(Clone, Debug, PartialEq)]
@@ -40,6 +46,7 @@ pub enum Interaction {
     HorizontalLine(HorizontalLine),
     VerticalLine(VerticalLine),
     FinedGrainedROI(FinedGrainedROI),
+    ShapeROI(ShapeROI),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -57,6 +64,42 @@ pub struct FinedGrainedROI {
     pub(crate) id: usize,
     pub pixels: Vec<(usize, usize)>,
 }
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShapeROI {
+    pub(crate) id: usize,
+    pub shape: RoiShape,
+    /// Index of the handle being moved, if any
+    pub(crate) moving: Option<usize>,
+}
+
+/// A geometric region of interest.
+///
+/// Coordinates are in pixels: `x` is the index of the column and `y` the
+/// index of the row of the image. Angles are in degrees, counter-clockwise
+/// from the *x*-axis.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoiShape {
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    Ellipse {
+        center: (f32, f32),
+        radii: (f32, f32),
+        angle: f32,
+    },
+    Rectangle {
+        center: (f32, f32),
+        size: (f32, f32),
+        angle: f32,
+    },
+    Annulus {
+        center: (f32, f32),
+        inner: f32,
+        outer: f32,
+    },
+    Polygon(Vec<(f32, f32)>),
+}
 
 impl HorizontalLine {
     pub fn new(height: f32) -> Self {
@@ -82,6 +125,214 @@ impl FinedGrainedROI {
     }
 }
 
+impl ShapeROI {
+    pub fn new(id: usize, shape: RoiShape) -> Self {
+        Self {
+            id,
+            shape,
+            moving: None,
+        }
+    }
+}
+
+/// Number of segments used to draw the outline of an ellipse
+const ELLIPSE_SEGMENTS: usize = 48;
+
+impl RoiShape {
+    /// Get the positions of the handles used to edit the shape. The first
+    /// handle is the center of the shape and is used to move it.
+    pub(crate) fn handles(&self) -> Vec<(f32, f32)> {
+        match self {
+            RoiShape::Circle { center, radius } => vec![*center, (center.0 + radius, center.1)],
+            RoiShape::Ellipse {
+                center,
+                radii,
+                angle,
+            } => vec![
+                *center,
+                offset(*center, (radii.0, 0.0), *angle),
+                offset(*center, (0.0, radii.1), *angle),
+            ],
+            RoiShape::Rectangle {
+                center,
+                size,
+                angle,
+            } => vec![
+                *center,
+                offset(*center, (size.0 / 2.0, 0.0), *angle),
+                offset(*center, (0.0, size.1 / 2.0), *angle),
+            ],
+            RoiShape::Annulus {
+                center,
+                inner,
+                outer,
+            } => vec![
+                *center,
+                (center.0 + inner, center.1),
+                (center.0 + outer, center.1),
+            ],
+            RoiShape::Polygon(vertices) => {
+                let n = vertices.len().max(1) as f32;
+                let (x, y) = vertices
+                    .iter()
+                    .fold((0.0, 0.0), |acc, v| (acc.0 + v.0, acc.1 + v.1));
+                let mut handles = vec![(x / n, y / n)];
+                handles.extend_from_slice(vertices);
+                handles
+            }
+        }
+    }
+
+    /// Move handle number `handle` (as returned by `handles`) to `pos`.
+    pub(crate) fn move_handle(&mut self, handle: usize, pos: (f32, f32)) {
+        if handle == 0 {
+            let center = self.handles()[0];
+            self.translate((pos.0 - center.0, pos.1 - center.1));
+            return;
+        }
+        match self {
+            RoiShape::Circle { center, radius } => *radius = distance(*center, pos),
+            RoiShape::Ellipse {
+                center,
+                radii,
+                angle,
+            } => {
+                let (u, v) = offset((0.0, 0.0), (pos.0 - center.0, pos.1 - center.1), -*angle);
+                if handle == 1 {
+                    radii.0 = u.abs();
+                } else {
+                    radii.1 = v.abs();
+                }
+            }
+            RoiShape::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                let (u, v) = offset((0.0, 0.0), (pos.0 - center.0, pos.1 - center.1), -*angle);
+                if handle == 1 {
+                    size.0 = 2.0 * u.abs();
+                } else {
+                    size.1 = 2.0 * v.abs();
+                }
+            }
+            RoiShape::Annulus {
+                center,
+                inner,
+                outer,
+            } => {
+                let r = distance(*center, pos);
+                if handle == 1 {
+                    *inner = r.min(*outer);
+                } else {
+                    *outer = r.max(*inner);
+                }
+            }
+            RoiShape::Polygon(vertices) => {
+                if let Some(vertex) = vertices.get_mut(handle - 1) {
+                    *vertex = pos;
+                }
+            }
+        }
+    }
+
+    fn translate(&mut self, (dx, dy): (f32, f32)) {
+        match self {
+            RoiShape::Circle { center, .. }
+            | RoiShape::Ellipse { center, .. }
+            | RoiShape::Rectangle { center, .. }
+            | RoiShape::Annulus { center, .. } => {
+                center.0 += dx;
+                center.1 += dy;
+            }
+            RoiShape::Polygon(vertices) => {
+                for vertex in vertices.iter_mut() {
+                    vertex.0 += dx;
+                    vertex.1 += dy;
+                }
+            }
+        }
+    }
+
+    /// Insert a new vertex in the middle of the edge following vertex
+    /// `index` of a polygon. Do nothing for other shapes.
+    pub(crate) fn insert_vertex(&mut self, index: usize) {
+        if let RoiShape::Polygon(vertices) = self {
+            if index < vertices.len() {
+                let (a, b) = (vertices[index], vertices[(index + 1) % vertices.len()]);
+                vertices.insert(index + 1, ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0));
+            }
+        }
+    }
+
+    /// Remove vertex `index` of a polygon, unless the polygon is a triangle.
+    /// Do nothing for other shapes.
+    pub(crate) fn remove_vertex(&mut self, index: usize) {
+        if let RoiShape::Polygon(vertices) = self {
+            if index < vertices.len() && vertices.len() > 3 {
+                vertices.remove(index);
+            }
+        }
+    }
+
+    /// Get the outline of the shape as a list of closed polylines.
+    pub(crate) fn outline(&self) -> Vec<Vec<(f32, f32)>> {
+        match self {
+            RoiShape::Circle { center, radius } => {
+                vec![ellipse_outline(*center, (*radius, *radius), 0.0)]
+            }
+            RoiShape::Ellipse {
+                center,
+                radii,
+                angle,
+            } => vec![ellipse_outline(*center, *radii, *angle)],
+            RoiShape::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                let (w, h) = (size.0 / 2.0, size.1 / 2.0);
+                vec![[(-w, -h), (w, -h), (w, h), (-w, h)]
+                    .iter()
+                    .map(|corner| offset(*center, *corner, *angle))
+                    .collect()]
+            }
+            RoiShape::Annulus {
+                center,
+                inner,
+                outer,
+            } => vec![
+                ellipse_outline(*center, (*inner, *inner), 0.0),
+                ellipse_outline(*center, (*outer, *outer), 0.0),
+            ],
+            RoiShape::Polygon(vertices) => vec![vertices.clone()],
+        }
+    }
+}
+
+/// Get position of `center + v`, where `v` is rotated by `angle` degrees
+/// counter-clockwise.
+fn offset(center: (f32, f32), v: (f32, f32), angle: f32) -> (f32, f32) {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    (
+        center.0 + v.0 * cos - v.1 * sin,
+        center.1 + v.0 * sin + v.1 * cos,
+    )
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn ellipse_outline(center: (f32, f32), radii: (f32, f32), angle: f32) -> Vec<(f32, f32)> {
+    (0..ELLIPSE_SEGMENTS)
+        .map(|i| {
+            let t = i as f32 / ELLIPSE_SEGMENTS as f32 * 2.0 * ::std::f32::consts::PI;
+            offset(center, (radii.0 * t.cos(), radii.1 * t.sin()), angle)
+        })
+        .collect()
+}
+
 /// Record all interactions.
 ///
 /// Contains a counter that counts the number of interactions inserted.
@@ -117,6 +368,7 @@ impl Interactions {
             Interaction::HorizontalLine(HorizontalLine { moving, .. }) => *moving,
             Interaction::VerticalLine(VerticalLine { moving, .. }) => *moving,
             Interaction::FinedGrainedROI(..) => false,
+            Interaction::ShapeROI(ShapeROI { moving, .. }) => moving.is_some(),
         })
     }
 }
@@ -129,6 +381,7 @@ impl Interaction {
             Interaction::FinedGrainedROI(FinedGrainedROI { pixels, .. }) => {
                 Value::FinedGrainedROI(pixels.clone())
             }
+            Interaction::ShapeROI(ShapeROI { shape, .. }) => Value::ShapeROI(shape.clone()),
         }
     }
 
@@ -146,6 +399,10 @@ impl Interaction {
                 *x_pos = *f;
                 Ok(())
             }
+            (Interaction::ShapeROI(ShapeROI { ref mut shape, .. }), Value::ShapeROI(s)) => {
+                *shape = s.clone();
+                Ok(())
+            }
             interaction => Err(format!(
                 "Got unexpected value type: '{:?}' for an interaction '{:?}'",
                 value, interaction
@@ -203,8 +460,11 @@ impl<'a> Iterator for FilterRoi<'a> {
     type Item = (&'a InteractionId, &'a mut Interaction);
     fn next(&mut self) -> Option<Self::Item> {
         for x in &mut self.0 {
-            if let (_, Interaction::FinedGrainedROI(_)) = x {
-                return Some(x);
+            match x {
+                (_, Interaction::FinedGrainedROI(_)) | (_, Interaction::ShapeROI(_)) => {
+                    return Some(x);
+                }
+                _ => (),
             }
         }
         None
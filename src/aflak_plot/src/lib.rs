@@ -24,5 +24,7 @@ mod units;
 mod util;
 
 pub use err::Error;
-pub use interactions::{Interaction, InteractionId, InteractionIterMut, Value, ValueIter};
+pub use interactions::{
+    Interaction, InteractionId, InteractionIterMut, RoiShape, Value, ValueIter,
+};
 pub use units::AxisTransform;
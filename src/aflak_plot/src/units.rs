@@ -1,5 +1,10 @@
 /// Define transformation between pixel coordinates and world coordinates for
 /// an axis.
+///
+/// For 1D plots, the transform is a function of the pixel position on the
+/// axis. For 2D images, the transform is a function of both pixel coordinates
+/// `(x, y)` of a point, so that rotated and projected axes (e.g. right
+/// ascension and declination) are correctly represented.
 pub struct AxisTransform<'a, F> {
     label: &'a str,
     unit: &'a str,
@@ -19,7 +24,7 @@ impl<'a, F> AxisTransform<'a, F> {
 
     /// Get axis label.
     pub fn label(&self) -> &str {
-        &self.unit
+        &self.label
     }
 
     /// Get axis unit.
@@ -63,3 +68,18 @@ impl<'a, F: Fn(f32) -> f32> AxisTransform<'a, F> {
         (self.transform)(p)
     }
 }
+
+impl<'a, T> AxisTransform<'a, fn(T, T) -> T> {
+    /// Convenience function to get a None value for a 2D image.
+    pub fn none2d() -> Option<&'static Self> {
+        None
+    }
+}
+
+impl<'a, F: Fn(f32, f32) -> f32> AxisTransform<'a, F> {
+    /// Convert point `(x, y)` in pixel coordinates of a 2D image to world
+    /// coordinates.
+    pub fn pix2world_2d(&self, x: f32, y: f32) -> f32 {
+        (self.transform)(x, y)
+    }
+}
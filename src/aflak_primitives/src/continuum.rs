@@ -0,0 +1,208 @@
+//! Fit a continuum on spectra.
+//!
+//! Continua are fitted on samples `(x, y)` of a spectrum taken in line-free
+//! windows, either with a least-squares polynomial or with a natural cubic
+//! spline going through the mean of each window.
+use linalg::solve;
+
+/// A polynomial. Coefficients are sorted by increasing degree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polynomial(Vec<f64>);
+
+impl Polynomial {
+    /// Fit a polynomial of the given `degree` on samples `(x, y)` with the
+    /// least-squares method.
+    ///
+    /// Return `None` if there are not enough samples to fit the polynomial.
+    pub fn fit(samples: &[(f64, f64)], degree: usize) -> Option<Self> {
+        let n = degree + 1;
+        if samples.len() < n {
+            return None;
+        }
+        // Build normal equations (A^T A) c = A^T y
+        let mut ata = vec![vec![0.0; n]; n];
+        let mut aty = vec![0.0; n];
+        let mut powers = vec![0.0; 2 * n - 1];
+        for &(x, y) in samples {
+            let mut p = 1.0;
+            for power in powers.iter_mut() {
+                *power = p;
+                p *= x;
+            }
+            for (i, row) in ata.iter_mut().enumerate() {
+                for (j, v) in row.iter_mut().enumerate() {
+                    *v += powers[i + j];
+                }
+                aty[i] += powers[i] * y;
+            }
+        }
+        solve(ata, aty).map(Polynomial)
+    }
+
+    /// Evaluate polynomial at `x`.
+    pub fn eval(&self, x: f64) -> f64 {
+        self.0.iter().rev().fold(0.0, |acc, c| acc * x + c)
+    }
+}
+
+/// A natural cubic spline.
+///
+/// The spline is extrapolated linearly beyond its first and last knots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spline {
+    knots: Vec<(f64, f64)>,
+    /// Second derivative at each knot
+    d2: Vec<f64>,
+}
+
+impl Spline {
+    /// Make a natural cubic spline going through `knots`. Knots must be
+    /// sorted by strictly increasing `x`.
+    ///
+    /// Return `None` if `knots` is empty. A spline made of one knot is
+    /// constant, a spline made of two knots is linear.
+    pub fn new(knots: Vec<(f64, f64)>) -> Option<Self> {
+        let n = knots.len();
+        if n == 0 {
+            return None;
+        }
+        let mut d2 = vec![0.0; n];
+        if n > 2 {
+            // Solve tridiagonal system for inner second derivatives
+            let mut diag = vec![0.0; n];
+            let mut rhs = vec![0.0; n];
+            for i in 1..n - 1 {
+                let (x0, y0) = knots[i - 1];
+                let (x1, y1) = knots[i];
+                let (x2, y2) = knots[i + 1];
+                diag[i] = 2.0 * (x2 - x0);
+                rhs[i] = 6.0 * ((y2 - y1) / (x2 - x1) - (y1 - y0) / (x1 - x0));
+            }
+            for i in 2..n - 1 {
+                let h = knots[i].0 - knots[i - 1].0;
+                let m = h / diag[i - 1];
+                diag[i] -= m * h;
+                rhs[i] -= m * rhs[i - 1];
+            }
+            for i in (1..n - 1).rev() {
+                let h = knots[i + 1].0 - knots[i].0;
+                d2[i] = (rhs[i] - h * d2[i + 1]) / diag[i];
+            }
+        }
+        Some(Spline { knots, d2 })
+    }
+
+    /// Evaluate spline at `x`.
+    pub fn eval(&self, x: f64) -> f64 {
+        let n = self.knots.len();
+        if n == 1 {
+            return self.knots[0].1;
+        }
+        let (first, last) = (self.knots[0], self.knots[n - 1]);
+        if x < first.0 {
+            return first.1 + (x - first.0) * self.slope(0, 0.0);
+        } else if x > last.0 {
+            return last.1 + (x - last.0) * self.slope(n - 2, 1.0);
+        }
+        let i = match self.knots[1..].iter().position(|&(xk, _)| x <= xk) {
+            Some(i) => i,
+            None => n - 2,
+        };
+        let (x0, y0) = self.knots[i];
+        let (x1, y1) = self.knots[i + 1];
+        let h = x1 - x0;
+        let a = (x1 - x) / h;
+        let b = (x - x0) / h;
+        a * y0
+            + b * y1
+            + ((a * a * a - a) * self.d2[i] + (b * b * b - b) * self.d2[i + 1]) * h * h / 6.0
+    }
+
+    /// Derivative of segment `i` at relative position `t` (0 at knot `i`, 1
+    /// at knot `i + 1`).
+    fn slope(&self, i: usize, t: f64) -> f64 {
+        let (x0, y0) = self.knots[i];
+        let (x1, y1) = self.knots[i + 1];
+        let h = x1 - x0;
+        let (a, b) = (1.0 - t, t);
+        (y1 - y0) / h - (3.0 * a * a - 1.0) * h * self.d2[i] / 6.0
+            + (3.0 * b * b - 1.0) * h * self.d2[i + 1] / 6.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Polynomial, Spline};
+    use test_util::assert_close;
+
+    #[test]
+    fn test_polynomial_exact_fit() {
+        let f = |x: f64| 1.0 - 2.0 * x + 0.5 * x * x;
+        let samples: Vec<_> = (0..6).map(|i| (i as f64, f(i as f64))).collect();
+        let poly = Polynomial::fit(&samples, 2).unwrap();
+        assert_close(&poly.0, &[1.0, -2.0, 0.5], 1e-9);
+        assert_close(&[poly.eval(10.0)], &[f(10.0)], 1e-9);
+    }
+
+    #[test]
+    fn test_polynomial_least_squares() {
+        let samples = [(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (3.0, 1.0)];
+        let line = Polynomial::fit(&samples, 1).unwrap();
+        assert_close(&[line.eval(0.0)], &[0.2], 1e-9);
+        assert_close(&[line.eval(1.0)], &[0.4], 1e-9);
+
+        let constant = Polynomial::fit(&samples, 0).unwrap();
+        assert_close(&[constant.eval(42.0)], &[0.5], 1e-9);
+    }
+
+    #[test]
+    fn test_polynomial_cannot_fit() {
+        // Not enough samples
+        assert_eq!(Polynomial::fit(&[(0.0, 1.0)], 1), None);
+        assert_eq!(Polynomial::fit(&[], 0), None);
+        // All samples at the same position
+        assert_eq!(Polynomial::fit(&[(1.0, 1.0), (1.0, 2.0)], 1), None);
+    }
+
+    #[test]
+    fn test_spline_few_knots() {
+        assert_eq!(Spline::new(vec![]), None);
+
+        let constant = Spline::new(vec![(2.0, 3.0)]).unwrap();
+        assert_close(&[constant.eval(-10.0)], &[3.0], 1e-9);
+        assert_close(&[constant.eval(10.0)], &[3.0], 1e-9);
+
+        let linear = Spline::new(vec![(0.0, 1.0), (2.0, 5.0)]).unwrap();
+        for &x in &[-1.0, 0.5, 2.0, 3.0] {
+            assert_close(&[linear.eval(x)], &[1.0 + 2.0 * x], 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_spline_linear_knots() {
+        // Natural spline through aligned knots is the line itself
+        let knots: Vec<_> = [0.0, 1.0, 3.0, 4.5, 7.0]
+            .iter()
+            .map(|&x| (x, 3.0 * x - 2.0))
+            .collect();
+        let spline = Spline::new(knots).unwrap();
+        for &x in &[-2.0, 0.0, 0.5, 2.0, 4.0, 7.0, 9.0] {
+            assert_close(&[spline.eval(x)], &[3.0 * x - 2.0], 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_spline_curve() {
+        let spline = Spline::new(vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)]).unwrap();
+        // Spline goes through its knots
+        assert_close(&[spline.eval(0.0)], &[0.0], 1e-9);
+        assert_close(&[spline.eval(1.0)], &[1.0], 1e-9);
+        assert_close(&[spline.eval(2.0)], &[0.0], 1e-9);
+        // Second derivative is -3 on the middle knot and 0 on the edges
+        assert_close(&[spline.eval(0.5)], &[0.6875], 1e-9);
+        assert_close(&[spline.eval(1.5)], &[0.6875], 1e-9);
+        // Extrapolated linearly with the slope on the edges
+        assert_close(&[spline.eval(-1.0)], &[-1.5], 1e-9);
+        assert_close(&[spline.eval(4.0)], &[-3.0], 1e-9);
+    }
+}
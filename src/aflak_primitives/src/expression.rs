@@ -0,0 +1,599 @@
+//! Parse and evaluate pixel-wise arithmetic expressions such as
+//! `(a - b) / sqrt(c) + 2*log10(a)`.
+//!
+//! Values are n-dimensional arrays with a unit. Arrays are broadcast against
+//! each other following numpy's rules (shapes are compared starting from the
+//! last axis in ndarray order), and units are propagated.
+use std::error::Error;
+use std::fmt;
+
+use ndarray::{ArrayD, IxDyn};
+
+use physical_unit::PhysicalUnit;
+use unit::{Dimensioned, Unit};
+
+type Value = Dimensioned<ArrayD<f32>>;
+
+/// Error raised when an expression cannot be parsed or evaluated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionError(String);
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ExpressionError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A parsed arithmetic expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression(Node);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f32),
+    /// Index of variable
+    Variable(usize),
+    Neg(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Abs,
+    Min,
+    Max,
+}
+
+/// Name and number of arguments of each function
+const FUNCTIONS: &[(&str, Function, usize)] = &[
+    ("sqrt", Function::Sqrt, 1),
+    ("exp", Function::Exp, 1),
+    ("ln", Function::Ln, 1),
+    ("log", Function::Ln, 1),
+    ("log10", Function::Log10, 1),
+    ("sin", Function::Sin, 1),
+    ("cos", Function::Cos, 1),
+    ("tan", Function::Tan, 1),
+    ("asin", Function::Asin, 1),
+    ("acos", Function::Acos, 1),
+    ("atan", Function::Atan, 1),
+    ("abs", Function::Abs, 1),
+    ("min", Function::Min, 2),
+    ("max", Function::Max, 2),
+];
+
+impl Expression {
+    /// Parse `expression`. Variables in `variables` may be used in the
+    /// expression and are referred to by their index.
+    pub fn parse(expression: &str, variables: &[&str]) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            pos: 0,
+            variables,
+        };
+        let node = parser.expression()?;
+        if let Some(token) = parser.next() {
+            return Err(unexpected(Some(token)));
+        }
+        Ok(Expression(node))
+    }
+
+    /// Evaluate expression. `inputs` contains the value of each variable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are fewer `inputs` than variables used to parse the
+    /// expression.
+    pub fn eval(&self, inputs: &[Value]) -> Result<Value, ExpressionError> {
+        self.0.eval(inputs)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(ref name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+fn unexpected(token: Option<Token>) -> ExpressionError {
+    match token {
+        Some(token) => ExpressionError(format!("Unexpected '{}' in expression", token)),
+        None => ExpressionError("Unexpected end of expression".to_owned()),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ExpressionError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // Exponent, e.g. 1.5e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            let value = number
+                .parse()
+                .map_err(|_| ExpressionError(format!("Invalid number '{}'", number)))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let token = match c {
+                // Accept '**' as power operator
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    Token::Op('^')
+                }
+                '+' | '-' | '*' | '/' | '^' => Token::Op(c),
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ',' => Token::Comma,
+                _ => {
+                    return Err(ExpressionError(format!(
+                        "Unexpected character '{}' in expression",
+                        c
+                    )))
+                }
+            };
+            tokens.push(token);
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser.
+///
+/// ```text
+/// expression := term (('+' | '-') term)*
+/// term       := unary (('*' | '/') unary)*
+/// unary      := ('-' | '+') unary | power
+/// power      := primary ('^' unary)?
+/// primary    := number | variable | constant | function '(' arguments ')' | '(' expression ')'
+/// ```
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    variables: &'a [&'a str],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), ExpressionError> {
+        match self.next() {
+            Some(ref token) if token == expected => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Node, ExpressionError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Op('+')) => Operator::Add,
+                Some(&Token::Op('-')) => Operator::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, ExpressionError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Op('*')) => Operator::Mul,
+                Some(&Token::Op('/')) => Operator::Div,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        let sign = match self.peek() {
+            Some(&Token::Op('-')) => Some(true),
+            Some(&Token::Op('+')) => Some(false),
+            _ => None,
+        };
+        match sign {
+            Some(negative) => {
+                self.pos += 1;
+                let operand = self.unary()?;
+                Ok(if negative {
+                    Node::Neg(Box::new(operand))
+                } else {
+                    operand
+                })
+            }
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Node, ExpressionError> {
+        let base = self.primary()?;
+        if self.peek() == Some(&Token::Op('^')) {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            Ok(Node::Binary(
+                Operator::Pow,
+                Box::new(base),
+                Box::new(exponent),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, ExpressionError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::LParen) => {
+                let node = self.expression()?;
+                self.expect(&Token::RParen)?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    self.call(&name)
+                } else {
+                    self.variable(&name)
+                }
+            }
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<Node, ExpressionError> {
+        if let Some(i) = self.variables.iter().position(|v| *v == name) {
+            Ok(Node::Variable(i))
+        } else if name == "pi" {
+            Ok(Node::Number(::std::f32::consts::PI))
+        } else if name == "e" {
+            Ok(Node::Number(::std::f32::consts::E))
+        } else {
+            Err(ExpressionError(format!("Unknown variable '{}'", name)))
+        }
+    }
+
+    /// Parse arguments of function `name`. Opening parenthesis is already
+    /// consumed.
+    fn call(&mut self, name: &str) -> Result<Node, ExpressionError> {
+        let (function, arg_count) = match FUNCTIONS.iter().find(|f| f.0 == name) {
+            Some(&(_, function, arg_count)) => (function, arg_count),
+            None => return Err(ExpressionError(format!("Unknown function '{}'", name))),
+        };
+        let mut args = vec![self.expression()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.expression()?);
+        }
+        self.expect(&Token::RParen)?;
+        if args.len() != arg_count {
+            return Err(ExpressionError(format!(
+                "Function '{}' takes {} argument(s), got {}",
+                name,
+                arg_count,
+                args.len()
+            )));
+        }
+        Ok(Node::Call(function, args))
+    }
+}
+
+impl Node {
+    fn eval(&self, inputs: &[Value]) -> Result<Value, ExpressionError> {
+        match *self {
+            Node::Number(value) => Ok(Dimensioned::new(
+                ArrayD::from_elem(IxDyn(&[]), value),
+                Unit::None,
+            )),
+            Node::Variable(i) => Ok(inputs[i].clone()),
+            Node::Neg(ref operand) => {
+                let value = operand.eval(inputs)?;
+                Ok(value.with_new_value(value.scalar().map(|v| -v)))
+            }
+            Node::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.eval(inputs)?;
+                let rhs = rhs.eval(inputs)?;
+                match op {
+                    Operator::Add => add_like(&lhs, &rhs, "add", |a, b| a + b),
+                    Operator::Sub => add_like(&lhs, &rhs, "subtract", |a, b| a - b),
+                    Operator::Mul => {
+                        let values = zip_with(lhs.scalar(), rhs.scalar(), |a, b| a * b)?;
+                        Ok(Dimensioned::new(values, mul_unit(lhs.unit(), rhs.unit())))
+                    }
+                    Operator::Div => {
+                        let values = zip_with(lhs.scalar(), rhs.scalar(), |a, b| a / b)?;
+                        Ok(Dimensioned::new(values, div_unit(lhs.unit(), rhs.unit())))
+                    }
+                    Operator::Pow => pow(&lhs, &rhs),
+                }
+            }
+            Node::Call(function, ref args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(inputs))
+                    .collect::<Result<Vec<_>, _>>()?;
+                call(function, &args)
+            }
+        }
+    }
+}
+
+fn call(function: Function, args: &[Value]) -> Result<Value, ExpressionError> {
+    let f: fn(f32) -> f32 = match function {
+        Function::Sqrt => {
+            let unit = root_unit(args[0].unit(), 2)?;
+            return Ok(Dimensioned::new(args[0].scalar().map(|v| v.sqrt()), unit));
+        }
+        Function::Abs => return Ok(args[0].with_new_value(args[0].scalar().map(|v| v.abs()))),
+        Function::Min => return add_like(&args[0], &args[1], "compare", f32::min),
+        Function::Max => return add_like(&args[0], &args[1], "compare", f32::max),
+        Function::Exp => f32::exp,
+        Function::Ln => f32::ln,
+        Function::Log10 => f32::log10,
+        Function::Sin => f32::sin,
+        Function::Cos => f32::cos,
+        Function::Tan => f32::tan,
+        Function::Asin => f32::asin,
+        Function::Acos => f32::acos,
+        Function::Atan => f32::atan,
+    };
+    // Units of arguments of transcendental functions are dropped, except
+    // for dimensionless units (e.g. km/m) that are simplified.
+    let values = match *args[0].unit() {
+        Unit::Physical(ref unit) if unit.is_dimensionless() => args[0]
+            .value_in(&Unit::Physical(PhysicalUnit::dimensionless()))
+            .expect("Dimensionless units can be converted"),
+        _ => args[0].scalar().clone(),
+    };
+    Ok(Dimensioned::new(values.mapv(f), Unit::None))
+}
+
+/// Apply `f` on values with compatible units, such as in an addition. The
+/// right-hand side is converted to the unit of the left-hand side. Values
+/// without unit are assumed to be in the unit of the other side.
+fn add_like<F>(lhs: &Value, rhs: &Value, verb: &str, f: F) -> Result<Value, ExpressionError>
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let unit = if *lhs.unit() == Unit::None {
+        rhs.unit()
+    } else {
+        lhs.unit()
+    };
+    let rhs_values = rhs.value_in(unit).ok_or_else(|| {
+        ExpressionError(format!(
+            "Cannot {} values in '{}' and '{}'",
+            verb,
+            lhs.unit(),
+            rhs.unit()
+        ))
+    })?;
+    let values = zip_with(lhs.scalar(), &rhs_values, f)?;
+    Ok(Dimensioned::new(values, unit.clone()))
+}
+
+fn pow(base: &Value, exponent: &Value) -> Result<Value, ExpressionError> {
+    let unit = match *base.unit() {
+        Unit::None => Unit::None,
+        ref unit => {
+            let exponent = scalar_value(exponent).ok_or_else(|| {
+                ExpressionError(format!(
+                    "Exponent of a value in '{}' must be a number",
+                    unit
+                ))
+            })?;
+            pow_unit(unit, exponent)?
+        }
+    };
+    let exponent = dimensionless_values(exponent)?;
+    let values = zip_with(base.scalar(), &exponent, f32::powf)?;
+    Ok(Dimensioned::new(values, unit))
+}
+
+/// Get value of a dimensionless array made of a single value.
+fn scalar_value(value: &Value) -> Option<f32> {
+    if value.scalar().len() == 1 {
+        dimensionless_values(value)
+            .ok()
+            .and_then(|values| values.iter().next().cloned())
+    } else {
+        None
+    }
+}
+
+fn dimensionless_values(value: &Value) -> Result<ArrayD<f32>, ExpressionError> {
+    value
+        .value_in(&Unit::Physical(PhysicalUnit::dimensionless()))
+        .ok_or_else(|| {
+            ExpressionError(format!(
+                "Exponent must be dimensionless, got '{}'",
+                value.unit()
+            ))
+        })
+}
+
+fn mul_unit(lhs: &Unit, rhs: &Unit) -> Unit {
+    match (lhs, rhs) {
+        (&Unit::None, unit) | (unit, &Unit::None) => unit.clone(),
+        _ => lhs * rhs,
+    }
+}
+
+fn div_unit(lhs: &Unit, rhs: &Unit) -> Unit {
+    match (lhs, rhs) {
+        (unit, &Unit::None) => unit.clone(),
+        (&Unit::None, &Unit::Physical(ref unit)) => {
+            Unit::Physical(&PhysicalUnit::dimensionless() / unit)
+        }
+        (&Unit::None, _) => Unit::None,
+        _ => lhs / rhs,
+    }
+}
+
+fn pow_unit(unit: &Unit, exponent: f32) -> Result<Unit, ExpressionError> {
+    let error = || {
+        ExpressionError(format!(
+            "Cannot raise a value in '{}' to the power {}",
+            unit, exponent
+        ))
+    };
+    match *unit {
+        Unit::Physical(ref physical) => {
+            // Integer and half-integer exponents are supported
+            let twice = 2.0 * exponent;
+            if twice.fract() != 0.0 {
+                return Err(error());
+            }
+            let squared = physical.powi(twice as i32);
+            squared.root(2).map(Unit::Physical).ok_or_else(error)
+        }
+        Unit::None => Ok(Unit::None),
+        Unit::Custom(_) => Err(error()),
+    }
+}
+
+fn root_unit(unit: &Unit, n: i32) -> Result<Unit, ExpressionError> {
+    match *unit {
+        Unit::Physical(ref physical) => physical
+            .root(n)
+            .map(Unit::Physical)
+            .ok_or_else(|| ExpressionError(format!("Cannot take root {} of unit '{}'", n, unit))),
+        Unit::None => Ok(Unit::None),
+        Unit::Custom(_) => Err(ExpressionError(format!(
+            "Cannot take root {} of unit '{}'",
+            n, unit
+        ))),
+    }
+}
+
+/// Apply `f` element-wise on `a` and `b`, broadcasting them to a common
+/// shape.
+fn zip_with<F>(a: &ArrayD<f32>, b: &ArrayD<f32>, f: F) -> Result<ArrayD<f32>, ExpressionError>
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let shape = broadcast_shape(a.shape(), b.shape()).ok_or_else(|| {
+        ExpressionError(format!(
+            "Cannot broadcast arrays of shapes {:?} and {:?}",
+            a.shape(),
+            b.shape()
+        ))
+    })?;
+    let mut out = a
+        .broadcast(shape)
+        .expect("Shape is broadcastable")
+        .to_owned();
+    out.zip_mut_with(b, |x, y| *x = f(*x, *y));
+    Ok(out)
+}
+
+/// Compute shape to which arrays of shapes `a` and `b` are broadcast, if any.
+fn broadcast_shape(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    let ndim = a.len().max(b.len());
+    let mut shape = vec![0; ndim];
+    for (k, len) in shape.iter_mut().enumerate() {
+        // Compare shapes starting from the last axis
+        let len_a = if k < ndim - a.len() {
+            1
+        } else {
+            a[k - (ndim - a.len())]
+        };
+        let len_b = if k < ndim - b.len() {
+            1
+        } else {
+            b[k - (ndim - b.len())]
+        };
+        *len = if len_a == len_b || len_b == 1 {
+            len_a
+        } else if len_a == 1 {
+            len_b
+        } else {
+            return None;
+        };
+    }
+    Some(shape)
+}
@@ -1,7 +1,14 @@
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::{self, Range};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use fitrs::FitsDataArray;
+use fitrs::{Fits, FitsDataArray};
+use memmap::Mmap;
 use ndarray::{self, ArrayD, IxDyn};
 
 pub trait FitsDataToArray<Dimension> {
@@ -15,6 +22,8 @@ pub enum FitsArrayReadError {
     UnexpectedDimension { expected: usize, got: usize },
     ShapeError(ndarray::ShapeError),
     UnsupportedData(&'static str),
+    InvalidKeyword(String),
+    IoError(io::Error),
 }
 
 impl fmt::Display for FitsArrayReadError {
@@ -29,6 +38,10 @@ impl fmt::Display for FitsArrayReadError {
             FitsArrayReadError::UnsupportedData(format) => {
                 write!(f, "Unsupported data array format: '{}'.", format)
             }
+            FitsArrayReadError::InvalidKeyword(ref key) => {
+                write!(f, "Missing or invalid keyword '{}' in header.", key)
+            }
+            FitsArrayReadError::IoError(ref e) => write!(f, "Could not read data: {}", e),
         }
     }
 }
@@ -101,3 +114,401 @@ impl FitsDataToArray<IxDyn> for FitsDataArray<Option<u32>> {
         .map_err(FitsArrayReadError::ShapeError)
     }
 }
+
+/// Size of a FITS block in bytes. Headers and data are padded to a multiple
+/// of this size.
+const BLOCK_SIZE: usize = 2880;
+/// Size of a header card in bytes
+const CARD_SIZE: usize = 80;
+
+/// A FITS file opened by aflak.
+///
+/// Headers are read by fitrs and available through `Deref`. Image data is
+/// decoded by aflak, so that all values of BITPIX are supported, and that
+/// BSCALE, BZERO and BLANK keywords are applied.
+#[derive(Debug)]
+pub struct FitsFile {
+    path: PathBuf,
+    fits: Fits,
+}
+
+impl FitsFile {
+    /// Open FITS file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FitsFile> {
+        let path = path.as_ref();
+        let fits = Fits::open(path)?;
+        // Read all headers now. fitrs goes back to the primary HDU when it
+        // iterates past a partially read list of HDUs, so that HDUs would be
+        // indexed differently than with `map_image`.
+        fits.iter().count();
+        Ok(FitsFile {
+            path: path.to_owned(),
+            fits,
+        })
+    }
+
+    /// Path of the opened file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read and decode the image contained in HDU number `hdu_idx`.
+    ///
+    /// Integer values equal to BLANK are replaced by NaN. Other values are
+    /// scaled as `BZERO + BSCALE * value`.
+    pub fn read_image(&self, hdu_idx: usize) -> Result<ArrayD<f32>, FitsArrayReadError> {
+        self.map_image(hdu_idx).map(|image| image.read())
+    }
+
+    /// Memory-map the image contained in HDU number `hdu_idx`. No data is
+    /// read until requested.
+    pub fn map_image(&self, hdu_idx: usize) -> Result<MappedImage, FitsArrayReadError> {
+        let mut file = File::open(&self.path).map_err(FitsArrayReadError::IoError)?;
+        let (_, header, offset) =
+            walk_headers(&mut file, |n, _| n == hdu_idx)?.ok_or_else(|| {
+                FitsArrayReadError::IoError(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("No HDU #{} in file", hdu_idx),
+                ))
+            })?;
+        let bytes_per_value = header
+            .bytes_per_value()
+            .ok_or(FitsArrayReadError::UnsupportedData("BITPIX"))?;
+        if header.naxis.is_empty() {
+            return Err(FitsArrayReadError::UnexpectedDimension {
+                expected: 1,
+                got: 0,
+            });
+        }
+
+        // Safety: The file must not be modified while aflak is running.
+        // There is no way to enforce this, as with any other program reading
+        // a file.
+        let mmap = unsafe { Mmap::map(&file) }.map_err(FitsArrayReadError::IoError)?;
+        let end = offset + header.element_count() * bytes_per_value;
+        if mmap.len() < end {
+            return Err(FitsArrayReadError::IoError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Expected {} bytes of data, file is truncated", end - offset),
+            )));
+        }
+        Ok(MappedImage {
+            mmap: Arc::new(mmap),
+            offset,
+            shape: header.naxis.iter().rev().cloned().collect(),
+            header: Arc::new(header),
+        })
+    }
+
+    /// Index of the first HDU whose EXTNAME is `extname`, if any.
+    ///
+    /// HDUs are counted as in `map_image`.
+    pub fn extension_index(&self, extname: &str) -> Result<Option<usize>, FitsArrayReadError> {
+        let mut file = File::open(&self.path).map_err(FitsArrayReadError::IoError)?;
+        let found = walk_headers(&mut file, |_, header| {
+            header
+                .extname
+                .as_ref()
+                .map_or(false, |name| name == extname)
+        })?;
+        Ok(found.map(|(n, _, _)| n))
+    }
+}
+
+/// Read the headers of `file` from the primary HDU, until `found` returns
+/// true. Return the index of the matching HDU, its header and the offset of
+/// its data, or `None` if the end of the file is reached first.
+fn walk_headers<F>(
+    file: &mut File,
+    mut found: F,
+) -> Result<Option<(usize, DataHeader, usize)>, FitsArrayReadError>
+where
+    F: FnMut(usize, &DataHeader) -> bool,
+{
+    let len = file.metadata().map_err(FitsArrayReadError::IoError)?.len() as usize;
+    let mut offset = 0;
+    let mut n = 0;
+    while offset < len {
+        file.seek(SeekFrom::Start(offset as u64))
+            .map_err(FitsArrayReadError::IoError)?;
+        let header = DataHeader::read(file)?;
+        let data_offset = offset + header.header_size;
+        if found(n, &header) {
+            return Ok(Some((n, header, data_offset)));
+        }
+        offset = data_offset + padded(header.data_size());
+        n += 1;
+    }
+    Ok(None)
+}
+
+impl ops::Deref for FitsFile {
+    type Target = Fits;
+
+    fn deref(&self) -> &Fits {
+        &self.fits
+    }
+}
+
+/// Keywords of an HDU header used to find and decode its data.
+struct DataHeader {
+    /// Size of the header in bytes, including padding
+    header_size: usize,
+    /// EXTNAME value, without quotes
+    extname: Option<String>,
+    bitpix: i64,
+    /// NAXISn values, in FITS order
+    naxis: Vec<usize>,
+    pcount: usize,
+    gcount: usize,
+    bscale: f64,
+    bzero: f64,
+    blank: Option<i64>,
+}
+
+impl DataHeader {
+    /// Read header at the current position of `reader`, until the END card.
+    fn read<R: Read>(reader: &mut R) -> Result<DataHeader, FitsArrayReadError> {
+        let mut cards = HashMap::new();
+        let mut header_size = 0;
+        let mut block = [0; BLOCK_SIZE];
+        'blocks: loop {
+            reader
+                .read_exact(&mut block)
+                .map_err(FitsArrayReadError::IoError)?;
+            header_size += BLOCK_SIZE;
+            for card in block.chunks(CARD_SIZE) {
+                let key = String::from_utf8_lossy(&card[..8]);
+                let key = key.trim();
+                if key == "END" {
+                    break 'blocks;
+                }
+                if &card[8..10] == b"= " {
+                    let value = String::from_utf8_lossy(&card[10..]);
+                    let value = value.split('/').next().unwrap_or("").trim();
+                    cards.insert(key.to_owned(), value.to_owned());
+                }
+            }
+        }
+
+        let integer = |key: &str| -> Result<Option<i64>, FitsArrayReadError> {
+            cards
+                .get(key)
+                .map(|value| {
+                    value
+                        .parse::<i64>()
+                        .or_else(|_| value.parse::<f64>().map(|f| f as i64))
+                        .map_err(|_| FitsArrayReadError::InvalidKeyword(key.to_owned()))
+                })
+                .map_or(Ok(None), |value| value.map(Some))
+        };
+        let float = |key: &str, default: f64| -> Result<f64, FitsArrayReadError> {
+            cards.get(key).map_or(Ok(default), |value| {
+                value
+                    .replace('D', "E")
+                    .parse()
+                    .map_err(|_| FitsArrayReadError::InvalidKeyword(key.to_owned()))
+            })
+        };
+        let required = |key: &str| -> Result<i64, FitsArrayReadError> {
+            integer(key)?.ok_or_else(|| FitsArrayReadError::InvalidKeyword(key.to_owned()))
+        };
+
+        let bitpix = required("BITPIX")?;
+        let naxis_count = required("NAXIS")?;
+        let mut naxis = Vec::with_capacity(naxis_count as usize);
+        for n in 1..=naxis_count {
+            naxis.push(required(&format!("NAXIS{}", n))? as usize);
+        }
+        Ok(DataHeader {
+            header_size,
+            extname: cards
+                .get("EXTNAME")
+                .map(|value| value.trim_matches('\'').trim().to_owned()),
+            bitpix,
+            naxis,
+            pcount: integer("PCOUNT")?.unwrap_or(0) as usize,
+            gcount: integer("GCOUNT")?.unwrap_or(1) as usize,
+            bscale: float("BSCALE", 1.0)?,
+            bzero: float("BZERO", 0.0)?,
+            blank: integer("BLANK")?,
+        })
+    }
+
+    /// Number of elements in the data array
+    fn element_count(&self) -> usize {
+        if self.naxis.is_empty() {
+            0
+        } else {
+            self.naxis.iter().product()
+        }
+    }
+
+    /// Size of the data unit in bytes, without padding
+    fn data_size(&self) -> usize {
+        let bytes = self.bitpix.abs() as usize / 8;
+        if self.naxis.is_empty() {
+            0
+        } else {
+            bytes * self.gcount * (self.pcount + self.element_count())
+        }
+    }
+
+    /// Size of a value in bytes. Return `None` for invalid BITPIX values.
+    fn bytes_per_value(&self) -> Option<usize> {
+        match self.bitpix {
+            8 | 16 | 32 | 64 | -32 | -64 => Some(self.bitpix.abs() as usize / 8),
+            _ => None,
+        }
+    }
+
+    /// Decode raw big-endian `bytes` into scaled values.
+    fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        let values = bytes.chunks(self.bitpix.abs() as usize / 8);
+        match self.bitpix {
+            8 => values
+                .map(|v| self.scale_integer(i64::from(v[0])))
+                .collect(),
+            16 => values
+                .map(|v| self.scale_integer(i64::from(BigEndian::read_i16(v))))
+                .collect(),
+            32 => values
+                .map(|v| self.scale_integer(i64::from(BigEndian::read_i32(v))))
+                .collect(),
+            64 => values
+                .map(|v| self.scale_integer(BigEndian::read_i64(v)))
+                .collect(),
+            -32 => values
+                .map(|v| self.scale_float(f64::from(BigEndian::read_f32(v))))
+                .collect(),
+            _ => values
+                .map(|v| self.scale_float(BigEndian::read_f64(v)))
+                .collect(),
+        }
+    }
+
+    fn scale_integer(&self, value: i64) -> f32 {
+        if Some(value) == self.blank {
+            ::std::f32::NAN
+        } else {
+            (self.bzero + self.bscale * value as f64) as f32
+        }
+    }
+
+    fn scale_float(&self, value: f64) -> f32 {
+        (self.bzero + self.bscale * value) as f32
+    }
+}
+
+/// Data array of an HDU, memory-mapped from a FITS file.
+///
+/// Values are decoded only when they are read.
+#[derive(Clone)]
+pub struct MappedImage {
+    mmap: Arc<Mmap>,
+    /// Position of the data array in the file
+    offset: usize,
+    /// Shape of the data array, in ndarray order (i.e. the reverse of FITS
+    /// order)
+    shape: Vec<usize>,
+    header: Arc<DataHeader>,
+}
+
+impl MappedImage {
+    /// Number of values on each axis, in ndarray order (i.e. the reverse of
+    /// FITS order).
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// Read the whole data array.
+    pub fn read(&self) -> ArrayD<f32> {
+        self.frames(0..self.shape[0])
+    }
+
+    /// Read frames in `range` along the first axis (in ndarray order) of the
+    /// data array.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn frames(&self, range: Range<usize>) -> ArrayD<f32> {
+        let mut shape = self.shape.clone();
+        assert!(
+            range.start <= range.end && range.end <= shape[0],
+            "Frames {:?} out of bounds (got {} frames)",
+            range,
+            shape[0]
+        );
+        let frame_len: usize = shape[1..].iter().product();
+        let data = self.read_values(range.start * frame_len, range.end * frame_len);
+        shape[0] = range.end - range.start;
+        ArrayD::from_shape_vec(shape, data).expect("Shape matches data length")
+    }
+
+    /// Read values from index `start` to `end` of the flattened data array.
+    fn read_values(&self, start: usize, end: usize) -> Vec<f32> {
+        let bytes_per_value = self.header.bitpix.abs() as usize / 8;
+        let start = self.offset + start * bytes_per_value;
+        let end = self.offset + end * bytes_per_value;
+        self.header.decode(&self.mmap[start..end])
+    }
+}
+
+impl fmt::Debug for MappedImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedImage")
+            .field("offset", &self.offset)
+            .field("bitpix", &self.header.bitpix)
+            .field("shape", &self.shape())
+            .finish()
+    }
+}
+
+/// Round `size` up to a multiple of the FITS block size
+fn padded(size: usize) -> usize {
+    (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE
+}
+
+/// Read big-endian values
+struct BigEndian;
+
+impl BigEndian {
+    fn read_u16(bytes: &[u8]) -> u16 {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(bytes);
+        u16::from_be_bytes(buf)
+    }
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(bytes);
+        u32::from_be_bytes(buf)
+    }
+
+    fn read_u64(bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(bytes);
+        u64::from_be_bytes(buf)
+    }
+
+    fn read_i16(bytes: &[u8]) -> i16 {
+        BigEndian::read_u16(bytes) as i16
+    }
+
+    fn read_i32(bytes: &[u8]) -> i32 {
+        BigEndian::read_u32(bytes) as i32
+    }
+
+    fn read_i64(bytes: &[u8]) -> i64 {
+        BigEndian::read_u64(bytes) as i64
+    }
+
+    fn read_f32(bytes: &[u8]) -> f32 {
+        f32::from_bits(BigEndian::read_u32(bytes))
+    }
+
+    fn read_f64(bytes: &[u8]) -> f64 {
+        f64::from_bits(BigEndian::read_u64(bytes))
+    }
+}
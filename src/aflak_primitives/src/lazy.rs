@@ -0,0 +1,147 @@
+use std::ops::Range;
+
+use ndarray::{ArrayD, Axis, Slice};
+
+use fits::MappedImage;
+use unit::{WcsArray, WcsMeta};
+
+/// An image whose data may not be loaded in memory yet.
+///
+/// Images read from FITS files are memory-mapped. Values are only decoded
+/// and loaded in memory when frames are requested, so that a single frame of
+/// a cube bigger than the available memory can be viewed.
+#[derive(Clone, Debug)]
+pub enum LazyWcsArray {
+    /// Image already loaded in memory
+    Loaded(WcsArray),
+    /// Image memory-mapped from a FITS file.
+    Mapped {
+        /// Unit and world coordinates of the image
+        meta: WcsMeta,
+        /// Values of the image
+        data: MappedImage,
+        /// Variance of the values, which may be mapped from another HDU
+        variance: Option<MappedImage>,
+        /// Data-quality flags, which may be mapped from another HDU. Values
+        /// with non-zero flags are masked.
+        quality: Option<MappedImage>,
+    },
+}
+
+impl LazyWcsArray {
+    /// Get the number of values on each axis of the image.
+    pub fn shape(&self) -> &[usize] {
+        match *self {
+            LazyWcsArray::Loaded(ref image) => image.shape(),
+            LazyWcsArray::Mapped { ref data, .. } => data.shape(),
+        }
+    }
+
+    /// Edit the units and world coordinates of the image with `f`, without
+    /// loading its data in memory. Return `None` if `f` returns `None`.
+    pub fn with_meta<F>(&self, f: F) -> Option<LazyWcsArray>
+    where
+        F: FnOnce(&WcsMeta) -> Option<WcsMeta>,
+    {
+        match *self {
+            LazyWcsArray::Loaded(ref image) => {
+                f(&image.meta()).map(|meta| LazyWcsArray::Loaded(image.clone().with_meta(meta)))
+            }
+            LazyWcsArray::Mapped {
+                ref meta,
+                ref data,
+                ref variance,
+                ref quality,
+            } => f(meta).map(|meta| LazyWcsArray::Mapped {
+                meta,
+                data: data.clone(),
+                variance: variance.clone(),
+                quality: quality.clone(),
+            }),
+        }
+    }
+
+    /// Load the whole image in memory.
+    pub fn load(&self) -> WcsArray {
+        match *self {
+            LazyWcsArray::Loaded(ref image) => image.clone(),
+            LazyWcsArray::Mapped {
+                ref meta,
+                ref data,
+                ref variance,
+                ref quality,
+            } => meta
+                .with_image(data.read())
+                .with_variance(variance.as_ref().map(MappedImage::read))
+                .with_mask(
+                    quality
+                        .as_ref()
+                        .map(|quality| flags_to_mask(&quality.read())),
+                ),
+        }
+    }
+
+    /// Load frames in `range` along the first axis (in ndarray order) of the
+    /// image, with their variance and mask. World coordinates are shifted
+    /// accordingly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn load_frames(&self, range: Range<usize>) -> WcsArray {
+        let start = range.start;
+        let (meta, data, variance, mask) = match *self {
+            LazyWcsArray::Loaded(ref image) => {
+                let slice = |array: &ArrayD<f32>| {
+                    array
+                        .slice_axis(Axis(0), Slice::from(range.clone()))
+                        .to_owned()
+                };
+                let mask = image.mask().map(|mask| {
+                    mask.slice_axis(Axis(0), Slice::from(range.clone()))
+                        .to_owned()
+                });
+                (
+                    image.meta(),
+                    slice(image.scalar()),
+                    image.variance().map(slice),
+                    mask,
+                )
+            }
+            LazyWcsArray::Mapped {
+                ref meta,
+                ref data,
+                ref variance,
+                ref quality,
+            } => (
+                meta.clone(),
+                data.frames(range.clone()),
+                variance
+                    .as_ref()
+                    .map(|variance| variance.frames(range.clone())),
+                quality
+                    .as_ref()
+                    .map(|quality| flags_to_mask(&quality.frames(range.clone()))),
+            ),
+        };
+        // The first axis in ndarray order is the last axis in FITS order
+        let last = data.ndim() - 1;
+        let indices: Vec<_> = (0..data.ndim())
+            .map(|i| (i, if i == last { start as f32 } else { 0.0 }, 1.0))
+            .collect();
+        meta.make_slice(&indices, data)
+            .with_variance(variance)
+            .with_mask(mask)
+    }
+}
+
+/// Turn data-quality flags into a mask. Any non-zero flag is masked.
+fn flags_to_mask(flags: &ArrayD<f32>) -> ArrayD<bool> {
+    flags.mapv(|flag| flag != 0.0)
+}
+
+impl From<WcsArray> for LazyWcsArray {
+    fn from(image: WcsArray) -> Self {
+        LazyWcsArray::Loaded(image)
+    }
+}
@@ -16,6 +16,7 @@
 //! the lazy_static crate.
 #[macro_use]
 extern crate lazy_static;
+extern crate memmap;
 extern crate variant_name;
 #[macro_use]
 extern crate variant_name_derive;
@@ -24,18 +25,43 @@ extern crate aflak_cake as cake;
 pub extern crate fitrs;
 #[macro_use]
 pub extern crate ndarray;
+extern crate rayon;
+#[cfg(test)]
+extern crate ron;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+mod continuum;
+mod expression;
 mod fits;
+mod lazy;
+mod linalg;
+mod line_fit;
+mod physical_unit;
 #[macro_use]
 mod precond;
+mod region;
+mod resample;
 mod roi;
+mod smooth;
+mod spectral;
+mod stats;
+mod table;
+#[cfg(test)]
+mod test_util;
 mod unit;
+mod wcs;
 
+pub use expression::{Expression, ExpressionError};
+pub use fits::{FitsArrayReadError, FitsFile, MappedImage};
+pub use lazy::LazyWcsArray;
+pub use physical_unit::{ParseUnitError, PhysicalUnit};
+pub use region::RegionError;
 pub use roi::ROI;
-pub use unit::{Dimensioned, Unit, WcsArray};
+pub use table::{Column, ColumnData, Table, TableReadError};
+pub use unit::{Dimensioned, Unit, WcsArray, WcsMeta};
+pub use wcs::{Projection, Wcs};
 
 use std::error::Error;
 use std::fmt;
@@ -43,7 +69,8 @@ use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use ndarray::{Array1, Array2, ArrayD, ArrayViewD, Axis, Dimension, Slice};
+use ndarray::{Array1, Array2, ArrayD, ArrayViewD, Axis, Dimension, IxDyn, Slice};
+use rayon::prelude::*;
 use variant_name::VariantName;
 
 /// Value used for I/O in astronomical transforms.
@@ -53,6 +80,8 @@ use variant_name::VariantName;
 pub enum IOValue {
     Integer(i64),
     Float(f32),
+    /// Floating point value with a unit
+    Quantity(Dimensioned<f32>),
     Float2([f32; 2]),
     Float3([f32; 3]),
     Str(String),
@@ -60,8 +89,13 @@ pub enum IOValue {
     Path(PathBuf),
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
-    Fits(Arc<fitrs::Fits>),
+    Fits(Arc<FitsFile>),
     Image(WcsArray),
+    /// Image whose data is only loaded when needed
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    LazyImage(LazyWcsArray),
+    Table(Table),
     Map2dTo3dCoords(Array2<[f32; 3]>),
     Roi(roi::ROI),
 }
@@ -72,11 +106,13 @@ impl PartialEq for IOValue {
         match (self, val) {
             (Integer(i1), Integer(i2)) => i1 == i2,
             (Float(f1), Float(f2)) => f1 == f2,
+            (Quantity(q1), Quantity(q2)) => q1 == q2,
             (Float2(f1), Float2(f2)) => f1 == f2,
             (Float3(f1), Float3(f2)) => f1 == f2,
             (Str(s1), Str(s2)) => s1 == s2,
             (Bool(b1), Bool(b2)) => b1 == b2,
             (Image(i1), Image(i2)) => i1 == i2,
+            (Table(t1), Table(t2)) => t1 == t2,
             (Map2dTo3dCoords(m1), Map2dTo3dCoords(m2)) => m1 == m2,
             (Roi(r1), Roi(r2)) => r1 == r2,
             (Path(p1), Path(p2)) => p1 == p2,
@@ -130,16 +166,35 @@ lazy_static! {
                 }
             ),
             cake_transform!(
-                "Extract dataset from FITS file.",
+                "Extract dataset from FITS file.
+The dataset is memory-mapped. Only the frames used by the following nodes are loaded in memory.
+If variance is the name of an extension (e.g. STAT for MUSE cubes), the variance of the dataset is read from this extension and propagated through the pipeline.
+If mask is the name of an extension (e.g. DQ), values with non-zero data-quality flags in this extension are masked and excluded from reductions.",
+                1, 3, 0,
+                fits_to_image<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 0, extension: Str = "".to_owned(), variance: Str = "".to_owned(), mask: Str = "".to_owned()) -> LazyImage {
+                    vec![run_fits_to_image(fits, *hdu_idx, extension, variance, mask)]
+                }
+            ),
+            cake_transform!(
+                "Extract table from an ASCII or binary table extension of a FITS file.
+Parameters: fits, hdu_idx, extension, columns (comma-separated list of column names, all columns if empty).",
                 1, 0, 0,
-                fits_to_image<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 0, extension: Str = "".to_owned()) -> Image {
-                    vec![run_fits_to_image(fits, *hdu_idx, extension)]
+                fits_to_table<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 1, extension: Str = "".to_owned(), columns: Str = "".to_owned()) -> Table {
+                    vec![run_fits_to_table(fits, *hdu_idx, extension, columns)]
                 }
             ),
             cake_transform!(
-                "Slice one frame of a n-dimensional dataset turning it into an (n-1)-dimensional dataset.",
+                "Save image to a FITS file. Units and world coordinates are saved as FITS keywords.
+Output the path of the saved file.",
                 1, 0, 0,
-                slice_one_frame<IOValue, IOErr>(image: Image, frame: Integer = 0) -> Image {
+                save_fits<IOValue, IOErr>(image: Image, path: Path) -> Path {
+                    vec![run_save_fits(image, path)]
+                }
+            ),
+            cake_transform!(
+                "Slice one frame of a n-dimensional dataset turning it into an (n-1)-dimensional dataset.",
+                1, 1, 0,
+                slice_one_frame<IOValue, IOErr>(image: LazyImage, frame: Integer = 0) -> Image {
                     vec![run_slice_one_frame(image, *frame)]
                 }
             ),
@@ -171,17 +226,18 @@ for 0 <= i < n and 0 <= j < m",
             ),
             cake_transform!(
                 "Extract waveform from image with the provided region of interest.",
-                1, 0, 0,
-                extract_wave<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All) -> Image {
+                1, 1, 0,
+                extract_wave<IOValue, IOErr>(image: LazyImage, roi: Roi = roi::ROI::All) -> Image {
                     vec![run_extract_wave(image, roi)]
                 }
             ),
             cake_transform!("Replace all values above or below a threshold in a image with NaN.
-Takes two parameters: a threshold and a bool.
-If bool value is checked, then replaces the values above the threshold with NaN, else replace the values below the threshold with NaN.",
-                1, 0, 0,
-                clip_image<IOValue, IOErr>(image: Image, threshold: Float = 0.0, above: Bool = false) -> Image {
-                    vec![run_clip(image, *threshold, *above)]
+Takes three parameters: a threshold and two bools.
+If above is checked, then replaces the values above the threshold with NaN, else replace the values below the threshold with NaN.
+If as_mask is checked, the values are masked instead of being replaced with NaN. Values already masked stay masked.",
+                1, 1, 0,
+                clip_image<IOValue, IOErr>(image: Image, threshold: Float = 0.0, above: Bool = false, as_mask: Bool = false) -> Image {
+                    vec![run_clip(image, *threshold, *above, *as_mask)]
                 }
             ),
             cake_transform!("Replace all NaN values in image with the provided value.",
@@ -211,8 +267,8 @@ Compute Sum[k, {a, b}]image[k]. image[k] is k-th slice of image.
 Second output contains (a + b) / 2
 Third output contains (b - a)
 Note: indices for a and b start from 0",
-                1, 0, 0,
-                integral<IOValue, IOErr>(image: Image, start: Integer = 0, end: Integer = 1) -> Image, Float, Float {
+                1, 1, 0,
+                integral<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1) -> Image, Float, Float {
                     let middle = (*start as f32 + *end as f32) / 2.0;
                     let width = *end as f32 - *start as f32;
                     vec![run_integral(image, *start, *end), Ok(IOValue::Float(middle)), Ok(IOValue::Float(width))]
@@ -244,21 +300,32 @@ Compute (Sum[k, {a, b}]image[k]) / (b - a). image[k] is k-th slice of image.
 Second output contains (a + b) / 2
 Third output contains (b - a)
 Note: indices for a and b start from 0",
-                1, 0, 0,
-                average<IOValue, IOErr>(image: Image, start: Integer = 0, end: Integer = 1) -> Image, Float, Float {
+                1, 1, 0,
+                average<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1) -> Image, Float, Float {
                     let middle = (*start as f32 + *end as f32) / 2.0;
                     let width = *end as f32 - *start as f32;
                     vec![run_average(image, *start, *end), Ok(IOValue::Float(middle)), Ok(IOValue::Float(width))]
                 }
             ),
+            cake_transform!(
+                "Reduce image along an axis. Parameters: image, axis, kind.
+Axes are numbered in FITS order starting from 0 (the spectral axis of a cube is usually axis 2, reducing a cube along a spatial axis gives a position-velocity diagram).
+kind is one of sum, mean, median, min, max, std or argmax. argmax outputs the world coordinate of the maximum along axis.
+NaN and masked values are ignored. The variance is propagated by sum and mean.",
+                1, 0, 0,
+                reduce<IOValue, IOErr>(image: LazyImage, axis: Integer = 2, kind: Str = "sum".to_owned()) -> Image {
+                    vec![run_reduce(image, *axis, kind)]
+                }
+            ),
             cake_transform!(
                 "Create Equivalent-Width map from off-band and on-band.
 Parameters i_off, i_on, onband-width, min, is_emission.
 Compute value = (i1 - i2) * fl / i1 (if is_emission is true, the sign of this value turns over).
-if value > max, value changes to 0.",
-                0, 1, 0,
-                create_equivalent_width<IOValue, IOErr>(i_off: Image, i_on: Image, fl: Float = 1.0, max: Float = ::std::f32::INFINITY, is_emission: Bool = false) -> Image {
-                    vec![run_create_equivalent_width(i_off, i_on, *fl, *max, *is_emission)]
+if value > max, value changes to 0.
+The unit of the output is the unit of fl (e.g. Angstrom).",
+                0, 2, 0,
+                create_equivalent_width<IOValue, IOErr>(i_off: Image, i_on: Image, fl: Quantity = Dimensioned::new(1.0, Unit::None), max: Float = ::std::f32::INFINITY, is_emission: Bool = false) -> Image {
+                    vec![run_create_equivalent_width(i_off, i_on, fl, *max, *is_emission)]
                 }
             ),
             cake_transform!(
@@ -271,29 +338,48 @@ Compute y = log(ax + 1) / log(a)  (x = (value - v_min) / (v_max - v_min))",
             ),
             cake_transform!(
                 "Image's min and max value. Parameter: image.
-Compute v_min(first), v_max(second)",
+Compute v_min(first), v_max(second). Masked values are ignored.",
                 1, 0, 0,
                 image_min_max<IOValue, IOErr>(image: Image) -> Float, Float {
                     let mut min = std::f32::MAX;
                     let mut max = std::f32::MIN;
-                    let image_arr = image.scalar();
 
-                    for i in image_arr {
-                        min = min.min(*i);
-                        max = max.max(*i);
+                    for i in unmasked_values(image) {
+                        min = min.min(i);
+                        max = max.max(i);
                     }
 
                     vec![Ok(IOValue::Float(min)), Ok(IOValue::Float(max))]
                 }
             ),
+            cake_transform!(
+                "Statistics of image values in a region of interest. Parameters: image, roi, sigma, iterations.
+Outputs are the mean, median, standard deviation and median absolute deviation (MAD) of the values, then the mean, median and standard deviation after sigma clipping, and the number of values.
+Sigma clipping rejects values farther than sigma standard deviations from the median, and is repeated at most iterations times.
+The region of interest is applied to each frame of image. NaN and masked values are ignored.",
+                1, 0, 0,
+                statistics<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All, sigma: Float = 3.0, iterations: Integer = 5) -> Quantity, Quantity, Quantity, Quantity, Quantity, Quantity, Quantity, Integer {
+                    run_statistics(image, roi, *sigma, *iterations)
+                }
+            ),
+            cake_transform!(
+                "Statistic map for Image. Parameters: image, a=start, b=end (a <= b), statistic, sigma, iterations.
+Compute the statistic of image[k] for k in (a, b) for each pixel. image[k] is k-th slice of image.
+statistic is one of mean, median, std, mad, clipped_mean, clipped_median or clipped_std. sigma and iterations are used for sigma clipping.
+NaN and masked values are ignored.",
+                1, 0, 0,
+                statistics_map<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1, statistic: Str = "median".to_owned(), sigma: Float = 3.0, iterations: Integer = 5) -> Image {
+                    vec![run_statistics_map(image, *start, *end, statistic, *sigma, *iterations)]
+                }
+            ),
             cake_transform!(
                 "Extract min/max wavelength value of each pixel.
 Parameter: image, start, end, is_min (start <= end)
 Output argmax/argmin map of flux; wavelength
 Second output contains max/min flux map
 Note: output wavelength values are discrete. indices for start and end start from 0",
-                0, 1, 0,
-                extract_argmin_max_wavelength<IOValue, IOErr>(image: Image, start: Integer = 0, end: Integer = 1, is_min: Bool = false) -> Image, Image {
+                0, 2, 0,
+                extract_argmin_max_wavelength<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1, is_min: Bool = false) -> Image, Image {
                     vec![run_argminmax(image, *start, *end, *is_min), run_minmax(image, *start, *end, *is_min)]
                 }
             ),
@@ -302,25 +388,19 @@ Note: output wavelength values are discrete. indices for start and end start fro
 Parameter: image (which has wavelength value w_i and flux f_i), start, end
 Compute Sum[k, (start, end)](f_k * w_k) / Sum(k, (start, end)(f_k))
 Note: indices for start and end start from 0",
-                0, 1, 0,
-                extract_centrobaric_wavelength<IOValue, IOErr>(image: Image, start: Integer = 0, end: Integer = 1) -> Image {
+                0, 2, 0,
+                extract_centrobaric_wavelength<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1) -> Image {
                     vec![run_centroid(image, *start, *end)]
                 }
             ),
             cake_transform!(
                 "Create velocity field map
 Parameter: image (which has wavelength value w_i in each pixel), representative wavelength w_0
-Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
-                1, 0, 0,
-                create_velocity_field_map<IOValue, IOErr>(image: Image, w_0: Float = 0.0) -> Image {
-                    let c = 3e5;
-                    let image_arr = image.scalar();
-                    let out = image_arr.map(|v| c * (*v - w_0) / w_0);
-
-                    vec![Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-                        out,
-                        Unit::Custom("km/s".to_string()),
-                    ))))]
+Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])
+w_0 is converted to the unit of the image. If w_0 has no unit, it is assumed to be in the unit of the image.",
+                1, 1, 0,
+                create_velocity_field_map<IOValue, IOErr>(image: Image, w_0: Quantity = Dimensioned::new(0.0, Unit::None)) -> Image {
+                    vec![run_create_velocity_field_map(image, w_0)]
                 }
             ),
             cake_transform!(
@@ -330,6 +410,303 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
                     vec![run_negation(image)]
                 }
             ),
+            cake_transform!(
+                "Attach variance to image. Parameters: image, variance, is_error.
+If is_error is checked, variance contains standard errors instead, which are squared.
+The variance is propagated by integral, average, linear_composition, negation, slice_one_frame, extract_wave and convert_unit.",
+                1, 0, 0,
+                set_variance<IOValue, IOErr>(image: Image, variance: Image, is_error: Bool = false) -> Image {
+                    vec![run_set_variance(image, variance, *is_error)]
+                }
+            ),
+            cake_transform!(
+                "Extract variance of image. Parameter: image.
+The unit of the output is the square of the unit of image.",
+                1, 0, 0,
+                variance<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_variance(image)]
+                }
+            ),
+            cake_transform!(
+                "Signal-to-noise ratio map. Parameter: image.
+Compute image / sqrt(variance).",
+                1, 0, 0,
+                signal_to_noise<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_signal_to_noise(image)]
+                }
+            ),
+            cake_transform!(
+                "Attach mask to image. Parameters: image, mask.
+Values of image are masked where mask is non-zero or NaN. Masked values are ignored by integral, average, image_min_max, extract_wave and extract_centrobaric_wavelength.",
+                1, 0, 0,
+                set_mask<IOValue, IOErr>(image: Image, mask: Image) -> Image {
+                    vec![run_set_mask(image, mask)]
+                }
+            ),
+            cake_transform!(
+                "Mask the values of image in the region of interest. Parameters: image, roi, outside.
+If outside is checked, the values outside of the region of interest are masked instead. The region is applied to each frame of image.
+Values already masked stay masked.",
+                1, 0, 0,
+                mask_roi<IOValue, IOErr>(image: Image, roi: Roi, outside: Bool = false) -> Image {
+                    vec![run_mask_roi(image, roi, *outside)]
+                }
+            ),
+            cake_transform!(
+                "Combine the masks of two images. Parameters: image, other, operation.
+Operation is one of 'or', 'and' or 'xor'. Output image with the combined mask. Images without a mask are considered as unmasked.",
+                1, 0, 0,
+                combine_masks<IOValue, IOErr>(image: Image, other: Image, operation: Str = "or".to_owned()) -> Image {
+                    vec![run_combine_masks(image, other, operation)]
+                }
+            ),
+            cake_transform!(
+                "Replace the masked values of image with the provided value and remove the mask.",
+                1, 0, 0,
+                apply_mask<IOValue, IOErr>(image: Image, placeholder: Float = ::std::f32::NAN) -> Image {
+                    vec![run_apply_mask(image, *placeholder)]
+                }
+            ),
+            cake_transform!(
+                "Extract mask of image. Parameter: image.
+Output 1 for masked values and 0 elsewhere.",
+                1, 0, 0,
+                mask<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_mask(image)]
+                }
+            ),
+            cake_transform!(
+                "Compute moment maps of image along its spectral axis (axis 2 in FITS order). Parameters: image, start, end, w_0, threshold, clip.
+Velocities are computed from the wavelength of each frame with respect to the rest wavelength w_0.
+First output is moment 0 (integrated intensity Sum[k, (start, end)](f_k * dv_k)).
+Second output is moment 1 (intensity-weighted velocity), third output is moment 2 (velocity dispersion).
+If clip is checked, only values above threshold are used. NaN values are ignored.
+Note: indices for start and end start from 0",
+                1, 0, 0,
+                moment_maps<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1, w_0: Quantity = Dimensioned::new(0.0, Unit::None), threshold: Float = 0.0, clip: Bool = false) -> Image, Image, Image {
+                    run_moment_maps(image, *start, *end, w_0, *threshold, *clip)
+                }
+            ),
+            cake_transform!(
+                "Evaluate an arithmetic expression on each pixel. Parameters: expr, a, b, c, d.
+expr may use variables a, b, c and d, numbers, operators + - * / ^, parentheses, constants pi and e,
+and functions sqrt, exp, ln, log10, sin, cos, tan, asin, acos, atan, abs, min and max. E.g. '(a - b) / sqrt(c) + 2*log10(a)'.
+Inputs are broadcast against each other (e.g. a 2D image applies to each frame of a cube) and units are propagated.
+Floats and quantities may be used as inputs. Unconnected inputs are NaN.
+The number of operands is fixed to 4: chain expressions to combine more inputs.",
+                1, 0, 0,
+                expression<IOValue, IOErr>(expr: Str = "a".to_owned(), a: Image, b: Image = nan_image(), c: Image = nan_image(), d: Image = nan_image()) -> Image {
+                    vec![run_expression(expr, &[a, b, c, d])]
+                }
+            ),
+            cake_transform!(
+                "Convert values of image to another unit. Parameters: image, unit (e.g. 'Jy', 'erg/s/cm2/Angstrom').
+If image has no unit, unit is assigned to it.",
+                1, 0, 0,
+                convert_unit<IOValue, IOErr>(image: Image, unit: Str = "".to_owned()) -> Image {
+                    vec![run_convert_unit(image, unit)]
+                }
+            ),
+            cake_transform!(
+                "Convert world coordinates along an axis of image to another unit. Parameters: image, axis, unit (e.g. 'nm', 'um').
+Axes are numbered in FITS order starting from 0 (the spectral axis of a cube is usually axis 2).
+If axis has no unit, unit is assigned to it.",
+                1, 0, 0,
+                convert_axis_unit<IOValue, IOErr>(image: Image, axis: Integer = 2, unit: Str = "".to_owned()) -> Image {
+                    vec![run_convert_axis_unit(image, *axis, unit)]
+                }
+            ),
+            cake_transform!(
+                "Shift the spectral axis (axis 2 in FITS order) of image to the rest frame. Parameters: image, z (redshift).
+Wavelengths are divided by (1 + z). Frequencies (FREQ axis) are multiplied by (1 + z).
+Only world coordinates are changed, the values of image are left as is.",
+                1, 0, 0,
+                redshift_correction<IOValue, IOErr>(image: LazyImage, z: Float = 0.0) -> LazyImage {
+                    vec![run_redshift_correction(image, *z)]
+                }
+            ),
+            cake_transform!(
+                "Convert wavelengths of the spectral axis (axis 2 in FITS order) of image between air and vacuum. Parameters: image, to_vacuum.
+If to_vacuum is checked, air wavelengths are converted to vacuum wavelengths, else vacuum wavelengths are converted to air wavelengths.
+The refractive index of air of Morton (2000) is used, valid above 2000 Angstrom. Wavelengths are assumed to be in Angstrom if the axis has no unit.
+The axis stays linear, with a conversion exact at both ends of the axis (the error is below 0.01 Angstrom across the optical range). Only world coordinates are changed, the values of image are left as is.",
+                1, 0, 0,
+                air_vacuum_conversion<IOValue, IOErr>(image: LazyImage, to_vacuum: Bool = true) -> LazyImage {
+                    vec![run_air_vacuum_conversion(image, *to_vacuum)]
+                }
+            ),
+            cake_transform!(
+                "Gaussian smoothing along an axis of image. Parameters: image, sigma (in pixels), axis.
+Axes are numbered in FITS order starting from 0 (the spectral axis of a cube is usually axis 2).
+NaN values are ignored.",
+                1, 0, 0,
+                gaussian_smooth<IOValue, IOErr>(image: Image, sigma: Float = 1.0, axis: Integer = 2) -> Image {
+                    vec![run_gaussian_smooth(image, *sigma, Some(*axis))]
+                }
+            ),
+            cake_transform!(
+                "Gaussian smoothing over the spatial plane (axes 0 and 1) of image. Parameters: image, sigma (in pixels).
+NaN values are ignored.",
+                1, 0, 0,
+                gaussian_smooth_spatial<IOValue, IOErr>(image: Image, sigma: Float = 1.0) -> Image {
+                    vec![run_gaussian_smooth(image, *sigma, None)]
+                }
+            ),
+            cake_transform!(
+                "Boxcar smoothing along an axis of image. Parameters: image, width (in pixels), axis.
+Axes are numbered in FITS order starting from 0 (the spectral axis of a cube is usually axis 2).
+NaN values are ignored.",
+                1, 0, 0,
+                boxcar_smooth<IOValue, IOErr>(image: Image, width: Integer = 3, axis: Integer = 2) -> Image {
+                    vec![run_boxcar_smooth(image, *width, Some(*axis))]
+                }
+            ),
+            cake_transform!(
+                "Boxcar smoothing over the spatial plane (axes 0 and 1) of image. Parameters: image, width (in pixels).
+NaN values are ignored.",
+                1, 0, 0,
+                boxcar_smooth_spatial<IOValue, IOErr>(image: Image, width: Integer = 3) -> Image {
+                    vec![run_boxcar_smooth(image, *width, None)]
+                }
+            ),
+            cake_transform!(
+                "Median smoothing along an axis of image. Parameters: image, width (in pixels), axis.
+Axes are numbered in FITS order starting from 0 (the spectral axis of a cube is usually axis 2).
+NaN values are ignored.",
+                1, 0, 0,
+                median_smooth<IOValue, IOErr>(image: Image, width: Integer = 3, axis: Integer = 2) -> Image {
+                    vec![run_median_smooth(image, *width, Some(*axis))]
+                }
+            ),
+            cake_transform!(
+                "Median smoothing over the spatial plane (axes 0 and 1) of image. Parameters: image, width (in pixels).
+NaN values are ignored.",
+                1, 0, 0,
+                median_smooth_spatial<IOValue, IOErr>(image: Image, width: Integer = 3) -> Image {
+                    vec![run_median_smooth(image, *width, None)]
+                }
+            ),
+            cake_transform!(
+                "Fit a polynomial continuum on each spectrum (along axis 2 in FITS order) of image. Parameters: image, windows, degree.
+windows are line-free ranges of frames used for the fit, e.g. '0:20, 150:200' (start included, end excluded). Indices start from 0.
+NaN values are ignored.
+First output is the continuum, second output is image with continuum subtracted.",
+                1, 0, 0,
+                fit_continuum_polynomial<IOValue, IOErr>(image: LazyImage, windows: Str = "".to_owned(), degree: Integer = 1) -> Image, Image {
+                    run_fit_continuum(image, windows, ContinuumModel::Polynomial(*degree))
+                }
+            ),
+            cake_transform!(
+                "Fit a natural cubic spline continuum on each spectrum (along axis 2 in FITS order) of image. Parameters: image, windows.
+The spline goes through the mean of each line-free window, e.g. '0:20, 80:100, 150:200' (start included, end excluded). Indices start from 0.
+NaN values are ignored.
+First output is the continuum, second output is image with continuum subtracted.",
+                1, 0, 0,
+                fit_continuum_spline<IOValue, IOErr>(image: LazyImage, windows: Str = "".to_owned()) -> Image, Image {
+                    run_fit_continuum(image, windows, ContinuumModel::Spline)
+                }
+            ),
+            cake_transform!(
+                "Fit Gaussian profiles on the emission line of each spectrum (along axis 2 in FITS order) of image. Parameters: image, start, end, components.
+Profiles are fitted on frames [start, end) with the Levenberg-Marquardt method. Continuum should be subtracted beforehand (see fit_continuum_polynomial).
+Output maps of amplitude, center, sigma and flux (amplitude * sigma * sqrt(2 pi)), then maps of their standard errors.
+Center and sigma are in world coordinates of the spectral axis. If several components are fitted, each map has one frame per component, sorted by center.
+NaN values are ignored. Pixels where the fit fails are NaN.
+Note: indices for start and end start from 0",
+                1, 0, 0,
+                fit_gaussian_line<IOValue, IOErr>(image: LazyImage, start: Integer = 0, end: Integer = 1, components: Integer = 1) -> Image, Image, Image, Image, Image, Image, Image, Image {
+                    run_fit_gaussian_line(image, *start, *end, *components)
+                }
+            ),
+            cake_transform!(
+                "Convolve image with kernel. Parameters: image, kernel.
+Kernel is centered on its middle pixel. If kernel has fewer axes than image, it is applied on the first axes of image
+(e.g. a 2D kernel convolves each frame of a cube). Use a kernel of size 1 along axes 0 and 1 to convolve along the spectral axis only.
+If the sum of kernel is not zero, NaN values are ignored and the result is normalized by the weights of valid values.",
+                1, 0, 0,
+                convolve<IOValue, IOErr>(image: Image, kernel: Image) -> Image {
+                    vec![run_convolve(image, kernel)]
+                }
+            ),
+            cake_transform!(
+                "Crop the spatial plane (axes 0 and 1 in FITS order) of image. Parameters: image, x, y, width, height.
+Keep the region of width x height pixels starting from pixel (x, y). A width or height of 0 extends the region to the edge of image.
+World coordinates are updated accordingly.
+Note: indices for x and y start from 0",
+                1, 0, 0,
+                crop<IOValue, IOErr>(image: Image, x: Integer = 0, y: Integer = 0, width: Integer = 0, height: Integer = 0) -> Image {
+                    vec![run_crop(image, *x, *y, *width, *height)]
+                }
+            ),
+            cake_transform!(
+                "Rebin the spatial plane (axes 0 and 1 in FITS order) of image by groups of factor_x x factor_y pixels. Parameters: image, factor_x, factor_y, mean.
+Each bin is the sum of its pixels, or their mean if mean is checked. Remaining pixels on the edges are dropped.
+NaN values are ignored. World coordinates are updated accordingly.",
+                1, 0, 0,
+                rebin<IOValue, IOErr>(image: Image, factor_x: Integer = 2, factor_y: Integer = 2, mean: Bool = false) -> Image {
+                    vec![run_rebin(image, *factor_x, *factor_y, *mean)]
+                }
+            ),
+            cake_transform!(
+                "Resample the spatial plane (axes 0 and 1 in FITS order) of image onto a grid of width x height pixels covering the same region. Parameters: image, width, height, method.
+method is 'nearest', 'bilinear' or 'flux' (flux-conserving: each pixel is the sum of the input pixels it overlaps, weighted by the overlapping area).
+A width or height of 0 keeps the size of image. NaN values are ignored. World coordinates are updated accordingly.",
+                1, 0, 0,
+                resample<IOValue, IOErr>(image: Image, width: Integer = 0, height: Integer = 0, method: Str = "bilinear".to_owned()) -> Image {
+                    vec![run_resample(image, *width, *height, method)]
+                }
+            ),
+            cake_transform!(
+                "Reproject the spatial plane (axes 0 and 1 in FITS order) of source onto the pixel grid of reference, using the world coordinates of both images. Parameters: source, reference, method.
+method is 'nearest', 'bilinear' or 'flux' (bilinear interpolation scaled by the area of the reference pixels, to conserve flux).
+Other axes of source (e.g. the spectral axis of a cube) are unchanged. Pixels out of source are NaN. NaN values are ignored.",
+                1, 0, 0,
+                reproject<IOValue, IOErr>(source: Image, reference: Image, method: Str = "bilinear".to_owned()) -> Image {
+                    vec![run_reproject(source, reference, method)]
+                }
+            ),
+            cake_transform!(
+                "Union of two regions of interest. Parameters: a, b.
+Select pixels belonging to a or b.",
+                1, 0, 0,
+                roi_union<IOValue, IOErr>(a: Roi, b: Roi) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Union(Box::new(a.clone()), Box::new(b.clone()))))]
+                }
+            ),
+            cake_transform!(
+                "Intersection of two regions of interest. Parameters: a, b.
+Select pixels belonging to both a and b.",
+                1, 0, 0,
+                roi_intersection<IOValue, IOErr>(a: Roi, b: Roi) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Intersection(Box::new(a.clone()), Box::new(b.clone()))))]
+                }
+            ),
+            cake_transform!(
+                "Difference of two regions of interest. Parameters: a, b.
+Select pixels belonging to a but not to b.",
+                1, 0, 0,
+                roi_difference<IOValue, IOErr>(a: Roi, b: Roi) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Difference(Box::new(a.clone()), Box::new(b.clone()))))]
+                }
+            ),
+            cake_transform!(
+                "Load region of interest from a DS9 region file. Parameters: path, image.
+Circles, ellipses, boxes, annuli and polygons in image or fk5 coordinates are supported. World coordinates are converted to pixels with the world coordinates of image.",
+                1, 0, 0,
+                load_region<IOValue, IOErr>(path: Path, image: Image) -> Roi {
+                    vec![run_load_region(path, image)]
+                }
+            ),
+            cake_transform!(
+                "Aperture photometry on a 2D image. Parameters: image, aperture, background, gain.
+Outputs are the total flux in aperture, the background-subtracted flux, the background per pixel, the number of pixels in aperture and the error on the background-subtracted flux.
+The background per pixel is the median of the pixels in the background region (e.g. an annulus around the aperture). There is no background by default.
+The error includes the noise of the background and, if gain (in electrons per unit of image value) is positive, the photon noise of the source. NaN values are ignored.",
+                1, 0, 0,
+                aperture_photometry<IOValue, IOErr>(image: Image, aperture: Roi, background: Roi = roi::ROI::PixelList(vec![]), gain: Float = 0.0) -> Quantity, Quantity, Quantity, Integer, Quantity {
+                    run_aperture_photometry(image, aperture, background, *gain)
+                }
+            ),
         ]
     };
 }
@@ -350,6 +727,7 @@ impl cake::DefaultFor for IOValue {
         match variant_name {
             "Integer" => IOValue::Integer(0),
             "Float" => IOValue::Float(0.0),
+            "Quantity" => IOValue::Quantity(Dimensioned::new(0.0, Unit::None)),
             "Float2" => IOValue::Float2([0.0; 2]),
             "Float3" => IOValue::Float3([0.0; 3]),
             "Roi" => IOValue::Roi(roi::ROI::All),
@@ -364,7 +742,7 @@ impl cake::DefaultFor for IOValue {
 impl cake::EditableVariants for IOValue {
     fn editable_variants() -> &'static [&'static str] {
         &[
-            "Integer", "Float", "Float2", "Float3", "Roi", "Str", "Bool", "Path",
+            "Integer", "Float", "Quantity", "Float2", "Float3", "Roi", "Str", "Bool", "Path",
         ]
     }
 }
@@ -381,6 +759,41 @@ impl cake::ConvertibleVariants for IOValue {
             into: "Integer",
             f: float_to_integer,
         },
+        cake::ConvertibleVariant {
+            from: "Integer",
+            into: "Quantity",
+            f: integer_to_quantity,
+        },
+        cake::ConvertibleVariant {
+            from: "Float",
+            into: "Quantity",
+            f: float_to_quantity,
+        },
+        cake::ConvertibleVariant {
+            from: "Quantity",
+            into: "Float",
+            f: quantity_to_float,
+        },
+        cake::ConvertibleVariant {
+            from: "LazyImage",
+            into: "Image",
+            f: lazy_image_to_image,
+        },
+        cake::ConvertibleVariant {
+            from: "Image",
+            into: "LazyImage",
+            f: image_to_lazy_image,
+        },
+        cake::ConvertibleVariant {
+            from: "Float",
+            into: "Image",
+            f: float_to_image,
+        },
+        cake::ConvertibleVariant {
+            from: "Quantity",
+            into: "Image",
+            f: quantity_to_image,
+        },
     ];
 }
 
@@ -398,58 +811,217 @@ fn float_to_integer(from: &IOValue) -> IOValue {
         panic!("Unexpected input!")
     }
 }
+fn integer_to_quantity(from: &IOValue) -> IOValue {
+    if let IOValue::Integer(int) = from {
+        IOValue::Quantity(Dimensioned::new(*int as f32, Unit::None))
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn float_to_quantity(from: &IOValue) -> IOValue {
+    if let IOValue::Float(f) = from {
+        IOValue::Quantity(Dimensioned::new(*f, Unit::None))
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn quantity_to_float(from: &IOValue) -> IOValue {
+    if let IOValue::Quantity(q) = from {
+        IOValue::Float(*q.scalar())
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn lazy_image_to_image(from: &IOValue) -> IOValue {
+    if let IOValue::LazyImage(image) = from {
+        IOValue::Image(image.load())
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn image_to_lazy_image(from: &IOValue) -> IOValue {
+    if let IOValue::Image(image) = from {
+        IOValue::LazyImage(LazyWcsArray::Loaded(image.clone()))
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn float_to_image(from: &IOValue) -> IOValue {
+    if let IOValue::Float(f) = from {
+        IOValue::Image(WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![], *f),
+            Unit::None,
+        )))
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+fn quantity_to_image(from: &IOValue) -> IOValue {
+    if let IOValue::Quantity(q) = from {
+        IOValue::Image(WcsArray::from_array(
+            q.with_new_value(ArrayD::from_elem(vec![], *q.scalar())),
+        ))
+    } else {
+        panic!("Unexpected input!")
+    }
+}
 
 /// Open FITS file
 fn run_open_fits<P: AsRef<Path>>(path: P) -> Result<IOValue, IOErr> {
     let path = path.as_ref();
-    fitrs::Fits::open(path)
+    FitsFile::open(path)
         .map(|fits| IOValue::Fits(Arc::new(fits)))
         .map_err(|err| IOErr::IoError(err, format!("Could not open file {:?}", path)))
 }
 
-/// Turn a FITS file into an image
+/// Find HDU named `extension`, or the HDU at index `hdu_idx` if there is none.
+/// Return the index of the HDU with the HDU.
+fn find_hdu<'a>(
+    fits: &'a FitsFile,
+    hdu_idx: i64,
+    extension: &str,
+) -> Result<(usize, &'a fitrs::Hdu), IOErr> {
+    let hdu_idx = try_into_unsigned!(hdu_idx)?;
+    let named_idx = if extension == "" {
+        None
+    } else {
+        fits.extension_index(extension)
+            .map_err(|e| IOErr::FITSErr(format!("{}", e)))?
+    };
+    let hdu_idx = named_idx.unwrap_or(hdu_idx);
+    fits.get(hdu_idx).map(|hdu| (hdu_idx, hdu)).ok_or_else(|| {
+        let hdu_name = if hdu_idx == 0 {
+            "Primary HDU".to_owned()
+        } else {
+            format!("HDU #{}", hdu_idx)
+        };
+        if extension == "" {
+            IOErr::UnexpectedInput(format!("Could not find {} in FITS file.", hdu_name))
+        } else {
+            IOErr::UnexpectedInput(format!(
+                "Could not find HDU '{}', nor {} in FITS file.",
+                extension, hdu_name
+            ))
+        }
+    })
+}
+
+/// Turn a FITS file into an image, with the variance found in extension
+/// `variance` and the data-quality flags found in extension `mask` if they
+/// are not empty
 fn run_fits_to_image(
-    fits: &Arc<fitrs::Fits>,
+    fits: &Arc<FitsFile>,
     hdu_idx: i64,
     extension: &str,
+    variance: &str,
+    mask: &str,
 ) -> Result<IOValue, IOErr> {
-    let hdu_idx = try_into_unsigned!(hdu_idx)?;
-    let primary_hdu = fits
-        .get_by_name(extension)
-        .or_else(|| fits.get(hdu_idx))
+    let (hdu_idx, hdu) = find_hdu(fits, hdu_idx, extension)?;
+    let image = fits
+        .map_image(hdu_idx)
+        .map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+    let variance = map_companion_image(fits, variance, "variance", image.shape())?;
+    let quality = map_companion_image(fits, mask, "mask", image.shape())?;
+    // Data is kept in the mapped image, only units and WCS are read here
+    Ok(IOValue::LazyImage(LazyWcsArray::Mapped {
+        meta: WcsMeta::from_hdu(hdu),
+        data: image,
+        variance,
+        quality,
+    }))
+}
+
+/// Memory-map the image in extension `extension` (used as the `what` of the
+/// dataset), checking that it has shape `shape`. Return `None` if
+/// `extension` is empty.
+fn map_companion_image(
+    fits: &Arc<FitsFile>,
+    extension: &str,
+    what: &str,
+    shape: &[usize],
+) -> Result<Option<MappedImage>, IOErr> {
+    if extension == "" {
+        return Ok(None);
+    }
+    let hdu_idx = fits
+        .extension_index(extension)
+        .map_err(|e| IOErr::FITSErr(format!("{}", e)))?
         .ok_or_else(|| {
-            let hdu_name = if hdu_idx == 0 {
-                "Primary HDU".to_owned()
-            } else {
-                format!("HDU #{}", hdu_idx)
-            };
-            if extension == "" {
-                IOErr::UnexpectedInput(format!("Could not find {} in FITS file.", hdu_name))
-            } else {
-                IOErr::UnexpectedInput(format!(
-                    "Could not find HDU '{}', nor {} in FITS file.",
-                    extension, hdu_name
-                ))
-            }
+            IOErr::UnexpectedInput(format!(
+                "Could not find {} HDU '{}' in FITS file.",
+                what, extension
+            ))
         })?;
-    WcsArray::from_hdu(&primary_hdu)
-        .map(IOValue::Image)
+    let image = fits
+        .map_image(hdu_idx)
+        .map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+    precheck!(
+        image.shape() == shape,
+        "The {} has shape {:?}, but dataset has shape {:?}",
+        what,
+        image.shape(),
+        shape
+    )?;
+    Ok(Some(image))
+}
+
+/// Read columns of a table extension of a FITS file
+fn run_fits_to_table(
+    fits: &Arc<FitsFile>,
+    hdu_idx: i64,
+    extension: &str,
+    columns: &str,
+) -> Result<IOValue, IOErr> {
+    let (_, hdu) = find_hdu(fits, hdu_idx, extension)?;
+    let names: Vec<_> = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    Table::from_hdu(hdu, &names)
+        .map(IOValue::Table)
         .map_err(|e| IOErr::FITSErr(format!("{}", e)))
 }
 
-fn run_slice_one_frame(input_img: &WcsArray, frame_idx: i64) -> Result<IOValue, IOErr> {
+/// Save image to FITS file
+fn run_save_fits<P: AsRef<Path>>(image: &WcsArray, path: P) -> Result<IOValue, IOErr> {
+    let path = path.as_ref();
+    fitrs::Fits::create(path, image.to_hdu())
+        .map(|_| IOValue::Path(path.to_owned()))
+        .map_err(|err| IOErr::IoError(err, format!("Could not write file {:?}", path)))
+}
+
+/// Read region of interest from a DS9 region file
+fn run_load_region<P: AsRef<Path>>(path: P, image: &WcsArray) -> Result<IOValue, IOErr> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| IOErr::IoError(err, format!("Could not open file {:?}", path)))?;
+    ROI::from_ds9(&text, image.wcs())
+        .map(IOValue::Roi)
+        .map_err(|e| IOErr::UnexpectedInput(format!("{}", e)))
+}
+
+fn run_slice_one_frame(input_img: &LazyWcsArray, frame_idx: i64) -> Result<IOValue, IOErr> {
     let frame_idx = try_into_unsigned!(frame_idx)?;
     is_sliceable!(input_img, frame_idx)?;
 
-    let image_val = input_img.scalar();
-    let out = image_val.index_axis(Axis(0), frame_idx);
+    let frame = input_img.load_frames(frame_idx..frame_idx + 1);
+    let out = frame.scalar().index_axis(Axis(0), 0);
+    let variance = frame
+        .variance()
+        .map(|variance| variance.index_axis(Axis(0), 0).to_owned());
+    let mask = frame
+        .mask()
+        .map(|mask| mask.index_axis(Axis(0), 0).to_owned());
 
-    let wrap_with_unit = input_img.make_slice(
+    let wrap_with_unit = frame.make_slice(
         &(0..out.ndim()).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        input_img.array().with_new_value(out.to_owned()),
+        frame.array().with_new_value(out.to_owned()),
     );
 
-    Ok(IOValue::Image(wrap_with_unit))
+    Ok(IOValue::Image(
+        wrap_with_unit.with_variance(variance).with_mask(mask),
+    ))
 }
 
 /// Slice a 3D image through an arbitrary 2D plane
@@ -672,35 +1244,161 @@ fn run_make_plane3d(
     Ok(IOValue::Map2dTo3dCoords(map))
 }
 
-fn run_extract_wave(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
+fn run_extract_wave(image: &LazyWcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
     dim_is!(image, 3)?;
 
-    let image_val = image.scalar();
-
-    let wave_size = *image_val.dim().as_array_view().first().unwrap();
+    let wave_size = image.shape()[0];
     let mut wave = Vec::with_capacity(wave_size);
+    let mut wave_variance = Vec::with_capacity(wave_size);
+    let mut first_frame = None;
+    // Load one frame at a time, so that the whole cube is never in memory
     for i in 0..wave_size {
-        let mut res = 0.0;
-        for (_, val) in roi.filter(image_val.slice(s![i, .., ..])) {
-            res += val;
-        }
-        wave.push(res);
-    }
+        let frame = image.load_frames(i..i + 1);
+        {
+            // Masked values are left out of the sum
+            let is_masked =
+                |(i, j): (usize, usize)| frame.mask().map_or(false, |mask| mask[[0, i, j]]);
+            let mut res = 0.0;
+            for (index, val) in roi.filter(frame.scalar().slice(s![0, .., ..])) {
+                if !is_masked(index) {
+                    res += val;
+                }
+            }
+            wave.push(res);
+            if let Some(variance) = frame.variance() {
+                let mut res = 0.0;
+                for (index, val) in roi.filter(variance.slice(s![0, .., ..])) {
+                    if !is_masked(index) {
+                        res += val;
+                    }
+                }
+                wave_variance.push(res);
+            }
+        }
+        if i == 0 {
+            first_frame = Some(frame);
+        }
+    }
+    let first_frame = first_frame.ok_or_else(|| {
+        IOErr::UnexpectedInput("Cannot extract wave from an empty image".to_owned())
+    })?;
+    let variance = if wave_variance.len() == wave_size {
+        Some(Array1::from_vec(wave_variance).into_dyn())
+    } else {
+        None
+    };
     Ok(IOValue::Image(
-        image.make_slice(
-            &[(2, 0.0, 1.0)],
-            image
-                .array()
-                .with_new_value(Array1::from_vec(wave).into_dyn()),
-        ),
+        first_frame
+            .make_slice(
+                &[(2, 0.0, 1.0)],
+                first_frame
+                    .array()
+                    .with_new_value(Array1::from_vec(wave).into_dyn()),
+            )
+            .with_variance(variance),
     ))
 }
 
-fn run_clip(image: &WcsArray, threshold: f32, above: bool) -> Result<IOValue, IOErr> {
+fn run_aperture_photometry(
+    image: &WcsArray,
+    aperture: &roi::ROI,
+    background: &roi::ROI,
+    gain: f32,
+) -> Vec<Result<IOValue, IOErr>> {
+    match aperture_photometry(image, aperture, background, gain) {
+        Ok((total, net, sky, count, error)) => {
+            let array = image.array();
+            vec![
+                Ok(IOValue::Quantity(array.with_new_value(total))),
+                Ok(IOValue::Quantity(array.with_new_value(net))),
+                Ok(IOValue::Quantity(array.with_new_value(sky))),
+                Ok(IOValue::Integer(count as i64)),
+                Ok(IOValue::Quantity(array.with_new_value(error))),
+            ]
+        }
+        Err(e) => repeat_err(e, 5),
+    }
+}
+
+/// Compute the total flux in `aperture`, the background-subtracted flux, the
+/// background per pixel, the number of pixels in `aperture` and the error on
+/// the background-subtracted flux.
+///
+/// The error `sigma` is given by
+/// `sigma^2 = net / gain + n * s^2 + n^2 * s^2 / n_b`,
+/// where `n` and `n_b` are the number of pixels in the aperture and the
+/// background, and `s` the standard deviation of the background.
+fn aperture_photometry(
+    image: &WcsArray,
+    aperture: &roi::ROI,
+    background: &roi::ROI,
+    gain: f32,
+) -> Result<(f32, f32, f32, usize, f32), IOErr> {
+    dim_is!(image, 2)?;
+    precheck!(gain >= 0.0, "'gain' must be positive or zero")?;
+
+    let data = image.scalar2();
+    let values: Vec<_> = aperture
+        .filter(data)
+        .into_iter()
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_nan())
+        .collect();
+    precheck!(!values.is_empty(), "No valid pixel in aperture")?;
+    let n = values.len() as f32;
+    let total: f32 = values.iter().sum();
+
+    let mut sky_values: Vec<_> = background
+        .filter(data)
+        .into_iter()
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_nan())
+        .collect();
+    let (sky, sky_variance) = if sky_values.is_empty() {
+        (0.0, 0.0)
+    } else {
+        let n_b = sky_values.len() as f32;
+        let mean = sky_values.iter().sum::<f32>() / n_b;
+        let variance = if n_b > 1.0 {
+            sky_values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (n_b - 1.0)
+        } else {
+            0.0
+        };
+        let sky = smooth::median(&mut sky_values);
+        (sky, n * variance + n * n * variance / n_b)
+    };
+    let net = total - n * sky;
+
+    let error = if sky_values.is_empty() && gain == 0.0 {
+        // Noise cannot be estimated
+        ::std::f32::NAN
+    } else {
+        let source_variance = if gain > 0.0 { net.max(0.0) / gain } else { 0.0 };
+        (source_variance + sky_variance).sqrt()
+    };
+    Ok((total, net, sky, values.len(), error))
+}
+
+fn run_clip(
+    image: &WcsArray,
+    threshold: f32,
+    above: bool,
+    as_mask: bool,
+) -> Result<IOValue, IOErr> {
+    let clipped = |f: f32| (above && f >= threshold) || (!above && f <= threshold);
+
+    if as_mask {
+        let mut mask = image.scalar().mapv(clipped);
+        if let Some(masked) = image.mask() {
+            mask = mask | masked;
+        }
+        return Ok(IOValue::Image(image.clone().with_mask(Some(mask))));
+    }
+
     let mut image = image.clone();
 
     for f in image.scalar_mut().iter_mut() {
-        if (above && *f >= threshold) || (!above && *f <= threshold) {
+        if clipped(*f) {
             *f = ::std::f32::NAN;
         }
     }
@@ -735,64 +1433,172 @@ fn run_make_float3(f1: f32, f2: f32, f3: f32) -> Result<IOValue, IOErr> {
     Ok(IOValue::Float3([f1, f2, f3]))
 }
 
-fn reduce_array_slice<F>(image: &WcsArray, start: i64, end: i64, f: F) -> Result<IOValue, IOErr>
+/// Reduce frames `start..end` of `image` along its first axis (in ndarray
+/// order) with `f`. The variance of the result is computed from the variance
+/// of the frames with `f_variance`, if the variance of `image` is known.
+///
+/// If `image` is masked, masked values are left out of the reduction. Values
+/// that are masked in all frames are reduced to NaN and masked.
+fn reduce_array_slice<F, G>(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    f: F,
+    f_variance: G,
+) -> Result<IOValue, IOErr>
 where
     F: Fn(&ArrayViewD<f32>) -> ArrayD<f32>,
+    G: Fn(&ArrayViewD<f32>) -> Option<ArrayD<f32>>,
 {
     let start = try_into_unsigned!(start)?;
     let end = try_into_unsigned!(end)?;
     is_sliceable!(image, start, end)?;
 
-    let image_val = image.scalar();
-
-    let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
-    let raw = f(&slices);
+    let frames = image.load_frames(start..end);
+    let (raw, variance, mask) = match frames.mask() {
+        None => (
+            f(&frames.scalar().view()),
+            frames
+                .variance()
+                .and_then(|variance| f_variance(&variance.view())),
+            None,
+        ),
+        Some(mask) => (
+            reduce_unmasked(frames.scalar(), mask, |lane| Some(f(lane)))
+                .expect("Reduction of data is defined"),
+            frames
+                .variance()
+                .and_then(|variance| reduce_unmasked(variance, mask, &f_variance)),
+            Some(mask.fold_axis(Axis(0), true, |all, masked| *all && *masked)),
+        ),
+    };
     let ndim = raw.ndim();
 
-    let wrap_with_unit = image.make_slice(
+    let wrap_with_unit = frames.make_slice(
         &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        image.array().with_new_value(raw),
+        frames.array().with_new_value(raw),
     );
 
-    Ok(IOValue::Image(wrap_with_unit))
+    Ok(IOValue::Image(
+        wrap_with_unit.with_variance(variance).with_mask(mask),
+    ))
 }
 
-fn run_integral(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.sum_axis(Axis(0)))
+/// Reduce `data` along its first axis (in ndarray order) with `f`, leaving
+/// out the values masked in `mask`. Lanes whose values are all masked are
+/// reduced to NaN.
+///
+/// Return `None` if `f` returns `None` for any lane.
+fn reduce_unmasked<F>(data: &ArrayD<f32>, mask: &ArrayD<bool>, f: F) -> Option<ArrayD<f32>>
+where
+    F: Fn(&ArrayViewD<f32>) -> Option<ArrayD<f32>>,
+{
+    let values = data
+        .lanes(Axis(0))
+        .into_iter()
+        .zip(mask.lanes(Axis(0)))
+        .map(|(lane, lane_mask)| {
+            let unmasked: Vec<_> = lane
+                .iter()
+                .zip(lane_mask.iter())
+                .filter(|&(_, masked)| !*masked)
+                .map(|(value, _)| *value)
+                .collect();
+            if unmasked.is_empty() {
+                Some(::std::f32::NAN)
+            } else {
+                let unmasked = ArrayD::from_shape_vec(vec![unmasked.len()], unmasked)
+                    .expect("Shape matches data length");
+                f(&unmasked.view())
+                    .map(|reduced| reduced.iter().next().cloned().unwrap_or(::std::f32::NAN))
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let shape = data.shape()[1..].to_vec();
+    Some(ArrayD::from_shape_vec(shape, values).expect("One value per lane"))
+}
+
+/// Iterate over the values of `image` that are not masked.
+fn unmasked_values<'a>(image: &'a WcsArray) -> Box<dyn Iterator<Item = f32> + 'a> {
+    let values = image.scalar().iter().cloned();
+    match image.mask() {
+        Some(mask) => Box::new(
+            values
+                .zip(mask.iter())
+                .filter(|&(_, masked)| !*masked)
+                .map(|(value, _)| value),
+        ),
+        None => Box::new(values),
+    }
+}
+
+fn run_integral(image: &LazyWcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice(
+        image,
+        start,
+        end,
+        |slices| slices.sum_axis(Axis(0)),
+        |variance| Some(variance.sum_axis(Axis(0))),
+    )
 }
 
-fn run_average(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.mean_axis(Axis(0)))
+fn run_average(image: &LazyWcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice(
+        image,
+        start,
+        end,
+        |slices| slices.mean_axis(Axis(0)),
+        |variance| {
+            let n = variance.len_of(Axis(0)) as f32;
+            Some(variance.sum_axis(Axis(0)) / (n * n))
+        },
+    )
 }
 
-fn run_minmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result<IOValue, IOErr> {
+fn run_minmax(image: &LazyWcsArray, start: i64, end: i64, is_min: bool) -> Result<IOValue, IOErr> {
     if !is_min {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
-                -std::f32::INFINITY,
-                |x, y| if x > y { *x } else { *y },
-            )
-        })
+        reduce_array_slice(
+            image,
+            start,
+            end,
+            |slices| {
+                slices.fold_axis(
+                    Axis(0),
+                    -std::f32::INFINITY,
+                    |x, y| if x > y { *x } else { *y },
+                )
+            },
+            |_| None,
+        )
     } else {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
-                std::f32::INFINITY,
-                |x, y| if x < y { *x } else { *y },
-            )
-        })
+        reduce_array_slice(
+            image,
+            start,
+            end,
+            |slices| {
+                slices.fold_axis(
+                    Axis(0),
+                    std::f32::INFINITY,
+                    |x, y| if x < y { *x } else { *y },
+                )
+            },
+            |_| None,
+        )
     }
 }
 
-fn run_argminmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result<IOValue, IOErr> {
+fn run_argminmax(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    is_min: bool,
+) -> Result<IOValue, IOErr> {
     let start = try_into_unsigned!(start)?;
     let end = try_into_unsigned!(end)?;
     is_sliceable!(image, start, end)?;
 
-    let image_val = image.scalar();
-
-    let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
+    let frames = image.load_frames(start..end);
+    let slices = frames.scalar();
     let dim = slices.dim();
     let size = dim.as_array_view();
     let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
@@ -807,13 +1613,13 @@ fn run_argminmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
             if !is_min && slice[&index] > value {
                 value = slice[&index];
-                out = match image.pix2world(2, (k + start) as f32) {
+                out = match frames.pix2world(2, k as f32) {
                     Some(value) => value,
                     None => (k + start) as f32,
                 };
             } else if is_min && slice[&index] < value {
                 value = slice[&index];
-                out = match image.pix2world(2, (k + start) as f32) {
+                out = match frames.pix2world(2, k as f32) {
                     Some(value) => value,
                     None => (k + start) as f32,
                 };
@@ -822,34 +1628,36 @@ fn run_argminmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result
         out
     });
 
-    // FIXME: Unit support
-    // unit of index(Axis 0) should be adobped
-    //
-    // in above program...
-    // unit of variable 'out' should be adopted
-
     Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
         waveimg,
-        Unit::None,
+        frames.world_unit(2),
     ))))
 }
 
-fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+fn run_centroid(image: &LazyWcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
     let start = try_into_unsigned!(start)?;
     let end = try_into_unsigned!(end)?;
     is_sliceable!(image, start, end)?;
 
-    let image_val = image.scalar();
-
-    let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
+    let frames = image.load_frames(start..end);
+    let slices = frames.scalar();
     let dim = slices.dim();
     let size = dim.as_array_view();
     let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
     let new_size_2 = new_size.clone();
+    // Masked values do not contribute to the centroid
+    let is_masked = |k: usize, index: &IxDyn| {
+        frames
+            .mask()
+            .map_or(false, |mask| mask.index_axis(Axis(0), k)[index])
+    };
 
     let flux_sum = ArrayD::from_shape_fn(new_size, |index| {
         let mut out = 0.0;
-        for (_, slice) in slices.axis_iter(Axis(0)).enumerate() {
+        for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if is_masked(k, &index) {
+                continue;
+            }
             let flux = slice[&index];
             out += flux;
         }
@@ -859,8 +1667,11 @@ fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr
     let waveimg = ArrayD::from_shape_fn(new_size_2, |index| {
         let mut out = 0.0;
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if is_masked(k, &index) {
+                continue;
+            }
             let flux = slice[&index];
-            let wavelength = match image.pix2world(2, (k + start) as f32) {
+            let wavelength = match frames.pix2world(2, k as f32) {
                 Some(value) => value,
                 None => (k + start) as f32,
             };
@@ -871,43 +1682,1017 @@ fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr
 
     let result = waveimg / flux_sum;
 
-    // FIXME: Unit support
-    // unit of index(Axis 0) should be adobped
-    //
-    // in above program...
-    // 'waveimg' must have [flux * wavelength], 'flux_sum' must have [flux]
-    // 'result' = waveimg / flux_sum   must have [wavelength]
-
+    // [flux * wavelength] / [flux] = [wavelength]
     Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
         result,
-        Unit::None,
+        frames.world_unit(2),
     ))))
 }
 
+/// Reduction of the values along an axis, computed by `run_reduce`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reduction {
+    Sum,
+    Mean,
+    Median,
+    Min,
+    Max,
+    Std,
+    /// Position of the maximum
+    Argmax,
+}
+
+impl Reduction {
+    const NAMES: &'static [&'static str] =
+        &["sum", "mean", "median", "min", "max", "std", "argmax"];
+
+    fn from_name(name: &str) -> Option<Reduction> {
+        match name.trim().to_lowercase().as_str() {
+            "sum" => Some(Reduction::Sum),
+            "mean" => Some(Reduction::Mean),
+            "median" => Some(Reduction::Median),
+            "min" => Some(Reduction::Min),
+            "max" => Some(Reduction::Max),
+            "std" => Some(Reduction::Std),
+            "argmax" => Some(Reduction::Argmax),
+            _ => None,
+        }
+    }
+}
+
+/// Reduce `image` along FITS axis `axis`, leaving out NaN and masked values
+fn run_reduce(image: &LazyWcsArray, axis: i64, kind: &str) -> Result<IOValue, IOErr> {
+    let axis = try_into_unsigned!(axis)?;
+    let ndim = image.shape().len();
+    precheck!(
+        axis < ndim,
+        "Image has no axis {} (got {} axes)",
+        axis,
+        ndim
+    )?;
+    let reduction = Reduction::from_name(kind).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Unknown reduction '{}'. Expected one of '{}'.",
+            kind,
+            Reduction::NAMES.join("', '")
+        ))
+    })?;
+
+    let image = image.load();
+    // FITS axis `axis` is ndarray axis `ndim - 1 - axis`
+    let lane_axis = Axis(ndim - 1 - axis);
+    let len = image.shape()[lane_axis.index()];
+    let positions: Vec<_> = (0..len)
+        .map(|k| image.pix2world(axis, k as f32).unwrap_or(k as f32))
+        .collect();
+    let unmasked = ArrayD::from_elem(image.shape(), false);
+    let mask = image.mask().unwrap_or(&unmasked);
+    let variance = match reduction {
+        Reduction::Sum | Reduction::Mean => image.variance(),
+        _ => None,
+    };
+    let mut variance_lanes = variance.map(|variance| variance.lanes(lane_axis).into_iter());
+
+    let mut out = Vec::new();
+    let mut out_variance = Vec::new();
+    let mut out_mask = Vec::new();
+    for (lane, lane_mask) in image
+        .scalar()
+        .lanes(lane_axis)
+        .into_iter()
+        .zip(mask.lanes(lane_axis))
+    {
+        let used: Vec<_> = (0..len)
+            .filter(|&k| !lane_mask[k] && !lane[k].is_nan())
+            .collect();
+        let mut values: Vec<_> = used.iter().map(|&k| lane[k]).collect();
+        let n = values.len() as f32;
+        let value = if values.is_empty() {
+            ::std::f32::NAN
+        } else {
+            match reduction {
+                Reduction::Sum => values.iter().sum(),
+                Reduction::Mean => values.iter().sum::<f32>() / n,
+                Reduction::Median => smooth::median(&mut values),
+                Reduction::Min => values.iter().cloned().fold(::std::f32::INFINITY, f32::min),
+                Reduction::Max => values.iter().cloned().fold(-::std::f32::INFINITY, f32::max),
+                Reduction::Std => stats::Statistics::new(&values).std,
+                Reduction::Argmax => {
+                    let mut argmax = used[0];
+                    for &k in &used {
+                        if lane[k] > lane[argmax] {
+                            argmax = k;
+                        }
+                    }
+                    positions[argmax]
+                }
+            }
+        };
+        out.push(value);
+        out_mask.push(used.is_empty());
+        if let Some(ref mut lanes) = variance_lanes {
+            let lane_variance = lanes.next().expect("Variance has the shape of the data");
+            let sum: f32 = used.iter().map(|&k| lane_variance[k]).sum();
+            out_variance.push(match reduction {
+                _ if used.is_empty() => ::std::f32::NAN,
+                Reduction::Mean => sum / (n * n),
+                _ => sum,
+            });
+        }
+    }
+
+    let mut shape = image.shape().to_vec();
+    shape.remove(lane_axis.index());
+    let unit = if reduction == Reduction::Argmax {
+        image.world_unit(axis)
+    } else {
+        image.array().unit().clone()
+    };
+    let indices: Vec<_> = (0..ndim)
+        .filter(|&i| i != axis)
+        .map(|i| (i, 0.0, 1.0))
+        .collect();
+    let out = ArrayD::from_shape_vec(shape.clone(), out).expect("One value per lane");
+    let variance = variance_lanes.map(|_| {
+        ArrayD::from_shape_vec(shape.clone(), out_variance).expect("One variance per lane")
+    });
+    let mask = image
+        .mask()
+        .map(|_| ArrayD::from_shape_vec(shape, out_mask).expect("One flag per lane"));
+    Ok(IOValue::Image(
+        image
+            .make_slice(&indices, Dimensioned::new(out, unit))
+            .with_variance(variance)
+            .with_mask(mask),
+    ))
+}
+
 fn run_create_equivalent_width(
     i_off: &WcsArray,
     i_on: &WcsArray,
-    fl: f32,
+    fl: &Dimensioned<f32>,
     max: f32,
     is_emission: bool,
 ) -> Result<IOValue, IOErr> {
-    let i_off_arr = i_off.scalar();
-    let i_on_arr = i_on.scalar();
-    let out = (i_off_arr - i_on_arr) * fl / i_off_arr * (if is_emission { -1.0 } else { 1.0 });
-    let result = out.map(|v| if *v > max { 0.0 } else { *v });
+    let i_off_arr = i_off.array();
+    let i_on_arr = i_on.array();
+    let ratio = &(i_off_arr - i_on_arr) / i_off_arr;
+    let mut out = ratio * fl.clone() * (if is_emission { -1.0 } else { 1.0 });
+    out.scalar_mut()
+        .mapv_inplace(|v| if v > max { 0.0 } else { v });
+
+    Ok(IOValue::Image(WcsArray::from_array(out)))
+}
 
-    // FIXME: Unit support
-    // implementation of &WcsArray / &WcsArray is needed
-    //
-    // in above program...
-    // variable 'fl' is width of on-band, so unit of length should be adopted (e.g. [Ang]).
+/// Speed of light in km/s
+const SPEED_OF_LIGHT: f32 = 3e5;
+
+fn run_create_velocity_field_map(
+    image: &WcsArray,
+    w_0: &Dimensioned<f32>,
+) -> Result<IOValue, IOErr> {
+    let c = SPEED_OF_LIGHT;
+    let w_0 = w_0.value_in(image.array().unit()).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Cannot convert w_0 from '{}' to '{}'",
+            w_0.unit(),
+            image.array().unit()
+        ))
+    })?;
+    let out = image.scalar().map(|v| c * (*v - w_0) / w_0);
 
     Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-        result,
-        Unit::None,
+        out,
+        Unit::parse("km/s"),
     ))))
 }
 
+fn run_moment_maps(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    w_0: &Dimensioned<f32>,
+    threshold: f32,
+    clip: bool,
+) -> Vec<Result<IOValue, IOErr>> {
+    match moment_maps(image, start, end, w_0, threshold, clip) {
+        Ok(maps) => maps
+            .into_iter()
+            .map(|map| Ok(IOValue::Image(map)))
+            .collect(),
+        Err(e) => repeat_err(e, 3),
+    }
+}
+
+/// Compute moment 0, 1 and 2 maps of image.
+fn moment_maps(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    w_0: &Dimensioned<f32>,
+    threshold: f32,
+    clip: bool,
+) -> Result<Vec<WcsArray>, IOErr> {
+    let start = try_into_unsigned!(start)?;
+    let end = try_into_unsigned!(end)?;
+    is_sliceable!(image, start, end)?;
+
+    let frames = image.load_frames(start..end);
+    let world_unit = frames.world_unit(2);
+    let w_0 = w_0.value_in(&world_unit).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Cannot convert w_0 from '{}' to '{}'",
+            w_0.unit(),
+            world_unit
+        ))
+    })?;
+    precheck!(w_0 != 0.0, "'w_0' must not be zero")?;
+
+    let velocity = |pixel: f32| {
+        let wavelength = match frames.pix2world(2, pixel) {
+            Some(value) => value,
+            None => pixel + start as f32,
+        };
+        f64::from(SPEED_OF_LIGHT * (wavelength - w_0) / w_0)
+    };
+    // Velocity and velocity width of each frame
+    let channels: Vec<_> = (0..end - start)
+        .map(|k| {
+            let k = k as f32;
+            (velocity(k), (velocity(k + 0.5) - velocity(k - 0.5)).abs())
+        })
+        .collect();
+
+    let slices = frames.scalar();
+    let new_size = slices.shape()[1..].to_vec();
+    let moments = ArrayD::from_shape_fn(new_size, |index| {
+        let mut has_value = false;
+        let weights: Vec<_> = slices
+            .axis_iter(Axis(0))
+            .zip(&channels)
+            .filter_map(|(slice, &(v, dv))| {
+                let flux = slice[&index];
+                if flux.is_nan() {
+                    return None;
+                }
+                has_value = true;
+                if clip && flux <= threshold {
+                    None
+                } else {
+                    Some((v, f64::from(flux) * dv))
+                }
+            })
+            .collect();
+        if !has_value {
+            return [::std::f64::NAN; 3];
+        }
+        let m0: f64 = weights.iter().map(|&(_, w)| w).sum();
+        let m1 = weights.iter().map(|&(v, w)| v * w).sum::<f64>() / m0;
+        let m2 = weights
+            .iter()
+            .map(|&(v, w)| w * (v - m1) * (v - m1))
+            .sum::<f64>()
+            / m0;
+        [m0, m1, m2.max(0.0).sqrt()]
+    });
+
+    let velocity_unit = Unit::parse("km/s");
+    let units = [
+        frames.array().unit() * &velocity_unit,
+        velocity_unit.clone(),
+        velocity_unit,
+    ];
+    let ndim = moments.ndim();
+    let indices: Vec<_> = (0..ndim).map(|i| (i, 0.0, 1.0)).collect();
+    Ok(units
+        .iter()
+        .enumerate()
+        .map(|(i, unit)| {
+            let map = moments.map(|m| m[i] as f32);
+            frames.make_slice(&indices, Dimensioned::new(map, unit.clone()))
+        })
+        .collect())
+}
+
+/// Make a 0-dimensional image containing NaN
+fn nan_image() -> WcsArray {
+    WcsArray::from_array(Dimensioned::new(
+        ArrayD::from_elem(vec![], ::std::f32::NAN),
+        Unit::None,
+    ))
+}
+
+/// Variables of the expression transform, one for each of its operands
+const EXPRESSION_VARIABLES: [&str; 4] = ["a", "b", "c", "d"];
+
+fn run_expression(expr: &str, inputs: &[&WcsArray]) -> Result<IOValue, IOErr> {
+    precheck!(
+        inputs.len() == EXPRESSION_VARIABLES.len(),
+        "Expected {} operands, got {}",
+        EXPRESSION_VARIABLES.len(),
+        inputs.len()
+    )?;
+    let expression = Expression::parse(expr, &EXPRESSION_VARIABLES)
+        .map_err(|e| IOErr::UnexpectedInput(format!("{}", e)))?;
+    let values: Vec<_> = inputs.iter().map(|input| input.array().clone()).collect();
+    let result = expression
+        .eval(&values)
+        .map_err(|e| IOErr::UnexpectedInput(format!("{}", e)))?;
+
+    // Keep world coordinates of the first input with the same shape
+    let shape = result.scalar().shape().to_vec();
+    let ndim = shape.len();
+    let out = match inputs.iter().find(|input| input.shape() == &shape[..]) {
+        Some(input) => input.make_slice(
+            &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+            result,
+        ),
+        None => WcsArray::from_array(result),
+    };
+    Ok(IOValue::Image(out))
+}
+
+/// Parse unit given as input to a transform
+fn parse_unit(unit: &str) -> Result<Unit, IOErr> {
+    unit.parse::<PhysicalUnit>()
+        .map(Unit::Physical)
+        .map_err(|e| IOErr::UnexpectedInput(format!("{}", e)))
+}
+
+fn run_convert_unit(image: &WcsArray, unit: &str) -> Result<IOValue, IOErr> {
+    let unit = parse_unit(unit)?;
+    image
+        .convert_unit(&unit)
+        .map(IOValue::Image)
+        .ok_or_else(|| {
+            IOErr::UnexpectedInput(format!(
+                "Cannot convert image from '{}' to '{}'",
+                image.array().unit(),
+                unit
+            ))
+        })
+}
+
+fn run_convert_axis_unit(image: &WcsArray, axis: i64, unit: &str) -> Result<IOValue, IOErr> {
+    let axis = try_into_unsigned!(axis)?;
+    let ndim = image.scalar().ndim();
+    precheck!(
+        axis < ndim,
+        "Image has no axis {} (got {} axes)",
+        axis,
+        ndim
+    )?;
+    let unit = parse_unit(unit)?;
+    image
+        .convert_world_unit(axis, &unit)
+        .map(IOValue::Image)
+        .ok_or_else(|| {
+            IOErr::UnexpectedInput(format!(
+                "Cannot convert axis {} from '{}' to '{}'",
+                axis,
+                image.world_unit(axis),
+                unit
+            ))
+        })
+}
+
+/// Check that `image` has a spectral axis (axis 2 in FITS order), i.e. that
+/// it has at least 3 dimensions
+fn has_spectral_axis(image: &LazyWcsArray) -> Result<(), IOErr> {
+    let ndim = image.shape().len();
+    precheck!(
+        ndim >= 3,
+        "Expected an image with a spectral axis (at least 3 dimensions), got {} dimensions",
+        ndim
+    )
+}
+
+fn run_redshift_correction(image: &LazyWcsArray, z: f32) -> Result<IOValue, IOErr> {
+    has_spectral_axis(image)?;
+    precheck!(z > -1.0, "'z' must be greater than -1, but got {}", z)?;
+
+    let z = f64::from(z);
+    image
+        .with_meta(|meta| {
+            let is_frequency = meta
+                .axes()
+                .map_or(false, |axes| axes[2].name().starts_with("FREQ"));
+            let factor = if is_frequency {
+                1.0 + z
+            } else {
+                1.0 / (1.0 + z)
+            };
+            meta.transform_world_axis(2, factor, 0.0, None)
+        })
+        .map(IOValue::LazyImage)
+        .ok_or_else(|| {
+            IOErr::UnexpectedInput("Image has no world coordinates on its spectral axis".to_owned())
+        })
+}
+
+fn run_air_vacuum_conversion(image: &LazyWcsArray, to_vacuum: bool) -> Result<IOValue, IOErr> {
+    has_spectral_axis(image)?;
+
+    let shape = image.shape();
+    // Number of pixels on the spectral axis, which is third from the end in
+    // ndarray order
+    let len = shape[shape.len() - 3];
+    let angstrom = Unit::parse("Angstrom");
+    image
+        .with_meta(|meta| {
+            let unit = meta.world_unit(2);
+            let to_angstrom = if unit == Unit::None {
+                1.0
+            } else {
+                f64::from(unit.conversion_factor(&angstrom)?)
+            };
+            let convert = |wavelength: f64| {
+                let wavelength = wavelength * to_angstrom;
+                let converted = if to_vacuum {
+                    spectral::air_to_vacuum(wavelength)
+                } else {
+                    spectral::vacuum_to_air(wavelength)
+                };
+                converted / to_angstrom
+            };
+            // Linear transformation exact at both ends of the axis
+            let first = f64::from(meta.pix2world(2, 0.0)?);
+            let last = f64::from(meta.pix2world(2, len.saturating_sub(1) as f32)?);
+            let factor = if last != first {
+                (convert(last) - convert(first)) / (last - first)
+            } else {
+                convert(first) / first
+            };
+            let offset = convert(first) - factor * first;
+
+            // Air and vacuum wavelengths are respectively AWAV and WAVE axes
+            let name = meta.axes().map(|axes| axes[2].name()).unwrap_or("");
+            let (from, to) = if to_vacuum {
+                ("AWAV", "WAVE")
+            } else {
+                ("WAVE", "AWAV")
+            };
+            let renamed = if name.starts_with(from) {
+                Some(format!("{}{}", to, &name[from.len()..]))
+            } else {
+                None
+            };
+            meta.transform_world_axis(2, factor, offset, renamed.as_ref().map(String::as_str))
+        })
+        .map(IOValue::LazyImage)
+        .ok_or_else(|| {
+            IOErr::UnexpectedInput(
+                "Image has no wavelengths on its spectral axis, or they cannot be converted to Angstrom"
+                    .to_owned(),
+            )
+        })
+}
+
+/// Convert FITS `axis` of `image` to an ndarray axis. If `axis` is `None`,
+/// return `None` for the spatial plane.
+fn smoothing_axis(image: &WcsArray, axis: Option<i64>) -> Result<Option<usize>, IOErr> {
+    let ndim = image.scalar().ndim();
+    if let Some(axis) = axis {
+        let axis = try_into_unsigned!(axis)?;
+        precheck!(
+            axis < ndim,
+            "Image has no axis {} (got {} axes)",
+            axis,
+            ndim
+        )?;
+        Ok(Some(ndim - 1 - axis))
+    } else {
+        precheck!(ndim >= 2, "Image has no spatial plane (got {} axes)", ndim)?;
+        Ok(None)
+    }
+}
+
+/// Make an n-dimensional kernel suitable for `image` out of a 1D kernel.
+fn smoothing_kernel(image: &WcsArray, kernel: &[f32], axis: Option<usize>) -> ArrayD<f32> {
+    let ndim = image.scalar().ndim();
+    match axis {
+        Some(axis) => smooth::kernel_along_axis(kernel, ndim, axis),
+        None => smooth::kernel_on_plane(kernel, ndim),
+    }
+}
+
+fn run_gaussian_smooth(image: &WcsArray, sigma: f32, axis: Option<i64>) -> Result<IOValue, IOErr> {
+    precheck!(
+        sigma > 0.0,
+        "'sigma' must be strictly positive, got {}",
+        sigma
+    )?;
+    let axis = smoothing_axis(image, axis)?;
+    let kernel = smoothing_kernel(image, &smooth::gaussian_kernel(sigma), axis);
+    let out = smooth::convolve(image.scalar(), &kernel);
+    Ok(IOValue::Image(image.with_new_image(out)))
+}
+
+fn run_boxcar_smooth(image: &WcsArray, width: i64, axis: Option<i64>) -> Result<IOValue, IOErr> {
+    let width = try_into_unsigned!(width)?;
+    precheck!(width > 0, "'width' must be strictly positive")?;
+    let axis = smoothing_axis(image, axis)?;
+    let kernel = smoothing_kernel(image, &smooth::boxcar_kernel(width), axis);
+    let out = smooth::convolve(image.scalar(), &kernel);
+    Ok(IOValue::Image(image.with_new_image(out)))
+}
+
+fn run_median_smooth(image: &WcsArray, width: i64, axis: Option<i64>) -> Result<IOValue, IOErr> {
+    let width = try_into_unsigned!(width)?;
+    precheck!(width > 0, "'width' must be strictly positive")?;
+    let axis = smoothing_axis(image, axis)?;
+    let ndim = image.scalar().ndim();
+    let mut window = vec![1; ndim];
+    match axis {
+        Some(axis) => window[axis] = width,
+        None => {
+            window[ndim - 2] = width;
+            window[ndim - 1] = width;
+        }
+    }
+    let out = smooth::median_filter(image.scalar(), &window);
+    Ok(IOValue::Image(image.with_new_image(out)))
+}
+
+fn run_convolve(image: &WcsArray, kernel: &WcsArray) -> Result<IOValue, IOErr> {
+    let ndim = image.scalar().ndim();
+    let kernel = kernel.scalar();
+    precheck!(
+        kernel.ndim() <= ndim,
+        "Kernel has more axes than image ({} > {})",
+        kernel.ndim(),
+        ndim
+    )?;
+    precheck!(kernel.len() > 0, "Kernel is empty")?;
+    precheck!(
+        kernel.iter().all(|w| w.is_finite()),
+        "Kernel must only contain finite values"
+    )?;
+    // Missing axes come first in ndarray order, i.e. last in FITS order
+    let mut shape = vec![1; ndim - kernel.ndim()];
+    shape.extend_from_slice(kernel.shape());
+    let kernel = kernel
+        .to_owned()
+        .into_shape(shape)
+        .expect("Same number of elements");
+    let out = smooth::convolve(image.scalar(), &kernel);
+    Ok(IOValue::Image(image.with_new_image(out)))
+}
+
+/// Model of continuum fitted by `run_fit_continuum`
+enum ContinuumModel {
+    /// Polynomial of the given degree
+    Polynomial(i64),
+    /// Natural cubic spline going through the mean of each window
+    Spline,
+}
+
+/// Parse comma-separated windows of frames such as '0:20, 150:200'.
+/// Returned windows are sorted and do not overlap.
+fn parse_windows(windows: &str) -> Result<Vec<(usize, usize)>, IOErr> {
+    let mut out = Vec::new();
+    for window in windows.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+        let invalid =
+            || IOErr::UnexpectedInput(format!("Invalid window '{}', expected 'start:end'", window));
+        let bounds: Vec<_> = window.split(':').map(str::trim).collect();
+        if bounds.len() != 2 {
+            return Err(invalid());
+        }
+        let start = bounds[0].parse::<usize>().map_err(|_| invalid())?;
+        let end = bounds[1].parse::<usize>().map_err(|_| invalid())?;
+        precheck!(start < end, "Window '{}' is empty", window)?;
+        out.push((start, end));
+    }
+    precheck!(!out.is_empty(), "At least one window is required")?;
+    out.sort();
+    for pair in out.windows(2) {
+        precheck!(
+            pair[0].1 <= pair[1].0,
+            "Windows {}:{} and {}:{} overlap",
+            pair[0].0,
+            pair[0].1,
+            pair[1].0,
+            pair[1].1
+        )?;
+    }
+    Ok(out)
+}
+
+fn run_fit_continuum(
+    image: &LazyWcsArray,
+    windows: &str,
+    model: ContinuumModel,
+) -> Vec<Result<IOValue, IOErr>> {
+    match fit_continuum(image, windows, &model) {
+        Ok((continuum, subtracted)) => vec![
+            Ok(IOValue::Image(continuum)),
+            Ok(IOValue::Image(subtracted)),
+        ],
+        Err(e) => repeat_err(e, 2),
+    }
+}
+
+/// Make `count` outputs failing with error `e`.
+fn repeat_err(e: IOErr, count: usize) -> Vec<Result<IOValue, IOErr>> {
+    let msg = format!("{}", e);
+    let mut outputs = vec![Err(e)];
+    for _ in 1..count {
+        outputs.push(Err(IOErr::UnexpectedInput(msg.clone())));
+    }
+    outputs
+}
+
+/// Fit continuum on each spectrum of image. Return the continuum and the
+/// image with continuum subtracted.
+fn fit_continuum(
+    image: &LazyWcsArray,
+    windows: &str,
+    model: &ContinuumModel,
+) -> Result<(WcsArray, WcsArray), IOErr> {
+    if let ContinuumModel::Polynomial(degree) = *model {
+        try_into_unsigned!(degree)?;
+    }
+    let windows = parse_windows(windows)?;
+    let frame_cnt = has_gt_0_dim!(image)?;
+    for &(start, end) in &windows {
+        precheck!(
+            end <= frame_cnt,
+            "Window {}:{} is out of the input image (expected {} <= {})",
+            start,
+            end,
+            end,
+            frame_cnt
+        )?;
+    }
+
+    let cube = image.load();
+    let shape = cube.shape().to_vec();
+    let spaxel_cnt = shape[1..].iter().product();
+    let spectra = Array2::from_shape_vec(
+        (frame_cnt, spaxel_cnt),
+        cube.scalar().iter().cloned().collect(),
+    )
+    .expect("Same number of elements");
+
+    // Map frame indices to [-1, 1] for numerical stability of the fit
+    let half = (frame_cnt as f64 - 1.0).max(1.0) / 2.0;
+    let to_x = |k: usize| k as f64 / half - 1.0;
+
+    let mut fitted = Array2::from_elem((frame_cnt, spaxel_cnt), std::f32::NAN);
+    for j in 0..spaxel_cnt {
+        let spectrum = spectra.column(j);
+        let samples = |&(start, end): &(usize, usize)| {
+            (start..end)
+                .filter(|&k| !spectrum[k].is_nan())
+                .map(|k| (to_x(k), f64::from(spectrum[k])))
+                .collect::<Vec<_>>()
+        };
+        let values: Option<Vec<f64>> = match *model {
+            ContinuumModel::Polynomial(degree) => {
+                let samples: Vec<_> = windows.iter().flat_map(samples).collect();
+                continuum::Polynomial::fit(&samples, degree as usize)
+                    .map(|p| (0..frame_cnt).map(|k| p.eval(to_x(k))).collect())
+            }
+            ContinuumModel::Spline => {
+                let knots = windows
+                    .iter()
+                    .map(samples)
+                    .filter(|samples| !samples.is_empty())
+                    .map(|samples| {
+                        let n = samples.len() as f64;
+                        let (x, y) = samples
+                            .iter()
+                            .fold((0.0, 0.0), |(x, y), &(xk, yk)| (x + xk, y + yk));
+                        (x / n, y / n)
+                    })
+                    .collect();
+                continuum::Spline::new(knots)
+                    .map(|s| (0..frame_cnt).map(|k| s.eval(to_x(k))).collect())
+            }
+        };
+        if let Some(values) = values {
+            for (out, v) in fitted.column_mut(j).iter_mut().zip(values) {
+                *out = v as f32;
+            }
+        }
+    }
+
+    let fitted = fitted.into_shape(shape).expect("Same number of elements");
+    let subtracted = cube.scalar() - &fitted;
+    Ok((cube.with_new_image(fitted), cube.with_new_image(subtracted)))
+}
+
+fn run_fit_gaussian_line(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    components: i64,
+) -> Vec<Result<IOValue, IOErr>> {
+    match fit_gaussian_line(image, start, end, components) {
+        Ok(maps) => maps
+            .into_iter()
+            .map(|map| Ok(IOValue::Image(map)))
+            .collect(),
+        Err(e) => repeat_err(e, 8),
+    }
+}
+
+/// Fit Gaussian profiles on each spectrum of image. Return maps of amplitude,
+/// center, sigma, flux and maps of their standard errors.
+fn fit_gaussian_line(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    components: i64,
+) -> Result<Vec<WcsArray>, IOErr> {
+    let start = try_into_unsigned!(start)?;
+    let end = try_into_unsigned!(end)?;
+    let count = try_into_unsigned!(components)?;
+    precheck!(count > 0, "'components' must be strictly positive")?;
+    dim_is!(image, 3)?;
+    is_sliceable!(image, start, end)?;
+
+    let frames = image.load_frames(start..end);
+    let shape = frames.shape().to_vec();
+    let spaxel_cnt = shape[1] * shape[2];
+    let spectra = Array2::from_shape_vec(
+        (shape[0], spaxel_cnt),
+        frames.scalar().iter().cloned().collect(),
+    )
+    .expect("Same number of elements");
+    let samples: Vec<Vec<_>> = (0..spaxel_cnt)
+        .map(|j| {
+            spectra
+                .column(j)
+                .iter()
+                .enumerate()
+                .filter(|&(_, y)| !y.is_nan())
+                .map(|(k, y)| (k as f64, f64::from(*y)))
+                .collect()
+        })
+        .collect();
+    let fits: Vec<_> = samples
+        .par_iter()
+        .map(|samples| line_fit::fit_gaussians(samples, count))
+        .collect();
+
+    // Convert pixel coordinates on spectral axis into world coordinates
+    let to_world = |pixel: f64| match frames.pix2world(2, pixel as f32) {
+        Some(value) => f64::from(value),
+        None => pixel + start as f64,
+    };
+    let mut maps = vec![vec![::std::f32::NAN; count * spaxel_cnt]; 8];
+    for (j, fit) in fits.iter().enumerate() {
+        let fit = match *fit {
+            Some(ref fit) => fit,
+            None => continue,
+        };
+        for k in 0..count {
+            let (c, e) = (fit.components[k], fit.errors[k]);
+            let step = (to_world(c.center + 0.5) - to_world(c.center - 0.5)).abs();
+            let sigma = c.sigma * step;
+            let sigma_err = e.sigma * step;
+            let norm = (2.0 * ::std::f64::consts::PI).sqrt();
+            let flux = norm * c.amplitude * sigma;
+            let flux_var = norm
+                * norm
+                * (sigma * sigma * e.amplitude * e.amplitude
+                    + c.amplitude * c.amplitude * sigma_err * sigma_err
+                    + 2.0 * c.amplitude * sigma * fit.amplitude_sigma_covariance[k] * step);
+            let values = [
+                c.amplitude,
+                to_world(c.center),
+                sigma,
+                flux,
+                e.amplitude,
+                e.center * step,
+                sigma_err,
+                flux_var.max(0.0).sqrt(),
+            ];
+            for (map, value) in maps.iter_mut().zip(&values) {
+                map[k * spaxel_cnt + j] = *value as f32;
+            }
+        }
+    }
+
+    let value_unit = frames.array().unit().clone();
+    let world_unit = frames.world_unit(2);
+    let flux_unit = &value_unit * &world_unit;
+    let units = [&value_unit, &world_unit, &world_unit, &flux_unit];
+    let map_shape = if count == 1 {
+        vec![shape[1], shape[2]]
+    } else {
+        vec![count, shape[1], shape[2]]
+    };
+    Ok(maps
+        .into_iter()
+        .enumerate()
+        .map(|(i, map)| {
+            let map =
+                ArrayD::from_shape_vec(map_shape.clone(), map).expect("Same number of elements");
+            frames.make_slice(
+                &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+                Dimensioned::new(map, units[i % 4].clone()),
+            )
+        })
+        .collect())
+}
+
+/// Get the size `(width, height)` of the spatial plane of `image`, i.e. the
+/// length of its axes 0 and 1 in FITS order.
+fn spatial_shape(image: &WcsArray) -> Result<(usize, usize), IOErr> {
+    let shape = image.shape();
+    let ndim = shape.len();
+    precheck!(ndim >= 2, "Image has no spatial plane (got {} axes)", ndim)?;
+    Ok((shape[ndim - 1], shape[ndim - 2]))
+}
+
+/// Make a new image out of the spatial plane `out` computed from `image`.
+///
+/// A pixel `p` of `out` is located at pixel `start + factor * p` of `image`,
+/// where `(start, factor)` is given by `x` and `y` for axes 0 and 1 (in FITS
+/// order). All other axes are unchanged.
+fn make_spatial_slice(
+    image: &WcsArray,
+    x: (f32, f32),
+    y: (f32, f32),
+    out: ArrayD<f32>,
+) -> WcsArray {
+    let indices: Vec<_> = (0..out.ndim())
+        .map(|i| match i {
+            0 => (0, x.0, x.1),
+            1 => (1, y.0, y.1),
+            _ => (i, 0.0, 1.0),
+        })
+        .collect();
+    image.make_slice(&indices, image.array().with_new_value(out))
+}
+
+fn run_crop(image: &WcsArray, x: i64, y: i64, width: i64, height: i64) -> Result<IOValue, IOErr> {
+    let x = try_into_unsigned!(x)?;
+    let y = try_into_unsigned!(y)?;
+    let width = try_into_unsigned!(width)?;
+    let height = try_into_unsigned!(height)?;
+    let (nx, ny) = spatial_shape(image)?;
+    precheck!(
+        x < nx && y < ny,
+        "Origin ({}, {}) is out of the image of size {}x{}",
+        x,
+        y,
+        nx,
+        ny
+    )?;
+    let width = if width == 0 { nx - x } else { width };
+    let height = if height == 0 { ny - y } else { height };
+    precheck!(
+        x + width <= nx && y + height <= ny,
+        "Region {}x{} at ({}, {}) exceeds the image of size {}x{}",
+        width,
+        height,
+        x,
+        y,
+        nx,
+        ny
+    )?;
+
+    let ndim = image.shape().len();
+    let out = image
+        .scalar()
+        .slice_axis(Axis(ndim - 1), Slice::from(x..x + width))
+        .slice_axis(Axis(ndim - 2), Slice::from(y..y + height))
+        .to_owned();
+    Ok(IOValue::Image(make_spatial_slice(
+        image,
+        (x as f32, 1.0),
+        (y as f32, 1.0),
+        out,
+    )))
+}
+
+fn run_rebin(image: &WcsArray, factor_x: i64, factor_y: i64, mean: bool) -> Result<IOValue, IOErr> {
+    let factor_x = try_into_unsigned!(factor_x)?;
+    let factor_y = try_into_unsigned!(factor_y)?;
+    precheck!(
+        factor_x > 0 && factor_y > 0,
+        "Rebinning factors must be strictly positive"
+    )?;
+    let (nx, ny) = spatial_shape(image)?;
+    precheck!(
+        factor_x <= nx && factor_y <= ny,
+        "Rebinning factors {}x{} exceed the image of size {}x{}",
+        factor_x,
+        factor_y,
+        nx,
+        ny
+    )?;
+
+    let weights_x = resample::rebin_weights(nx, factor_x, mean);
+    let weights_y = resample::rebin_weights(ny, factor_y, mean);
+    let out = resample::apply_weights(image.scalar(), &weights_y, &weights_x);
+    // Center of the first bin
+    let start = |factor: usize| (factor as f32 - 1.0) / 2.0;
+    Ok(IOValue::Image(make_spatial_slice(
+        image,
+        (start(factor_x), factor_x as f32),
+        (start(factor_y), factor_y as f32),
+        out,
+    )))
+}
+
+fn parse_resampling(method: &str) -> Result<resample::Resampling, IOErr> {
+    match method.trim().to_lowercase().as_str() {
+        "nearest" => Ok(resample::Resampling::Nearest),
+        "bilinear" => Ok(resample::Resampling::Bilinear),
+        "flux" => Ok(resample::Resampling::Flux),
+        _ => Err(IOErr::UnexpectedInput(format!(
+            "Unknown resampling method '{}'. Expected 'nearest', 'bilinear' or 'flux'.",
+            method
+        ))),
+    }
+}
+
+fn run_resample(image: &WcsArray, width: i64, height: i64, method: &str) -> Result<IOValue, IOErr> {
+    let width = try_into_unsigned!(width)?;
+    let height = try_into_unsigned!(height)?;
+    let method = parse_resampling(method)?;
+    let (nx, ny) = spatial_shape(image)?;
+    precheck!(nx > 0 && ny > 0, "Image is empty")?;
+    let width = if width == 0 { nx } else { width };
+    let height = if height == 0 { ny } else { height };
+
+    let weights_x = resample::resample_weights(method, nx, width);
+    let weights_y = resample::resample_weights(method, ny, height);
+    let out = resample::apply_weights(image.scalar(), &weights_y, &weights_x);
+    // Output pixel p is centered on input pixel (p + 0.5) * scale - 0.5
+    let scale_x = nx as f32 / width as f32;
+    let scale_y = ny as f32 / height as f32;
+    Ok(IOValue::Image(make_spatial_slice(
+        image,
+        ((scale_x - 1.0) / 2.0, scale_x),
+        ((scale_y - 1.0) / 2.0, scale_y),
+        out,
+    )))
+}
+
+fn run_reproject(source: &WcsArray, reference: &WcsArray, method: &str) -> Result<IOValue, IOErr> {
+    let method = parse_resampling(method)?;
+    let (nx, ny) = spatial_shape(source)?;
+    precheck!(nx > 0 && ny > 0, "Source image is empty")?;
+    let (width, height) = spatial_shape(reference)?;
+    let (source_wcs, reference_wcs) = match (source.wcs(), reference.wcs()) {
+        (Some(source_wcs), Some(reference_wcs)) => (source_wcs, reference_wcs),
+        _ => {
+            return Err(IOErr::UnexpectedInput(
+                "Both source and reference must have world coordinates".to_owned(),
+            ))
+        }
+    };
+
+    // World coordinates of the reference may be expressed in other units
+    let factors: Vec<_> = (0..2)
+        .map(|axis| {
+            reference
+                .world_unit(axis)
+                .conversion_factor(&source.world_unit(axis))
+                .unwrap_or(1.0)
+        })
+        .collect();
+    let origin = source_wcs.pix2world([0.0; 4]);
+    let to_source = |x: f32, y: f32| {
+        let world = reference_wcs.pix2world([x, y, 0.0, 0.0]);
+        let mut target = origin;
+        target[0] = world[0] * factors[0];
+        target[1] = world[1] * factors[1];
+        match source_wcs.world2pix(target) {
+            Some(pixel) => [pixel[0], pixel[1]],
+            None => [::std::f32::NAN; 2],
+        }
+    };
+    let positions = Array2::from_shape_fn((height, width), |(y, x)| to_source(x as f32, y as f32));
+    let mut out = resample::sample(source.scalar(), &positions, method);
+
+    if method == resample::Resampling::Flux {
+        // Area of each reference pixel in source pixels
+        let area = Array2::from_shape_fn((height, width), |(y, x)| {
+            let (x, y) = (x as f32, y as f32);
+            let (left, right) = (to_source(x - 0.5, y), to_source(x + 0.5, y));
+            let (bottom, top) = (to_source(x, y - 0.5), to_source(x, y + 0.5));
+            let dx = [right[0] - left[0], right[1] - left[1]];
+            let dy = [top[0] - bottom[0], top[1] - bottom[1]];
+            (dx[0] * dy[1] - dx[1] * dy[0]).abs()
+        });
+        let ndim = out.ndim();
+        for (index, v) in out.indexed_iter_mut() {
+            *v *= area[[index[ndim - 2], index[ndim - 1]]];
+        }
+    }
+
+    Ok(IOValue::Image(source.with_spatial_axes_of(
+        reference,
+        source.array().with_new_value(out),
+    )))
+}
+
 fn run_convert_to_logscale(
     image: &WcsArray,
     a: f32,
@@ -932,14 +2717,268 @@ fn run_negation(image: &WcsArray) -> Result<IOValue, IOErr> {
     Ok(IOValue::Image(out))
 }
 
+fn run_set_variance(
+    image: &WcsArray,
+    variance: &WcsArray,
+    is_error: bool,
+) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, variance)?;
+    let mut variance = variance.scalar().clone();
+    if is_error {
+        variance.mapv_inplace(|v| v * v);
+    }
+    Ok(IOValue::Image(image.clone().with_variance(Some(variance))))
+}
+
+/// Get the variance of `image`, failing if it is unknown
+fn known_variance(image: &WcsArray) -> Result<&ArrayD<f32>, IOErr> {
+    image.variance().ok_or_else(|| {
+        IOErr::UnexpectedInput(
+            "Image has no variance. Use 'fits_to_image' with a variance extension or 'set_variance'."
+                .to_owned(),
+        )
+    })
+}
+
+fn run_variance(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let variance = known_variance(image)?;
+    let unit = image.array().unit();
+    let indices: Vec<_> = (0..image.scalar().ndim()).map(|i| (i, 0.0, 1.0)).collect();
+    Ok(IOValue::Image(image.make_slice(
+        &indices,
+        Dimensioned::new(variance.clone(), unit * unit),
+    )))
+}
+
+fn run_signal_to_noise(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let variance = known_variance(image)?;
+    let snr = image.scalar() / &variance.mapv(f32::sqrt);
+    let indices: Vec<_> = (0..image.scalar().ndim()).map(|i| (i, 0.0, 1.0)).collect();
+    Ok(IOValue::Image(
+        image.make_slice(&indices, Dimensioned::new(snr, Unit::None)),
+    ))
+}
+
+fn run_statistics(
+    image: &WcsArray,
+    roi: &ROI,
+    sigma: f32,
+    iterations: i64,
+) -> Vec<Result<IOValue, IOErr>> {
+    match statistics(image, roi, sigma, iterations) {
+        Ok((summary, clipped)) => {
+            let array = image.array();
+            vec![
+                Ok(IOValue::Quantity(array.with_new_value(summary.mean))),
+                Ok(IOValue::Quantity(array.with_new_value(summary.median))),
+                Ok(IOValue::Quantity(array.with_new_value(summary.std))),
+                Ok(IOValue::Quantity(array.with_new_value(summary.mad))),
+                Ok(IOValue::Quantity(array.with_new_value(clipped.mean))),
+                Ok(IOValue::Quantity(array.with_new_value(clipped.median))),
+                Ok(IOValue::Quantity(array.with_new_value(clipped.std))),
+                Ok(IOValue::Integer(summary.count as i64)),
+            ]
+        }
+        Err(e) => repeat_err(e, 8),
+    }
+}
+
+/// Compute the statistics of the unmasked values of `image` in `roi`,
+/// without and with sigma clipping
+fn statistics(
+    image: &WcsArray,
+    roi: &ROI,
+    sigma: f32,
+    iterations: i64,
+) -> Result<(stats::Statistics, stats::Statistics), IOErr> {
+    let iterations = try_into_unsigned!(iterations)?;
+    precheck!(sigma > 0.0, "'sigma' must be positive")?;
+
+    let mut selected = roi_on_frames(image, roi)?;
+    if let Some(mask) = image.mask() {
+        selected.zip_mut_with(mask, |selected, masked| *selected = *selected && !*masked);
+    }
+    let values: Vec<_> = image
+        .scalar()
+        .iter()
+        .zip(selected.iter())
+        .filter(|&(_, selected)| *selected)
+        .map(|(value, _)| *value)
+        .collect();
+    Ok((
+        stats::Statistics::new(&values),
+        stats::Statistics::sigma_clipped(&values, sigma, iterations),
+    ))
+}
+
+fn run_statistics_map(
+    image: &LazyWcsArray,
+    start: i64,
+    end: i64,
+    statistic: &str,
+    sigma: f32,
+    iterations: i64,
+) -> Result<IOValue, IOErr> {
+    let statistic = stats::Statistic::from_name(statistic).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Unknown statistic '{}'. Expected one of '{}'.",
+            statistic,
+            stats::Statistic::NAMES.join("', '")
+        ))
+    })?;
+    let iterations = try_into_unsigned!(iterations)?;
+    precheck!(sigma > 0.0, "'sigma' must be positive")?;
+
+    reduce_array_slice(
+        image,
+        start,
+        end,
+        |slices| {
+            let values: Vec<_> = slices
+                .lanes(Axis(0))
+                .into_iter()
+                .map(|lane| statistic.compute(&lane.to_vec(), sigma, iterations))
+                .collect();
+            ArrayD::from_shape_vec(slices.shape()[1..].to_vec(), values)
+                .expect("One value per lane")
+        },
+        |_| None,
+    )
+}
+
+fn run_set_mask(image: &WcsArray, mask: &WcsArray) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, mask)?;
+    let mask = mask.scalar().mapv(|flag| flag != 0.0);
+    Ok(IOValue::Image(image.clone().with_mask(Some(mask))))
+}
+
+/// Rasterize `roi` on each frame (i.e. on the two last axes in ndarray
+/// order) of `image`. Values in the region are `true`.
+fn roi_on_frames(image: &WcsArray, roi: &ROI) -> Result<ArrayD<bool>, IOErr> {
+    let shape = image.shape();
+    if let ROI::All = *roi {
+        return Ok(ArrayD::from_elem(shape, true));
+    }
+    let ndim = shape.len();
+    precheck!(
+        ndim >= 2,
+        "Expected an image with at least 2 dimensions, got {}",
+        ndim
+    )?;
+    Ok(roi
+        .mask((shape[ndim - 2], shape[ndim - 1]))
+        .broadcast(shape)
+        .expect("Region is broadcast to each frame")
+        .to_owned())
+}
+
+fn run_mask_roi(image: &WcsArray, roi: &ROI, outside: bool) -> Result<IOValue, IOErr> {
+    let mut region = roi_on_frames(image, roi)?;
+    if outside {
+        region.mapv_inplace(|selected| !selected);
+    }
+    let mask = match image.mask() {
+        Some(mask) => mask | &region,
+        None => region,
+    };
+    Ok(IOValue::Image(image.clone().with_mask(Some(mask))))
+}
+
+fn run_combine_masks(
+    image: &WcsArray,
+    other: &WcsArray,
+    operation: &str,
+) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, other)?;
+    let op: fn(bool, bool) -> bool = match operation.trim() {
+        "or" => |a, b| a || b,
+        "and" => |a, b| a && b,
+        "xor" => |a, b| a != b,
+        _ => {
+            return Err(IOErr::UnexpectedInput(format!(
+                "Unknown operation '{}'. Expected 'or', 'and' or 'xor'.",
+                operation
+            )))
+        }
+    };
+    let unmasked = ArrayD::from_elem(image.shape(), false);
+    let mut mask = image.mask().unwrap_or(&unmasked).clone();
+    mask.zip_mut_with(other.mask().unwrap_or(&unmasked), |a, b| *a = op(*a, *b));
+    Ok(IOValue::Image(image.clone().with_mask(Some(mask))))
+}
+
+fn run_apply_mask(image: &WcsArray, placeholder: f32) -> Result<IOValue, IOErr> {
+    let mut out = image.clone().with_mask(None);
+    if let Some(mask) = image.mask() {
+        out.scalar_mut().zip_mut_with(mask, |v, masked| {
+            if *masked {
+                *v = placeholder;
+            }
+        });
+    }
+    Ok(IOValue::Image(out))
+}
+
+fn run_mask(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let mask = match image.mask() {
+        Some(mask) => mask.mapv(|masked| if masked { 1.0 } else { 0.0 }),
+        None => ArrayD::zeros(image.shape()),
+    };
+    let indices: Vec<_> = (0..image.scalar().ndim()).map(|i| (i, 0.0, 1.0)).collect();
+    Ok(IOValue::Image(
+        image.make_slice(&indices, Dimensioned::new(mask, Unit::None)),
+    ))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{run_fits_to_image, run_make_plane3d, run_open_fits, run_slice_3d_to_2d, IOValue};
+    use super::{
+        nan_image, run_air_vacuum_conversion, run_aperture_photometry, run_apply_mask, run_average,
+        run_boxcar_smooth, run_centroid, run_clip, run_combine_masks, run_convert_axis_unit,
+        run_convert_unit, run_convolve, run_create_velocity_field_map, run_crop, run_expression,
+        run_extract_wave, run_fit_continuum, run_fit_gaussian_line, run_fits_to_image,
+        run_fits_to_table, run_gaussian_smooth, run_integral, run_linear_composition,
+        run_load_region, run_make_plane3d, run_mask, run_mask_roi, run_median_smooth,
+        run_moment_maps, run_open_fits, run_rebin, run_redshift_correction, run_reduce,
+        run_reproject, run_resample, run_save_fits, run_set_variance, run_signal_to_noise,
+        run_slice_3d_to_2d, run_slice_one_frame, run_statistics, run_statistics_map, run_variance,
+        spectral, unmasked_values, ColumnData, ContinuumModel, Dimensioned, IOErr, IOValue,
+        LazyWcsArray, PhysicalUnit, Unit, WcsArray, ROI,
+    };
+    use cake::ConvertibleVariants;
+    use fitrs::Hdu;
+    use ndarray::{Array2, ArrayD};
+    use test_util::assert_close;
+
+    /// Make an image without world coordinates. `shape` is in ndarray order.
+    fn array(shape: &[usize], values: Vec<f32>, unit: &str) -> WcsArray {
+        WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_shape_vec(shape, values).unwrap(),
+            Unit::parse(unit),
+        ))
+    }
+
+    /// Get the image output by a transform, loading it if it is lazy.
+    fn image(output: Result<IOValue, IOErr>) -> WcsArray {
+        match output {
+            Ok(IOValue::Image(image)) => image,
+            Ok(IOValue::LazyImage(image)) => image.load(),
+            Ok(_) => panic!("Expected image"),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Get the values of `image` in ndarray order.
+    fn values(image: &WcsArray) -> Vec<f32> {
+        image.scalar().iter().cloned().collect()
+    }
+
     #[test]
     fn test_open_fits() {
         let path = "test/test.fits";
         if let IOValue::Fits(fits) = run_open_fits(path).unwrap() {
-            if let IOValue::Image(image) = run_fits_to_image(&fits, 0, "").unwrap() {
+            if let IOValue::LazyImage(image) = run_fits_to_image(&fits, 0, "", "", "").unwrap() {
+                let image = image.load();
                 if let IOValue::Map2dTo3dCoords(map) =
                     run_make_plane3d(&[0.0, 0.0, 0.0], &[1.0, 0.5, 0.0], &[0.0, 0.5, 1.0], 10, 20)
                         .unwrap()
@@ -951,4 +2990,1026 @@ mod test {
         }
         panic!("Failed somewhere!");
     }
+
+    #[test]
+    fn test_lazy_image() {
+        let path = "test/test.fits";
+        if let IOValue::Fits(fits) = run_open_fits(path).unwrap() {
+            if let IOValue::LazyImage(mapped) = run_fits_to_image(&fits, 0, "", "", "").unwrap() {
+                let loaded = LazyWcsArray::Loaded(mapped.load());
+                let frame_cnt = mapped.shape()[0] as i64;
+                for &(start, end) in &[(0, 1), (1, frame_cnt - 1)] {
+                    assert_eq!(
+                        run_slice_one_frame(&mapped, end).unwrap(),
+                        run_slice_one_frame(&loaded, end).unwrap()
+                    );
+                    assert_eq!(
+                        run_integral(&mapped, start, end).unwrap(),
+                        run_integral(&loaded, start, end).unwrap()
+                    );
+                }
+                assert!(run_slice_one_frame(&mapped, frame_cnt).is_err());
+                return;
+            }
+        }
+        panic!("Failed somewhere!");
+    }
+
+    #[test]
+    fn test_save_fits() {
+        let path = "test/test.fits";
+        let out_path = ::std::env::temp_dir().join("aflak_test_save_fits.fits");
+        if let IOValue::Fits(fits) = run_open_fits(path).unwrap() {
+            if let IOValue::LazyImage(image) = run_fits_to_image(&fits, 0, "", "", "").unwrap() {
+                let image = image.load();
+                run_save_fits(&image, &out_path).unwrap();
+                if let IOValue::Fits(saved) = run_open_fits(&out_path).unwrap() {
+                    if let IOValue::LazyImage(saved) =
+                        run_fits_to_image(&saved, 0, "", "", "").unwrap()
+                    {
+                        let saved = saved.load();
+                        assert_eq!(image.scalar().shape(), saved.scalar().shape());
+                        assert_eq!(image.array().unit(), saved.array().unit());
+                        assert_eq!(image.axes(), saved.axes());
+                        return;
+                    }
+                }
+            }
+        }
+        panic!("Failed somewhere!");
+    }
+
+    #[test]
+    fn test_convert_unit() {
+        let plane = array(&[2, 2], vec![2.0; 4], "km");
+        let converted = image(run_convert_unit(&plane, "m"));
+        assert_eq!(converted.array().unit(), &Unit::parse("m"));
+        assert_close(&values(&converted), &[2000.0; 4], 1e-6);
+        assert!(run_convert_unit(&plane, "s").is_err());
+        assert!(run_convert_axis_unit(&plane, 2, "m").is_err());
+
+        let w_0 = Dimensioned::new(1.0, Unit::parse("km"));
+        let velocity = image(run_create_velocity_field_map(&plane, &w_0));
+        assert_close(&values(&velocity), &[3e5; 4], 1e-6);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        // Cube of 3 frames of 2x1 pixels
+        let cube = array(&[3, 1, 2], vec![1.0, 0.0, 5.0, 0.0, 3.0, 9.0], "Jy");
+
+        // Along FITS axis 2, i.e. the spectral axis
+        let boxcar = image(run_boxcar_smooth(&cube, 3, Some(2)));
+        assert_eq!(boxcar.array().unit(), &Unit::parse("Jy"));
+        assert_close(&values(&boxcar), &[3.0, 0.0, 3.0, 3.0, 4.0, 4.5], 1e-6);
+        let median = image(run_median_smooth(&cube, 3, Some(2)));
+        assert_close(&values(&median), &[3.0, 0.0, 3.0, 0.0, 4.0, 4.5], 1e-6);
+        // On the spatial plane
+        let median = image(run_median_smooth(&cube, 3, None));
+        assert_close(&values(&median), &[0.5, 0.5, 2.5, 2.5, 6.0, 6.0], 1e-6);
+        let gaussian = image(run_gaussian_smooth(&cube, 1.0, None));
+        assert_eq!(gaussian.array().unit(), &Unit::parse("Jy"));
+        // Frames are not mixed
+        let sums: Vec<_> = values(&gaussian).chunks(2).map(|c| c[0] + c[1]).collect();
+        assert_close(&sums, &[1.0, 5.0, 12.0], 1e-5);
+
+        // 1D kernel is applied along FITS axis 0
+        let kernel = array(&[2], vec![0.5, 0.5], "");
+        let convolved = image(run_convolve(&cube, &kernel));
+        assert_close(&values(&convolved), &[0.5, 0.0, 2.5, 0.0, 6.0, 9.0], 1e-6);
+
+        let spectrum = array(&[3], vec![1.0; 3], "Jy");
+        assert!(run_gaussian_smooth(&cube, 0.0, None).is_err());
+        assert!(run_gaussian_smooth(&cube, 1.0, Some(3)).is_err());
+        assert!(run_gaussian_smooth(&spectrum, 1.0, None).is_err());
+        assert!(run_boxcar_smooth(&cube, 0, Some(2)).is_err());
+        assert!(run_median_smooth(&cube, -1, Some(2)).is_err());
+        assert!(run_convolve(&spectrum, &cube).is_err());
+        assert!(run_convolve(&cube, &array(&[0], vec![], "")).is_err());
+        let nan_kernel = array(&[1], vec![::std::f32::NAN], "");
+        assert!(run_convolve(&cube, &nan_kernel).is_err());
+    }
+
+    #[test]
+    fn test_fit_continuum() {
+        let nan = ::std::f32::NAN;
+        // First spaxel has a linear continuum, an emission line on frame 5
+        // and a NaN on frame 1. Second spaxel is NaN in line-free windows.
+        let mut spectra = Vec::new();
+        let mut continuum = Vec::new();
+        let mut subtracted = Vec::new();
+        for k in 0..10 {
+            let line = if k == 5 { 10.0 } else { 0.0 };
+            let first = if k == 1 {
+                nan
+            } else {
+                2.0 * k as f32 + 1.0 + line
+            };
+            let second = if k >= 4 && k < 7 { 1.0 } else { nan };
+            spectra.extend(&[first, second]);
+            continuum.extend(&[2.0 * k as f32 + 1.0, nan]);
+            subtracted.extend(&[if k == 1 { nan } else { line }, nan]);
+        }
+        let cube = LazyWcsArray::from(array(&[10, 1, 2], spectra, ""));
+
+        for model in vec![ContinuumModel::Polynomial(1), ContinuumModel::Spline] {
+            let outputs = run_fit_continuum(&cube, "7:10, 0:4", model);
+            let images: Vec<_> = outputs.into_iter().map(image).collect();
+            assert_close(&values(&images[0]), &continuum, 1e-4);
+            assert_close(&values(&images[1]), &subtracted, 1e-4);
+        }
+
+        for windows in &["", "4:2", "0:11", "0:5, 3:8", "0-4"] {
+            let outputs = run_fit_continuum(&cube, windows, ContinuumModel::Spline);
+            assert!(outputs.iter().all(|output| output.is_err()));
+        }
+        let outputs = run_fit_continuum(&cube, "0:4", ContinuumModel::Polynomial(-1));
+        assert!(outputs.iter().all(|output| output.is_err()));
+    }
+
+    #[test]
+    fn test_fit_gaussian_line() {
+        let nan = ::std::f32::NAN;
+        let gaussian = |x: f32, a: f32, c: f32, s: f32| a * (-0.5 * ((x - c) / s).powi(2)).exp();
+        // First spaxel has two lines, second spaxel has no line
+        let mut spectra = Vec::new();
+        for k in 0..30 {
+            let x = k as f32;
+            spectra.push(gaussian(x, 5.0, 8.3, 1.7) + gaussian(x, 2.0, 20.6, 1.2));
+            spectra.push(0.0);
+        }
+        let cube = LazyWcsArray::from(array(&[30, 1, 2], spectra, "Jy"));
+
+        // Single line on frames 0 to 15
+        let single: Vec<_> = run_fit_gaussian_line(&cube, 0, 15, 1)
+            .into_iter()
+            .map(image)
+            .collect();
+        assert_eq!(single.len(), 8);
+        assert_eq!(single[0].shape(), &[1, 2]);
+        assert_eq!(single[0].array().unit(), &Unit::parse("Jy"));
+        assert_close(&values(&single[0]), &[5.0, nan], 1e-4);
+        assert_close(&values(&single[1]), &[8.3, nan], 1e-4);
+        assert_close(&values(&single[2]), &[1.7, nan], 1e-4);
+        let flux = 5.0 * 1.7 * (2.0 * ::std::f32::consts::PI).sqrt();
+        assert_close(&values(&single[3]), &[flux, nan], 1e-4);
+
+        // Two components on the whole spectrum sorted by center, one per frame
+        let double: Vec<_> = run_fit_gaussian_line(&cube, 0, 29, 2)
+            .into_iter()
+            .map(image)
+            .collect();
+        assert_eq!(double[0].shape(), &[2, 1, 2]);
+        assert_close(&values(&double[0]), &[5.0, nan, 2.0, nan], 1e-4);
+        assert_close(&values(&double[1]), &[8.3, nan, 20.6, nan], 1e-4);
+        assert_close(&values(&double[2]), &[1.7, nan, 1.2, nan], 1e-4);
+
+        for outputs in vec![
+            run_fit_gaussian_line(&cube, 0, 30, 1),
+            run_fit_gaussian_line(&cube, 0, 10, 0),
+        ] {
+            assert_eq!(outputs.len(), 8);
+            assert!(outputs.iter().all(|output| output.is_err()));
+        }
+    }
+
+    #[test]
+    fn test_moment_maps() {
+        // Line centered on frame 14 with a sigma of 2 frames
+        let spectrum: Vec<_> = (0..30)
+            .map(|k| 3.0 * (-0.5 * ((k as f32 - 14.0) / 2.0).powi(2)).exp())
+            .collect();
+        let cube = LazyWcsArray::from(array(&[30, 1, 1], spectrum, "Jy"));
+        // Without WCS, wavelength is the frame index. One frame is 3e4 km/s.
+        let w_0 = Dimensioned::new(10.0, Unit::None);
+        let maps = |clip| -> Vec<WcsArray> {
+            run_moment_maps(&cube, 0, 29, &w_0, 1.0, clip)
+                .into_iter()
+                .map(image)
+                .collect()
+        };
+        let pixels = |maps: &[WcsArray]| -> Vec<f32> {
+            maps.iter().map(|map| map.scalar()[[0, 0]]).collect()
+        };
+
+        let moments = maps(false);
+        let flux = 3.0 * 2.0 * (2.0 * ::std::f32::consts::PI).sqrt() * 3e4;
+        assert_close(&pixels(&moments), &[flux, 1.2e5, 6e4], 1e-3);
+        assert_eq!(
+            moments[0].array().unit(),
+            &(&Unit::parse("Jy") * &Unit::parse("km/s"))
+        );
+        assert_eq!(moments[2].array().unit(), &Unit::parse("km/s"));
+
+        let clipped = pixels(&maps(true));
+        assert_close(&clipped[1..2], &[1.2e5], 1e-3);
+        assert!(clipped[2] < 6e4);
+
+        let zero = Dimensioned::new(0.0, Unit::None);
+        assert!(run_moment_maps(&cube, 0, 29, &zero, 0.0, false)
+            .iter()
+            .all(|output| output.is_err()));
+    }
+
+    #[test]
+    fn test_expression() {
+        let eval = |expr: &str, inputs: &[&WcsArray]| image(run_expression(expr, inputs));
+        let cube = array(&[2, 1, 2], vec![1.0, 2.0, 3.0, 4.0], "Jy");
+        let plane = array(&[1, 2], vec![100.0, 10.0], "Jy");
+        let variance = array(&[1, 2], vec![4.0, 16.0], "Jy2");
+        let nan = array(&[], vec![::std::f32::NAN], "");
+
+        // Plane is broadcast to each frame of the cube
+        let snr = eval("(a - b) / sqrt(c)", &[&cube, &plane, &variance, &nan]);
+        assert_eq!(snr.shape(), &[2, 1, 2]);
+        assert_close(&values(&snr), &[-49.5, -2.0, -48.5, -1.5], 1e-6);
+        assert_eq!(
+            snr.array().unit(),
+            &Unit::Physical(PhysicalUnit::dimensionless())
+        );
+
+        let log = eval("2*log10(b) + -a^2 / 2", &[&plane, &plane, &nan, &nan]);
+        assert_close(&values(&log), &[4.0 - 5000.0, 2.0 - 50.0], 1e-6);
+
+        // Units are converted in additions
+        let mjy = array(&[], vec![500.0], "mJy");
+        let sum = eval("a + b", &[&plane, &mjy, &nan, &nan]);
+        assert_eq!(sum.array().unit(), &Unit::parse("Jy"));
+        assert_close(&values(&sum), &[100.5, 10.5], 1e-6);
+        assert_eq!(
+            eval("a * a", &[&plane, &nan, &nan, &nan]).array().unit(),
+            &Unit::parse("Jy2")
+        );
+
+        for expr in &["a +", "a + s", "foo(a)", "max(a)", "(a", "a + c", "a ^ a"] {
+            assert!(run_expression(expr, &[&plane, &nan, &variance, &nan]).is_err());
+        }
+        let spectrum = array(&[3], vec![0.0; 3], "");
+        assert!(run_expression("a + b", &[&cube, &spectrum, &nan, &nan]).is_err());
+    }
+
+    #[test]
+    fn test_expression_operands() {
+        let plane = array(&[1, 2], vec![100.0, 10.0], "Jy");
+        let nan = nan_image();
+        // Floats and quantities are converted to 0-dimensional images
+        let float = match IOValue::convert("Float", "Image", &IOValue::Float(2.0)) {
+            Some(value) => match *value {
+                IOValue::Image(ref image) => image.clone(),
+                _ => panic!("Expected image"),
+            },
+            None => panic!("Float cannot be converted to Image"),
+        };
+        let scaled = image(run_expression("a * b", &[&plane, &float, &nan, &nan]));
+        assert_close(&values(&scaled), &[200.0, 20.0], 1e-6);
+
+        // Unconnected operands are NaN
+        let sum = image(run_expression("a + d", &[&plane, &nan, &nan, &nan]));
+        assert!(values(&sum).iter().all(|v| v.is_nan()));
+
+        // There are exactly 4 operands
+        assert!(run_expression("a", &[&plane, &nan, &nan]).is_err());
+        assert!(run_expression("a + f", &[&plane, &nan, &nan, &nan]).is_err());
+    }
+
+    /// Make a FITS header block from the given cards
+    fn header_block(cards: &[&str]) -> Vec<u8> {
+        let mut block = Vec::new();
+        for card in cards.iter().chain(&["END"]) {
+            block.extend_from_slice(format!("{:<80}", card).as_bytes());
+        }
+        let padded_len = (block.len() + 2879) / 2880 * 2880;
+        block.resize(padded_len, b' ');
+        block
+    }
+
+    /// Make a FITS data block of 32-bit floats from the given values
+    fn float_block(values: &[f32]) -> Vec<u8> {
+        let mut block = Vec::new();
+        for v in values {
+            block.extend_from_slice(&v.to_bits().to_be_bytes());
+        }
+        let padded_len = (block.len() + 2879) / 2880 * 2880;
+        block.resize(padded_len, 0);
+        block
+    }
+
+    #[test]
+    fn test_crop_rebin_resample() {
+        // Cube of 2 frames of 4x6 pixels
+        let mut data = vec![1.0; 48];
+        data[7] = ::std::f32::NAN;
+        let mut hdu = Hdu::new(&[6, 4, 2], vec![0.0; 48]);
+        hdu.insert("CRPIX1", 1.0);
+        hdu.insert("CRVAL1", 100.0);
+        hdu.insert("CDELT1", 2.0);
+        hdu.insert("CRPIX2", 1.0);
+        hdu.insert("CRVAL2", 50.0);
+        hdu.insert("CDELT2", 1.0);
+        let cube = WcsArray::from_hdu_and_image(
+            &hdu,
+            ArrayD::from_shape_vec(vec![2, 4, 6], data).unwrap(),
+        );
+        let frame =
+            |cube: &WcsArray| image(run_slice_one_frame(&LazyWcsArray::from(cube.clone()), 1));
+
+        let cropped = image(run_crop(&cube, 2, 1, 3, 0));
+        assert_eq!(cropped.shape(), &[2, 3, 3]);
+        let cropped = frame(&cropped);
+        assert_eq!(cropped.pix2world(0, 0.0), Some(104.0));
+        assert_eq!(cropped.pix2world(1, 0.0), Some(51.0));
+        assert!(run_crop(&cube, 2, 1, 5, 0).is_err());
+
+        let summed = image(run_rebin(&cube, 2, 2, false));
+        assert_eq!(summed.shape(), &[2, 2, 3]);
+        assert_close(&values(&summed), &[4.0; 12], 1e-6);
+        let mean = image(run_rebin(&cube, 2, 2, true));
+        assert_close(&values(&mean), &[1.0; 12], 1e-6);
+        let mean = frame(&mean);
+        assert_eq!(mean.pix2world(0, 0.0), Some(101.0));
+        assert_eq!(mean.pix2world(0, 1.0), Some(105.0));
+        assert_eq!(mean.pix2world(1, 0.0), Some(50.5));
+
+        let flux = image(run_resample(&cube, 3, 2, "flux"));
+        assert_eq!(flux.shape(), &[2, 2, 3]);
+        assert_close(&values(&flux), &[4.0; 12], 1e-6);
+        let bilinear = image(run_resample(&cube, 12, 8, "bilinear"));
+        assert_eq!(bilinear.shape(), &[2, 8, 12]);
+        assert_close(&values(&bilinear), &[1.0; 192], 1e-6);
+        let bilinear = frame(&bilinear);
+        assert_eq!(bilinear.pix2world(0, 0.0), Some(99.5));
+        assert_eq!(bilinear.pix2world(0, 2.0), Some(101.5));
+        let nearest = image(run_resample(&cube, 12, 0, "nearest"));
+        assert_eq!(nearest.shape(), &[2, 4, 12]);
+        assert!(nearest.scalar()[[0, 1, 2]].is_nan());
+        assert!(run_resample(&cube, 3, 2, "cubic").is_err());
+    }
+
+    #[test]
+    fn test_reproject() {
+        let wcs_image = |shape: &[usize], values: Vec<f32>, crval: [f64; 2], cdelt: [f64; 2]| {
+            let mut fits_shape = shape.to_vec();
+            fits_shape.reverse();
+            let mut hdu = Hdu::new(&fits_shape, vec![0.0; values.len()]);
+            for i in 0..2 {
+                hdu.insert(format!("CRPIX{}", i + 1).as_str(), 1.0);
+                hdu.insert(format!("CRVAL{}", i + 1).as_str(), crval[i]);
+                hdu.insert(format!("CDELT{}", i + 1).as_str(), cdelt[i]);
+            }
+            hdu.insert("BUNIT", "Jy");
+            let values = ArrayD::from_shape_vec(shape.to_vec(), values).unwrap();
+            WcsArray::from_hdu_and_image(&hdu, values)
+        };
+        // Two frames of a linear plane, v = x + 10 y + 100 k
+        let values: Vec<_> = (0..32)
+            .map(|i| (i % 4 + 10 * (i / 4 % 4) + 100 * (i / 16)) as f32)
+            .collect();
+        let source = wcs_image(&[2, 4, 4], values, [0.0, 0.0], [1.0, 1.0]);
+        // Pixel (x, y) of reference is pixel (1 + x / 2, 1 + y) of source
+        let reference = wcs_image(&[4, 3], vec![0.0; 12], [1.0, 1.0], [0.5, 1.0]);
+
+        let reproject = |method| image(run_reproject(&source, &reference, method));
+        let bilinear = reproject("bilinear");
+        assert_eq!(bilinear.shape(), &[2, 4, 3]);
+        let pixels = [bilinear.scalar()[[0, 2, 2]], bilinear.scalar()[[1, 0, 1]]];
+        assert_close(&pixels, &[32.0, 111.5], 1e-6);
+        assert!(bilinear.scalar()[[0, 3, 0]].is_nan());
+        assert_eq!(bilinear.pix2world(0, 2.0), Some(2.0));
+        assert_eq!(bilinear.array().unit(), &Unit::parse("Jy"));
+
+        let flux = reproject("flux");
+        assert_close(&[flux.scalar()[[0, 2, 2]]], &[16.0], 1e-6);
+
+        let no_wcs = WcsArray::from_array(reference.array().clone());
+        assert!(run_reproject(&source, &no_wcs, "bilinear").is_err());
+    }
+
+    #[test]
+    fn test_roi() {
+        let count = |roi: &ROI| roi.mask((5, 5)).iter().filter(|m| **m).count();
+        let circle = ROI::Circle {
+            center: (2.0, 2.0),
+            radius: 1.5,
+        };
+        assert_eq!(count(&circle), 9);
+        let annulus = ROI::Annulus {
+            center: (2.0, 2.0),
+            inner: 1.0,
+            outer: 2.0,
+        };
+        assert_eq!(count(&annulus), 8);
+
+        let ellipse = |angle| ROI::Ellipse {
+            center: (2.0, 2.0),
+            radii: (2.0, 1.0),
+            angle,
+        };
+        assert_eq!(count(&ellipse(0.0)), 7);
+        // Masks are indexed by (row, column), i.e. (y, x)
+        assert!(ellipse(0.0).mask((5, 5))[[2, 0]]);
+        assert!(!ellipse(90.0).mask((5, 5))[[2, 0]]);
+        assert!(ellipse(90.0).mask((5, 5))[[0, 2]]);
+
+        let rectangle = |angle| ROI::Rectangle {
+            center: (2.0, 2.0),
+            size: (3.0, 1.0),
+            angle,
+        };
+        assert_eq!(count(&rectangle(0.0)), 3);
+        assert!(rectangle(90.0).mask((5, 5))[[1, 2]]);
+        assert!(!rectangle(90.0).mask((5, 5))[[2, 1]]);
+
+        let triangle = ROI::Polygon(vec![(-0.5, -0.5), (4.6, -0.5), (-0.5, 4.6)]);
+        assert_eq!(count(&triangle), 15);
+
+        let boxed = |roi: &ROI| Box::new(roi.clone());
+        let union = ROI::Union(boxed(&circle), boxed(&annulus));
+        assert_eq!(count(&union), 13);
+        let intersection = ROI::Intersection(boxed(&circle), boxed(&rectangle(0.0)));
+        assert_eq!(count(&intersection), 3);
+        let center_only = ROI::Annulus {
+            center: (2.0, 2.0),
+            inner: 0.5,
+            outer: 2.0,
+        };
+        let difference = ROI::Difference(boxed(&circle), boxed(&center_only));
+        assert_eq!(count(&difference), 1);
+
+        let data = Array2::from_shape_vec((5, 5), (0..25).map(|v| v as f32).collect()).unwrap();
+        assert_eq!(difference.filter(data.view()), vec![((2, 2), 12.0)]);
+    }
+
+    #[test]
+    fn test_region() {
+        let mut hdu = Hdu::new(&[10, 10], vec![0.0; 100]);
+        hdu.insert("CTYPE1", "RA---TAN");
+        hdu.insert("CTYPE2", "DEC--TAN");
+        for i in 0..2 {
+            hdu.insert(format!("CRPIX{}", i + 1).as_str(), 6.0);
+            hdu.insert(format!("CDELT{}", i + 1).as_str(), [-1.0, 1.0][i] / 3600.0);
+        }
+        hdu.insert("CRVAL1", 83.0);
+        hdu.insert("CRVAL2", 22.0);
+        let image = WcsArray::from_hdu_and_image(&hdu, ArrayD::zeros(vec![10, 10]));
+        let no_wcs = WcsArray::from_array(image.array().clone());
+
+        // World coordinates are resolved with the WCS of the image
+        let path = ::std::env::temp_dir().join("aflak_test_region.reg");
+        ::std::fs::write(&path, "fk5;circle(83,22,2\")").unwrap();
+        match run_load_region(&path, &image).unwrap() {
+            IOValue::Roi(ROI::Circle { center, radius }) => {
+                assert_close(&[center.0, center.1, radius], &[5.0, 5.0, 2.0], 1e-2);
+            }
+            _ => panic!("Expected circle"),
+        }
+        assert!(run_load_region(&path, &no_wcs).is_err());
+        assert!(run_load_region("test/no_such_region.reg", &image).is_err());
+    }
+
+    #[test]
+    fn test_aperture_photometry() {
+        let mut data = vec![1.0; 25];
+        data[12] = 11.0;
+        let image = array(&[5, 5], data, "Jy");
+        let aperture = ROI::Circle {
+            center: (2.0, 2.0),
+            radius: 1.0,
+        };
+        let annulus = ROI::Annulus {
+            center: (2.0, 2.0),
+            inner: 1.5,
+            outer: 3.0,
+        };
+
+        let photometry = |background: &ROI, gain| -> Vec<f32> {
+            run_aperture_photometry(&image, &aperture, background, gain)
+                .into_iter()
+                .map(|output: Result<IOValue, IOErr>| match output.unwrap() {
+                    IOValue::Quantity(q) => {
+                        assert_eq!(q.unit(), &Unit::parse("Jy"));
+                        *q.scalar()
+                    }
+                    IOValue::Integer(i) => i as f32,
+                    _ => panic!("Unexpected output"),
+                })
+                .collect()
+        };
+        assert_eq!(photometry(&annulus, 0.0), vec![15.0, 10.0, 1.0, 5.0, 0.0]);
+        assert_eq!(photometry(&annulus, 2.5)[4], 2.0);
+        let no_background = photometry(&ROI::PixelList(vec![]), 0.0);
+        let nan = ::std::f32::NAN;
+        assert_close(&no_background, &[15.0, 15.0, 0.0, 5.0, nan], 1e-6);
+
+        let outside = ROI::PixelList(vec![(10, 10)]);
+        assert!(run_aperture_photometry(&image, &outside, &annulus, 0.0)[0].is_err());
+    }
+
+    #[test]
+    fn test_fits_to_table() {
+        let mut file = header_block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+            "EXTEND  =                    T",
+        ]);
+        file.extend(header_block(&[
+            "XTENSION= 'BINTABLE'",
+            "BITPIX  =                    8",
+            "NAXIS   =                    2",
+            "NAXIS1  =                   18",
+            "NAXIS2  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "TFIELDS =                    3",
+            "TTYPE1  = 'NAME    '",
+            "TFORM1  = '6A      '",
+            "TTYPE2  = 'FLUX    '",
+            "TFORM2  = '2E      '",
+            "TUNIT2  = 'Jy      '",
+            "TTYPE3  = 'ID      '",
+            "TFORM3  = '1J      '",
+            "EXTNAME = 'CATALOG '",
+        ]));
+        let mut data = Vec::new();
+        for (name, flux, id) in &[("M31", [1.5f32, 2.0], 7i32), ("NGC253", [-1.0, 0.25], -3)] {
+            data.extend_from_slice(format!("{:<6}", name).as_bytes());
+            for f in flux {
+                data.extend_from_slice(&f.to_bits().to_be_bytes());
+            }
+            data.extend_from_slice(&id.to_be_bytes());
+        }
+        data.resize(2880, 0);
+        file.extend(data);
+
+        let path = ::std::env::temp_dir().join("aflak_test_fits_to_table.fits");
+        ::std::fs::write(&path, &file).unwrap();
+        if let IOValue::Fits(fits) = run_open_fits(&path).unwrap() {
+            if let IOValue::Table(table) = run_fits_to_table(&fits, 0, "CATALOG", "").unwrap() {
+                assert_eq!(table.rows(), 2);
+                assert_eq!(table.columns().len(), 3);
+                let flux = table.column("flux").unwrap();
+                assert_eq!(flux.unit(), &Unit::parse("Jy"));
+                assert_eq!(flux.repeat(), 2);
+                assert_eq!(flux.data(), &ColumnData::Float(vec![1.5, 2.0, -1.0, 0.25]));
+                assert_eq!(table.column("NAME").unwrap().format_cell(1), "NGC253");
+                assert_eq!(table.column("ID").unwrap().format_cell(1), "-3");
+
+                if let IOValue::Table(table) = run_fits_to_table(&fits, 1, "", "ID, name").unwrap()
+                {
+                    let names: Vec<_> = table.columns().iter().map(|c| c.name()).collect();
+                    assert_eq!(names, vec!["ID", "NAME"]);
+                    assert!(run_fits_to_table(&fits, 1, "", "MAG").is_err());
+                    return;
+                }
+            }
+        }
+        panic!("Failed somewhere!");
+    }
+
+    #[test]
+    fn test_variance() {
+        let mut file = header_block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    3",
+            "EXTEND  =                    T",
+        ]);
+        file.extend(float_block(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    3",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'STAT    '",
+        ]));
+        file.extend(float_block(&[1.0, 1.0, 4.0, 4.0, 9.0, 9.0]));
+
+        let path = ::std::env::temp_dir().join("aflak_test_variance.fits");
+        ::std::fs::write(&path, &file).unwrap();
+        let fits = match run_open_fits(&path).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        assert!(run_fits_to_image(&fits, 0, "", "ERR", "").is_err());
+        let cube = match run_fits_to_image(&fits, 0, "", "STAT", "").unwrap() {
+            IOValue::LazyImage(cube) => cube,
+            _ => panic!("Expected image"),
+        };
+        let variance = |image: &WcsArray| {
+            image
+                .variance()
+                .unwrap()
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let integral = image(run_integral(&cube, 0, 2));
+        assert_close(&values(&integral), &[4.0, 6.0], 1e-6);
+        assert_close(&variance(&integral), &[5.0, 5.0], 1e-6);
+        let average = image(run_average(&cube, 0, 2));
+        assert_close(&variance(&average), &[1.25, 1.25], 1e-6);
+        let frame = image(run_slice_one_frame(&cube, 1));
+        assert_close(&variance(&frame), &[4.0, 4.0], 1e-6);
+        let composition = image(run_linear_composition(&integral, &integral, 2.0, -1.0));
+        assert_close(&variance(&composition), &[25.0, 25.0], 1e-6);
+
+        let snr = image(run_signal_to_noise(&integral));
+        assert_close(&values(&snr), &[4.0 / 5f32.sqrt(), 6.0 / 5f32.sqrt()], 1e-6);
+        assert_close(&values(&image(run_variance(&integral))), &[5.0, 5.0], 1e-6);
+
+        let no_variance = WcsArray::from_array(integral.array().clone());
+        assert!(run_variance(&no_variance).is_err());
+        let errors = image(run_set_variance(&no_variance, &integral, true));
+        assert_close(&variance(&errors), &[16.0, 36.0], 1e-6);
+    }
+
+    #[test]
+    fn test_variance_extension() {
+        // Empty primary HDU, followed by data, quality and variance extensions
+        let mut file = header_block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+            "EXTEND  =                    T",
+        ]);
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'SCI     '",
+            "BUNIT   = 'Jy      '",
+        ]));
+        file.extend(float_block(&[1.0, 2.0, 3.0, 4.0]));
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                   16",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'DQ      '",
+        ]));
+        let mut flags = Vec::new();
+        for v in &[0i16, 0, 0, 2] {
+            flags.extend_from_slice(&v.to_be_bytes());
+        }
+        flags.resize(2880, 0);
+        file.extend(flags);
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'VAR     '",
+        ]));
+        file.extend(float_block(&[1.0, 4.0, 9.0, 16.0]));
+
+        let path = ::std::env::temp_dir().join("aflak_test_variance_extension.fits");
+        ::std::fs::write(&path, &file).unwrap();
+        let fits = match run_open_fits(&path).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        // The primary HDU has no data
+        assert!(run_fits_to_image(&fits, 0, "", "", "").is_err());
+        assert!(run_fits_to_image(&fits, 0, "SCI", "ERR", "").is_err());
+        let cube = match run_fits_to_image(&fits, 0, "SCI", "VAR", "").unwrap() {
+            IOValue::LazyImage(cube) => cube,
+            _ => panic!("Expected image"),
+        };
+
+        let frame = image(run_slice_one_frame(&cube, 1));
+        assert_eq!(frame.array().unit(), &Unit::parse("Jy"));
+        assert_close(&values(&frame), &[3.0, 4.0], 1e-6);
+        let variance = frame
+            .variance()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_close(&variance, &[9.0, 16.0], 1e-6);
+
+        // The mask plane is read from the quality extension
+        assert!(run_fits_to_image(&fits, 0, "SCI", "VAR", "FLAGS").is_err());
+        let cube = match run_fits_to_image(&fits, 0, "SCI", "VAR", "DQ").unwrap() {
+            IOValue::LazyImage(cube) => cube,
+            _ => panic!("Expected image"),
+        };
+        let frame = image(run_slice_one_frame(&cube, 1));
+        let mask = frame.mask().unwrap().iter().cloned().collect::<Vec<_>>();
+        assert_eq!(mask, vec![false, true]);
+        assert!(image(run_slice_one_frame(&cube, 0))
+            .mask()
+            .unwrap()
+            .iter()
+            .all(|m| !m));
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut flags = Vec::new();
+        for v in &[0i16, 1, 4, 1, 0, 1, 0, 1] {
+            flags.extend_from_slice(&v.to_be_bytes());
+        }
+        flags.resize(2880, 0);
+        let mut file = header_block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    4",
+            "EXTEND  =                    T",
+        ]);
+        file.extend(float_block(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]));
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                   16",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    2",
+            "NAXIS2  =                    1",
+            "NAXIS3  =                    4",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'DQ      '",
+        ]));
+        file.extend(flags);
+
+        let path = ::std::env::temp_dir().join("aflak_test_mask.fits");
+        ::std::fs::write(&path, &file).unwrap();
+        let fits = match run_open_fits(&path).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        let cube = match run_fits_to_image(&fits, 0, "", "", "DQ").unwrap() {
+            IOValue::LazyImage(cube) => cube,
+            _ => panic!("Expected image"),
+        };
+        let mask = |image: &WcsArray| image.mask().unwrap().iter().cloned().collect::<Vec<_>>();
+        let nan = ::std::f32::NAN;
+
+        // The second pixel is masked in all frames
+        let integral = image(run_integral(&cube, 0, 3));
+        assert_close(&values(&integral), &[6.0, nan], 1e-6);
+        assert_eq!(mask(&integral), vec![false, true]);
+        let average = image(run_average(&cube, 0, 3));
+        assert_close(&values(&average)[..1], &[3.0], 1e-6);
+        let centroid = image(run_centroid(&cube, 0, 3));
+        let wavelength = |k| cube.load().pix2world(2, k).unwrap_or(k);
+        let expected = (1.0 * wavelength(0.0) + 5.0 * wavelength(2.0)) / 6.0;
+        assert_close(&values(&centroid)[..1], &[expected], 1e-6);
+        let spectrum = image(run_extract_wave(&cube, &ROI::All));
+        assert_close(&values(&spectrum), &[1.0, 0.0, 5.0, 7.0], 1e-6);
+
+        let frame = image(run_slice_one_frame(&cube, 0));
+        assert_eq!(mask(&frame), vec![false, true]);
+        assert_eq!(unmasked_values(&frame).collect::<Vec<_>>(), vec![1.0]);
+        assert_eq!(values(&image(run_mask(&frame))), vec![0.0, 1.0]);
+
+        let clipped = image(run_clip(&frame, 1.5, true, true));
+        assert_close(&values(&clipped), &[1.0, 2.0], 1e-6);
+        assert_eq!(mask(&clipped), vec![false, true]);
+        let applied = image(run_apply_mask(&clipped, 0.0));
+        assert_close(&values(&applied), &[1.0, 0.0], 1e-6);
+        assert!(applied.mask().is_none());
+
+        let roi = ROI::PixelList(vec![(0, 0)]);
+        assert_eq!(
+            mask(&image(run_mask_roi(&frame, &roi, false))),
+            vec![true, true]
+        );
+        let unmasked = image(run_apply_mask(&frame, 2.0));
+        let outside = image(run_mask_roi(&unmasked, &roi, true));
+        assert_eq!(mask(&outside), vec![false, true]);
+        assert_eq!(
+            mask(&image(run_combine_masks(&outside, &frame, "xor"))),
+            vec![false, false]
+        );
+        assert_eq!(
+            mask(&image(run_combine_masks(&outside, &applied, "and"))),
+            vec![false, false]
+        );
+        assert!(run_combine_masks(&outside, &frame, "nand").is_err());
+
+        // Masks are combined by arithmetic operations
+        let sum = &frame - &unmasked;
+        assert_eq!(mask(&sum), vec![false, true]);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let plane = array(
+            &[2, 5],
+            vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 100.0],
+            "",
+        );
+        let outputs = |image: &WcsArray, roi: &ROI| {
+            run_statistics(image, roi, 2.0, 5)
+                .into_iter()
+                .map(|output| match output.unwrap() {
+                    IOValue::Quantity(q) => *q.scalar(),
+                    IOValue::Integer(count) => count as f32,
+                    _ => panic!("Expected quantity or integer"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Mean, median, std, MAD, clipped mean, median and std, and count.
+        // The outlier is clipped.
+        let std = 818.25f32.sqrt();
+        let clipped_std = (20.0f32 / 3.0).sqrt();
+        let expected = [14.5, 5.5, std, 2.5, 5.0, 5.0, clipped_std, 10.0];
+        assert_close(&outputs(&plane, &ROI::All), &expected, 1e-6);
+
+        let roi = ROI::PixelList(vec![(0, 0), (1, 4)]);
+        assert_eq!(outputs(&plane, &roi)[0], 50.5);
+        let mut mask = ArrayD::from_elem(vec![2, 5], false);
+        mask[[1, 4]] = true;
+        let masked = plane.clone().with_mask(Some(mask));
+        assert_eq!(outputs(&masked, &ROI::All)[0], 5.0);
+        assert!(run_statistics(&plane, &ROI::All, 0.0, 5)[0].is_err());
+
+        let cube = LazyWcsArray::from(array(
+            &[4, 1, 2],
+            vec![1.0, 4.0, 2.0, 5.0, 30.0, 6.0, 100.0, 100.0],
+            "",
+        ));
+        let map = image(run_statistics_map(&cube, 0, 3, "median", 3.0, 5));
+        assert_close(&values(&map), &[2.0, 5.0], 1e-6);
+        assert!(run_statistics_map(&cube, 0, 3, "mode", 3.0, 5).is_err());
+    }
+
+    #[test]
+    fn test_spectral_axis() {
+        let mut hdu = Hdu::new(&[2, 1, 4], vec![0.0; 8]);
+        hdu.insert("CTYPE3", "AWAV");
+        hdu.insert("CUNIT3", "nm");
+        hdu.insert("CRPIX3", 1.0);
+        hdu.insert("CRVAL3", 500.0);
+        hdu.insert("CDELT3", 100.0);
+        let spectra: Vec<_> = (0..8).map(|v| v as f32).collect();
+        let cube = LazyWcsArray::from(WcsArray::from_hdu_and_image(
+            &hdu,
+            ArrayD::from_shape_vec(vec![4, 1, 2], spectra.clone()).unwrap(),
+        ));
+        let wavelengths = |image: &WcsArray, pixels: &[f32]| -> Vec<f32> {
+            pixels
+                .iter()
+                .map(|&pixel| image.pix2world(2, pixel).unwrap())
+                .collect()
+        };
+
+        let rest = image(run_redshift_correction(&cube, 1.0));
+        assert_close(&wavelengths(&rest, &[0.0, 1.0]), &[250.0, 300.0], 1e-6);
+        assert_eq!(values(&rest), spectra);
+        assert!(run_redshift_correction(&cube, -1.0).is_err());
+        let frame = LazyWcsArray::from(image(run_slice_one_frame(&cube, 0)));
+        assert!(run_redshift_correction(&frame, 1.0).is_err());
+
+        let vacuum = match run_air_vacuum_conversion(&cube, true).unwrap() {
+            IOValue::LazyImage(vacuum) => vacuum,
+            _ => panic!("Expected image"),
+        };
+        let loaded = vacuum.load();
+        assert_eq!(loaded.axes().unwrap()[2].name(), "WAVE");
+        let expected: Vec<_> = [5000.0, 8000.0]
+            .iter()
+            .map(|&air| (spectral::air_to_vacuum(air) / 10.0) as f32)
+            .collect();
+        assert_close(&wavelengths(&loaded, &[0.0, 3.0]), &expected, 1e-6);
+        let air = image(run_air_vacuum_conversion(&vacuum, false));
+        assert_eq!(air.axes().unwrap()[2].name(), "AWAV");
+        let expected = [500.0, 600.0, 700.0, 800.0];
+        assert_close(&wavelengths(&air, &[0.0, 1.0, 2.0, 3.0]), &expected, 1e-6);
+    }
+
+    #[test]
+    fn test_reduce() {
+        // FITS axes 0, 1 and 2 have 3, 2 and 2 pixels
+        let mut data: Vec<_> = (0..12).map(|v| v as f32).collect();
+        data[0] = ::std::f32::NAN;
+        let mut mask = ArrayD::from_elem(vec![2, 2, 3], false);
+        mask[[1, 1, 2]] = true;
+        let cube = array(&[2, 2, 3], data, "")
+            .with_variance(Some(ArrayD::from_elem(vec![2, 2, 3], 1.0)))
+            .with_mask(Some(mask));
+        let cube = LazyWcsArray::from(cube);
+        let reduce = |axis, kind| image(run_reduce(&cube, axis, kind));
+        let variance =
+            |image: &WcsArray| -> Vec<f32> { image.variance().unwrap().iter().cloned().collect() };
+
+        let sum = reduce(0, "sum");
+        assert_eq!(sum.shape(), &[2, 2]);
+        assert_close(&values(&sum), &[3.0, 12.0, 21.0, 19.0], 1e-6);
+        assert_close(&variance(&sum), &[2.0, 3.0, 3.0, 2.0], 1e-6);
+        let mean = reduce(2, "mean");
+        assert_eq!(mean.shape(), &[2, 3]);
+        assert_close(&values(&mean), &[6.0, 4.0, 5.0, 6.0, 7.0, 5.0], 1e-6);
+        assert_close(&variance(&mean)[1..2], &[0.5], 1e-6);
+        let median = reduce(1, "median");
+        assert_close(&values(&median), &[3.0, 2.5, 3.5, 7.5, 8.5, 8.0], 1e-6);
+        let min = reduce(1, "min");
+        assert_close(&values(&min), &[3.0, 1.0, 2.0, 6.0, 7.0, 8.0], 1e-6);
+        let max = reduce(1, "max");
+        assert_close(&values(&max), &[3.0, 4.0, 5.0, 9.0, 10.0, 8.0], 1e-6);
+        let std = reduce(2, "std");
+        assert_close(&values(&std)[1..2], &[3.0], 1e-6);
+        assert!(std.variance().is_none());
+        assert_eq!(values(&reduce(0, "argmax")), vec![2.0, 2.0, 2.0, 1.0]);
+
+        assert!(run_reduce(&cube, 3, "sum").is_err());
+        assert!(run_reduce(&cube, 0, "mode").is_err());
+    }
+
+    #[test]
+    fn test_fits_integer_images() {
+        let mut file = header_block(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                   16",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    3",
+            "NAXIS2  =                    2",
+            "BSCALE  =                  0.5",
+            "BZERO   =                32768",
+            "BLANK   =                   -1",
+            "BUNIT   = 'Jy      '",
+        ]);
+        let mut data = Vec::new();
+        for v in &[-32768i16, 0, 2, -1, 100, -100] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(2880, 0);
+        file.extend(data);
+
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                    8",
+            "NAXIS   =                    1",
+            "NAXIS1  =                    3",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "BSCALE  =                  2.0",
+            "BZERO   =                 -1.0",
+            "BLANK   =                  255",
+            "EXTNAME = 'BYTES   '",
+        ]));
+        let mut data = vec![0u8, 200, 255];
+        data.resize(2880, 0);
+        file.extend(data);
+
+        file.extend(header_block(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                   64",
+            "NAXIS   =                    1",
+            "NAXIS1  =                    2",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+        ]));
+        let mut data = Vec::new();
+        for v in &[-5_000_000_000i64, 7] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(2880, 0);
+        file.extend(data);
+
+        let path = ::std::env::temp_dir().join("aflak_test_fits_integer_images.fits");
+        ::std::fs::write(&path, &file).unwrap();
+        let fits = match run_open_fits(&path).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+
+        let plane = image(run_fits_to_image(&fits, 0, "", "", ""));
+        assert_eq!(plane.scalar().shape(), &[2, 3]);
+        assert_eq!(plane.array().unit(), &Unit::parse("Jy"));
+        let nan = ::std::f32::NAN;
+        let expected = [16384.0, 32768.0, 32769.0, nan, 32818.0, 32718.0];
+        assert_close(&values(&plane), &expected, 1e-6);
+
+        let bytes = image(run_fits_to_image(&fits, 0, "BYTES", "", ""));
+        assert_eq!(bytes.array().unit(), &Unit::None);
+        assert_close(&values(&bytes), &[-1.0, 399.0, nan], 1e-6);
+
+        let longs = image(run_fits_to_image(&fits, 2, "", "", ""));
+        assert_close(&values(&longs), &[-5e9, 7.0], 1e-6);
+    }
 }
@@ -0,0 +1,55 @@
+//! Small dense linear algebra routines used to fit models.
+
+/// Solve linear system `a * x = b` with Gaussian elimination with partial
+/// pivoting. Return `None` if `a` is singular.
+pub fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| {
+                a[i][col]
+                    .abs()
+                    .partial_cmp(&a[j][col].abs())
+                    .unwrap_or(::std::cmp::Ordering::Equal)
+            })
+            .expect("Non-empty range");
+        let p = a[pivot][col].abs();
+        if p.is_nan() || p <= 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (top, bottom) = a.split_at_mut(col + 1);
+        let (b_top, b_bottom) = b.split_at_mut(col + 1);
+        for (row, b_row) in bottom.iter_mut().zip(b_bottom) {
+            let m = row[col] / top[col][col];
+            for (v, p) in row[col..].iter_mut().zip(&top[col][col..]) {
+                *v -= m * p;
+            }
+            *b_row -= m * b_top[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Invert matrix `a`. Return `None` if `a` is singular.
+pub fn invert(a: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let n = a.len();
+    let mut columns = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut e = vec![0.0; n];
+        e[i] = 1.0;
+        columns.push(solve(a.clone(), e)?);
+    }
+    // Transpose columns into rows
+    Some(
+        (0..n)
+            .map(|i| columns.iter().map(|column| column[i]).collect())
+            .collect(),
+    )
+}
@@ -0,0 +1,308 @@
+//! Fit Gaussian profiles on emission lines.
+//!
+//! Profiles are fitted on samples `(x, y)` of a spectrum with the
+//! Levenberg-Marquardt method.
+use linalg;
+
+/// Maximum number of iterations of the Levenberg-Marquardt method
+const MAX_ITERATIONS: usize = 200;
+
+/// A Gaussian profile.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gaussian {
+    pub amplitude: f64,
+    pub center: f64,
+    pub sigma: f64,
+}
+
+impl Gaussian {
+    /// Evaluate profile at `x`.
+    pub fn eval(&self, x: f64) -> f64 {
+        let t = (x - self.center) / self.sigma;
+        self.amplitude * (-0.5 * t * t).exp()
+    }
+}
+
+/// Best-fit Gaussian components and their standard errors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineFit {
+    /// Components sorted by increasing center
+    pub components: Vec<Gaussian>,
+    /// Standard error on the parameters of each component
+    pub errors: Vec<Gaussian>,
+    /// Covariance between amplitude and sigma of each component
+    pub amplitude_sigma_covariance: Vec<f64>,
+}
+
+/// Fit `count` Gaussian components on `samples`. `samples` must be sorted by
+/// increasing `x` and must not contain NaN.
+///
+/// Errors are estimated from the covariance matrix of the fit, scaled by the
+/// reduced chi-square.
+///
+/// Return `None` if there are not enough samples, if no emission line is
+/// found or if the fit does not converge.
+pub fn fit_gaussians(samples: &[(f64, f64)], count: usize) -> Option<LineFit> {
+    let n_params = 3 * count;
+    if count == 0 || samples.len() <= n_params {
+        return None;
+    }
+    let mut params = initial_guess(samples, count)?;
+    let mut chi2 = chi_square(samples, &params);
+    let mut lambda = 1e-3;
+    for _ in 0..MAX_ITERATIONS {
+        let (mut jtj, jtr) = normal_equations(samples, &params);
+        for (i, row) in jtj.iter_mut().enumerate() {
+            row[i] *= 1.0 + lambda;
+        }
+        let step = match linalg::solve(jtj, jtr) {
+            Some(step) => step,
+            None => {
+                lambda *= 10.0;
+                continue;
+            }
+        };
+        let candidate: Vec<_> = params.iter().zip(&step).map(|(p, d)| p + d).collect();
+        let new_chi2 = chi_square(samples, &candidate);
+        if new_chi2 < chi2 {
+            let converged = chi2 - new_chi2 <= 1e-10 * chi2;
+            params = candidate;
+            chi2 = new_chi2;
+            lambda = (lambda / 10.0).max(1e-12);
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.0;
+            if lambda > 1e10 {
+                break;
+            }
+        }
+    }
+
+    let (jtj, _) = normal_equations(samples, &params);
+    let covariance = linalg::invert(jtj)?;
+    let scale = chi2 / (samples.len() - n_params) as f64;
+
+    let mut fits: Vec<_> = (0..count)
+        .map(|k| {
+            let i = 3 * k;
+            let component = Gaussian {
+                amplitude: params[i],
+                center: params[i + 1],
+                sigma: params[i + 2].abs(),
+            };
+            let error = Gaussian {
+                amplitude: (covariance[i][i] * scale).sqrt(),
+                center: (covariance[i + 1][i + 1] * scale).sqrt(),
+                sigma: (covariance[i + 2][i + 2] * scale).sqrt(),
+            };
+            // Sign of sigma was dropped
+            let cov = covariance[i][i + 2] * scale * params[i + 2].signum();
+            (component, error, cov)
+        })
+        .collect();
+    let is_valid = |&(c, e, _): &(Gaussian, Gaussian, f64)| {
+        c.amplitude.is_finite() && c.center.is_finite() && c.sigma > 0.0 && e.sigma.is_finite()
+    };
+    if !fits.iter().all(is_valid) {
+        return None;
+    }
+    fits.sort_by(|a, b| {
+        a.0.center
+            .partial_cmp(&b.0.center)
+            .expect("Centers are finite")
+    });
+    Some(LineFit {
+        components: fits.iter().map(|f| f.0).collect(),
+        errors: fits.iter().map(|f| f.1).collect(),
+        amplitude_sigma_covariance: fits.iter().map(|f| f.2).collect(),
+    })
+}
+
+/// Guess parameters of `count` components. Each component is centered on the
+/// maximum of the residuals left by the previous components, with a width
+/// estimated from the half maximum.
+fn initial_guess(samples: &[(f64, f64)], count: usize) -> Option<Vec<f64>> {
+    let mut residuals: Vec<_> = samples.iter().map(|&(_, y)| y).collect();
+    let spacing = (samples[samples.len() - 1].0 - samples[0].0) / (samples.len() - 1) as f64;
+    let mut params = Vec::with_capacity(3 * count);
+    for _ in 0..count {
+        let (peak, amplitude) = residuals.iter().cloned().enumerate().fold(
+            (0, ::std::f64::NEG_INFINITY),
+            |max, (i, y)| {
+                if y > max.1 {
+                    (i, y)
+                } else {
+                    max
+                }
+            },
+        );
+        if amplitude <= 0.0 {
+            return None;
+        }
+        let half = amplitude / 2.0;
+        let left = (0..peak).rev().find(|&i| residuals[i] < half).unwrap_or(0);
+        let right = (peak + 1..samples.len())
+            .find(|&i| residuals[i] < half)
+            .unwrap_or(samples.len() - 1);
+        // Half width at half maximum is sqrt(2 ln 2) sigma
+        let hwhm = (samples[right].0 - samples[left].0) / 2.0;
+        let sigma = (hwhm / 1.177_410_022_515_474_7).max(spacing / 2.0);
+        let component = Gaussian {
+            amplitude,
+            center: samples[peak].0,
+            sigma,
+        };
+        for (r, &(x, _)) in residuals.iter_mut().zip(samples) {
+            *r -= component.eval(x);
+        }
+        params.extend_from_slice(&[amplitude, component.center, sigma]);
+    }
+    Some(params)
+}
+
+/// Evaluate sum of components defined by `params` at `x`.
+fn model(params: &[f64], x: f64) -> f64 {
+    params
+        .chunks(3)
+        .map(|p| {
+            Gaussian {
+                amplitude: p[0],
+                center: p[1],
+                sigma: p[2],
+            }
+            .eval(x)
+        })
+        .sum()
+}
+
+fn chi_square(samples: &[(f64, f64)], params: &[f64]) -> f64 {
+    samples
+        .iter()
+        .map(|&(x, y)| {
+            let r = y - model(params, x);
+            r * r
+        })
+        .sum()
+}
+
+/// Compute `J^T J` and `J^T r`, where `J` is the jacobian of the model and
+/// `r` the residuals.
+fn normal_equations(samples: &[(f64, f64)], params: &[f64]) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let n = params.len();
+    let mut jtj = vec![vec![0.0; n]; n];
+    let mut jtr = vec![0.0; n];
+    let mut jacobian = vec![0.0; n];
+    for &(x, y) in samples {
+        for (j, p) in jacobian.chunks_mut(3).zip(params.chunks(3)) {
+            let (amplitude, center, sigma) = (p[0], p[1], p[2]);
+            let t = (x - center) / sigma;
+            let e = (-0.5 * t * t).exp();
+            j[0] = e;
+            j[1] = amplitude * e * t / sigma;
+            j[2] = amplitude * e * t * t / sigma;
+        }
+        let r = y - model(params, x);
+        for (i, row) in jtj.iter_mut().enumerate() {
+            for (k, v) in row.iter_mut().enumerate() {
+                *v += jacobian[i] * jacobian[k];
+            }
+            jtr[i] += jacobian[i] * r;
+        }
+    }
+    (jtj, jtr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fit_gaussians, initial_guess, Gaussian};
+    use test_util::assert_close;
+
+    fn samples(components: &[Gaussian], noise: f64) -> Vec<(f64, f64)> {
+        (0..40)
+            .map(|i| {
+                let x = 0.5 * i as f64;
+                let y: f64 = components.iter().map(|c| c.eval(x)).sum();
+                // Deterministic noise
+                let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+                (x, y + sign * noise)
+            })
+            .collect()
+    }
+
+    fn params(g: Gaussian) -> [f64; 3] {
+        [g.amplitude, g.center, g.sigma]
+    }
+
+    const LINE: Gaussian = Gaussian {
+        amplitude: 4.0,
+        center: 6.2,
+        sigma: 1.3,
+    };
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(LINE.eval(6.2), 4.0);
+        let at_sigma = 4.0 * (-0.5f64).exp();
+        assert_close(&[LINE.eval(4.9), LINE.eval(7.5)], &[at_sigma; 2], 1e-12);
+    }
+
+    #[test]
+    fn test_initial_guess() {
+        let params = initial_guess(&samples(&[LINE], 0.0), 1).unwrap();
+        // Centered on the highest sample
+        assert_eq!(params[1], 6.0);
+        assert_close(&params[..1], &[LINE.eval(6.0)], 1e-12);
+        assert!(params[2] > 0.5 && params[2] < 2.0);
+
+        assert_eq!(initial_guess(&samples(&[], 0.0), 1), None);
+    }
+
+    #[test]
+    fn test_fit_one_line() {
+        let fit = fit_gaussians(&samples(&[LINE], 0.0), 1).unwrap();
+        assert_eq!(fit.components.len(), 1);
+        assert_close(&params(fit.components[0]), &params(LINE), 1e-6);
+        // Perfect fit
+        assert!(fit.errors[0].amplitude < 1e-6);
+        assert!(fit.errors[0].sigma < 1e-6);
+    }
+
+    #[test]
+    fn test_fit_two_lines() {
+        let faint = Gaussian {
+            amplitude: 1.5,
+            center: 14.8,
+            sigma: 0.9,
+        };
+        // Components are sorted by center
+        let fit = fit_gaussians(&samples(&[faint, LINE], 0.0), 2).unwrap();
+        assert_close(&params(fit.components[0]), &params(LINE), 1e-6);
+        assert_close(&params(fit.components[1]), &params(faint), 1e-6);
+        assert_eq!(fit.errors.len(), 2);
+        assert_eq!(fit.amplitude_sigma_covariance.len(), 2);
+    }
+
+    #[test]
+    fn test_fit_errors() {
+        let fit = fit_gaussians(&samples(&[LINE], 0.05), 1).unwrap();
+        let (line, error) = (fit.components[0], fit.errors[0]);
+        assert_close(&params(line), &params(LINE), 1e-2);
+        for &e in &[error.amplitude, error.center, error.sigma] {
+            assert!(e > 0.0 && e < 0.1, "Unexpected error {:?}", error);
+        }
+        // Amplitude and sigma are anti-correlated
+        assert!(fit.amplitude_sigma_covariance[0] < 0.0);
+    }
+
+    #[test]
+    fn test_cannot_fit() {
+        let line = samples(&[LINE], 0.0);
+        assert_eq!(fit_gaussians(&line, 0), None);
+        assert_eq!(fit_gaussians(&line[..3], 1), None);
+        // No emission line
+        let absorption: Vec<_> = line.iter().map(|&(x, y)| (x, -y)).collect();
+        assert_eq!(fit_gaussians(&absorption, 1), None);
+    }
+}
@@ -0,0 +1,681 @@
+use std::error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::ops;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+/// Exponents of the SI base dimensions (m, kg, s, A, K, mol, cd), plus the
+/// radian as an additional base dimension to keep track of angles.
+type Dimension = [i32; 8];
+
+const DIMENSIONLESS: Dimension = [0, 0, 0, 0, 0, 0, 0, 0];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0, 0];
+const LUMINOUS_INTENSITY: Dimension = [0, 0, 0, 0, 0, 0, 1, 0];
+const ANGLE: Dimension = [0, 0, 0, 0, 0, 0, 0, 1];
+const SOLID_ANGLE: Dimension = [0, 0, 0, 0, 0, 0, 0, 2];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0, 0];
+const SPECTRAL_FLUX_DENSITY: Dimension = [0, 1, -2, 0, 0, 0, 0, 0];
+const MAGNETIC_FIELD: Dimension = [0, 1, -2, -1, 0, 0, 0, 0];
+
+/// Known unit symbols, with their value in SI base units, their dimension
+/// and whether they accept SI prefixes.
+const UNITS: &[(&str, f64, Dimension, bool)] = &[
+    ("m", 1.0, LENGTH, true),
+    ("g", 1e-3, MASS, true),
+    ("s", 1.0, TIME, true),
+    ("A", 1.0, CURRENT, true),
+    ("K", 1.0, TEMPERATURE, true),
+    ("mol", 1.0, AMOUNT, true),
+    ("cd", 1.0, LUMINOUS_INTENSITY, true),
+    ("rad", 1.0, ANGLE, true),
+    ("sr", 1.0, SOLID_ANGLE, false),
+    ("deg", PI / 180.0, ANGLE, false),
+    ("arcmin", PI / 10_800.0, ANGLE, false),
+    ("arcsec", PI / 648_000.0, ANGLE, false),
+    ("mas", PI / 648_000_000.0, ANGLE, false),
+    ("Hz", 1.0, FREQUENCY, true),
+    ("N", 1.0, FORCE, true),
+    ("J", 1.0, ENERGY, true),
+    ("W", 1.0, POWER, true),
+    ("erg", 1e-7, ENERGY, false),
+    ("eV", 1.602_176_634e-19, ENERGY, true),
+    ("Jy", 1e-26, SPECTRAL_FLUX_DENSITY, true),
+    ("T", 1.0, MAGNETIC_FIELD, true),
+    ("G", 1e-4, MAGNETIC_FIELD, false),
+    ("Angstrom", 1e-10, LENGTH, false),
+    ("angstrom", 1e-10, LENGTH, false),
+    ("Ang", 1e-10, LENGTH, false),
+    ("AA", 1e-10, LENGTH, false),
+    ("\u{c5}", 1e-10, LENGTH, false),
+    ("\u{212b}", 1e-10, LENGTH, false),
+    ("AU", 1.495_978_707e11, LENGTH, false),
+    ("au", 1.495_978_707e11, LENGTH, false),
+    ("pc", 3.085_677_581_491_367e16, LENGTH, true),
+    ("lyr", 9.460_730_472_580_8e15, LENGTH, false),
+    ("solRad", 6.957e8, LENGTH, false),
+    ("Rsun", 6.957e8, LENGTH, false),
+    ("solMass", 1.988_47e30, MASS, false),
+    ("Msun", 1.988_47e30, MASS, false),
+    ("solLum", 3.828e26, POWER, false),
+    ("Lsun", 3.828e26, POWER, false),
+    ("min", 60.0, TIME, false),
+    ("h", 3600.0, TIME, false),
+    ("d", 86_400.0, TIME, false),
+    ("yr", 31_557_600.0, TIME, true),
+    ("a", 31_557_600.0, TIME, false),
+    ("count", 1.0, DIMENSIONLESS, false),
+    ("counts", 1.0, DIMENSIONLESS, false),
+    ("ct", 1.0, DIMENSIONLESS, false),
+    ("photon", 1.0, DIMENSIONLESS, false),
+    ("ph", 1.0, DIMENSIONLESS, false),
+    ("electron", 1.0, DIMENSIONLESS, false),
+    ("adu", 1.0, DIMENSIONLESS, false),
+    ("ADU", 1.0, DIMENSIONLESS, false),
+    ("pix", 1.0, DIMENSIONLESS, false),
+    ("pixel", 1.0, DIMENSIONLESS, false),
+    ("spaxel", 1.0, DIMENSIONLESS, false),
+    ("beam", 1.0, DIMENSIONLESS, false),
+];
+
+/// SI prefixes. "da" must be tried before "d".
+const PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1),
+    ("y", 1e-24),
+    ("z", 1e-21),
+    ("a", 1e-18),
+    ("f", 1e-15),
+    ("p", 1e-12),
+    ("n", 1e-9),
+    ("u", 1e-6),
+    ("\u{b5}", 1e-6),
+    ("\u{3bc}", 1e-6),
+    ("m", 1e-3),
+    ("c", 1e-2),
+    ("d", 1e-1),
+    ("h", 1e2),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+    ("T", 1e12),
+    ("P", 1e15),
+    ("E", 1e18),
+    ("Z", 1e21),
+    ("Y", 1e24),
+];
+
+/// Find the value in SI base units and dimension of a unit symbol,
+/// optionally prefixed (e.g. "km" or "mJy").
+fn lookup(symbol: &str) -> Option<(f64, Dimension)> {
+    for &(name, scale, dimension, _) in UNITS {
+        if name == symbol {
+            return Some((scale, dimension));
+        }
+    }
+    for &(prefix, factor) in PREFIXES {
+        if symbol.len() > prefix.len() && symbol.starts_with(prefix) {
+            let rest = &symbol[prefix.len()..];
+            for &(name, scale, dimension, prefixable) in UNITS {
+                if prefixable && name == rest {
+                    return Some((factor * scale, dimension));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A physical unit, e.g. `km/s` or `1e-17 erg/s/cm2/Angstrom`.
+///
+/// Physical units are parsed from strings following the FITS standard (as
+/// found in BUNIT and CUNITn keywords) and the usual conventions in
+/// astrophysics. They can be multiplied, divided and converted into one
+/// another if their dimensions match.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate aflak_primitives as primitives;
+/// use primitives::PhysicalUnit;
+///
+/// let angstrom: PhysicalUnit = "Angstrom".parse().unwrap();
+/// let nm: PhysicalUnit = "nm".parse().unwrap();
+/// assert!((angstrom.conversion_factor(&nm).unwrap() - 0.1).abs() < 1e-12);
+///
+/// let velocity: PhysicalUnit = "km s-1".parse().unwrap();
+/// assert_eq!(velocity.repr(), "km/s");
+/// ```
+#[derive(Clone, Debug)]
+pub struct PhysicalUnit {
+    /// Numerical factor in front of the unit, e.g. 1e-17
+    multiplier: f64,
+    /// Unit symbols with their exponent, in order of appearance
+    factors: Vec<(String, i32)>,
+    /// Value of this unit in SI base units
+    scale: f64,
+    dimension: Dimension,
+    repr: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseUnitError(String);
+
+impl PhysicalUnit {
+    /// Unit of a dimensionless quantity.
+    pub fn dimensionless() -> Self {
+        Self::from_factors(1.0, vec![]).expect("Dimensionless unit is valid")
+    }
+
+    fn from_factors(multiplier: f64, factors: Vec<(String, i32)>) -> Result<Self, ParseUnitError> {
+        // Merge identical symbols, keeping the order of first appearance
+        let mut merged: Vec<(String, i32)> = Vec::with_capacity(factors.len());
+        for (symbol, exp) in factors {
+            if let Some(existing) = merged.iter_mut().find(|(s, _)| *s == symbol) {
+                existing.1 += exp;
+                continue;
+            }
+            merged.push((symbol, exp));
+        }
+        merged.retain(|(_, exp)| *exp != 0);
+
+        let mut scale = multiplier;
+        let mut dimension = DIMENSIONLESS;
+        for (symbol, exp) in &merged {
+            let (symbol_scale, symbol_dimension) = lookup(symbol)
+                .ok_or_else(|| ParseUnitError(format!("Unknown unit '{}'", symbol)))?;
+            scale *= symbol_scale.powi(*exp);
+            for (d, sd) in dimension.iter_mut().zip(&symbol_dimension) {
+                *d += sd * exp;
+            }
+        }
+        let repr = format_unit(multiplier, &merged);
+        Ok(Self {
+            multiplier,
+            factors: merged,
+            scale,
+            dimension,
+            repr,
+        })
+    }
+
+    /// String representation of the unit.
+    pub fn repr(&self) -> &str {
+        &self.repr
+    }
+
+    /// Check if unit is dimensionless.
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension == DIMENSIONLESS
+    }
+
+    /// Check if a value in this unit can be converted to unit `other`.
+    pub fn is_compatible(&self, other: &PhysicalUnit) -> bool {
+        self.dimension == other.dimension
+    }
+
+    /// Get the factor by which a value in this unit must be multiplied to be
+    /// expressed in unit `to`. Return `None` if dimensions do not match.
+    pub fn conversion_factor(&self, to: &PhysicalUnit) -> Option<f64> {
+        if self.is_compatible(to) {
+            Some(self.scale / to.scale)
+        } else {
+            None
+        }
+    }
+
+    /// Raise unit to the integer power `n`.
+    pub fn powi(&self, n: i32) -> Self {
+        let factors = self
+            .factors
+            .iter()
+            .map(|(symbol, exp)| (symbol.clone(), exp * n))
+            .collect();
+        Self::from_factors(self.multiplier.powi(n), factors).expect("Symbols were already checked")
+    }
+
+    /// Take the `n`-th root of unit. Return `None` if an exponent of the unit
+    /// is not a multiple of `n`.
+    pub fn root(&self, n: i32) -> Option<Self> {
+        if self.factors.iter().any(|(_, exp)| exp % n != 0) {
+            return None;
+        }
+        let factors = self
+            .factors
+            .iter()
+            .map(|(symbol, exp)| (symbol.clone(), exp / n))
+            .collect();
+        let multiplier = self.multiplier.powf(1.0 / f64::from(n));
+        Some(Self::from_factors(multiplier, factors).expect("Symbols were already checked"))
+    }
+}
+
+fn is_one(x: f64) -> bool {
+    (x - 1.0).abs() <= ::std::f64::EPSILON
+}
+
+/// Format unit as a string that can be parsed back, e.g.
+/// `1e-17 erg/s/cm2/Angstrom`.
+fn format_unit(multiplier: f64, factors: &[(String, i32)]) -> String {
+    let numerator: Vec<_> = factors.iter().filter(|(_, exp)| *exp > 0).collect();
+    let denominator: Vec<_> = factors.iter().filter(|(_, exp)| *exp < 0).collect();
+
+    let mut repr = String::new();
+    if numerator.is_empty() {
+        let terms: Vec<_> = denominator
+            .iter()
+            .map(|(symbol, exp)| format!("{}{}", symbol, exp))
+            .collect();
+        repr.push_str(&terms.join(" "));
+    } else {
+        let terms: Vec<_> = numerator
+            .iter()
+            .map(|(symbol, exp)| {
+                if *exp == 1 {
+                    symbol.clone()
+                } else {
+                    format!("{}{}", symbol, exp)
+                }
+            })
+            .collect();
+        repr.push_str(&terms.join(" "));
+        for (symbol, exp) in denominator {
+            repr.push('/');
+            repr.push_str(symbol);
+            if *exp != -1 {
+                repr.push_str(&(-exp).to_string());
+            }
+        }
+    }
+
+    if is_one(multiplier) {
+        repr
+    } else if repr.is_empty() {
+        format!("{:e}", multiplier)
+    } else {
+        format!("{:e} {}", multiplier, repr)
+    }
+}
+
+impl FromStr for PhysicalUnit {
+    type Err = ParseUnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        parser.skip_spaces();
+        let multiplier = parser.parse_multiplier()?;
+        parser.skip_spaces();
+        let mut factors = vec![];
+        if parser.peek().is_some() {
+            parser.parse_product(&mut factors)?;
+        }
+        if let Some(c) = parser.peek() {
+            return Err(ParseUnitError(format!(
+                "Unexpected character '{}' in unit '{}'",
+                c, s
+            )));
+        }
+        Self::from_factors(multiplier, factors)
+    }
+}
+
+/// Recursive descent parser for units.
+///
+/// Terms are separated with spaces, '.' or '*' for a multiplication and '/'
+/// for a division, e.g. `erg/s/cm2`, `erg.s-1.cm-2` or `erg s**-1 cm^-2`.
+/// Parentheses group terms, e.g. `erg/(s cm2)`.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_str(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        self.pos != start
+    }
+
+    fn error(&self, msg: &str) -> ParseUnitError {
+        ParseUnitError(format!(
+            "{} at position {} in unit '{}'",
+            msg, self.pos, self.input
+        ))
+    }
+
+    /// Parse optional numerical factor, e.g. `1e-17` or `10**-17`.
+    fn parse_multiplier(&mut self) -> Result<f64, ParseUnitError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == '.' {
+                self.bump();
+            } else if (c == 'e' || c == 'E') && self.pos != start {
+                // Exponent of a number in scientific notation
+                let rest = &self.input[self.pos + 1..];
+                let rest = rest.trim_start_matches(|c| c == '+' || c == '-');
+                if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                    self.bump();
+                    if let Some('+') | Some('-') = self.peek() {
+                        self.bump();
+                    }
+                } else {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            return Ok(1.0);
+        }
+        let number: f64 = self.input[start..self.pos]
+            .parse()
+            .map_err(|_| self.error("Invalid number"))?;
+        if self.peek_str("**") || self.peek_str("^") {
+            let exp = self.parse_exponent()?.unwrap_or(1);
+            Ok(number.powi(exp))
+        } else {
+            Ok(number)
+        }
+    }
+
+    /// Parse terms until the end of the input or a closing parenthesis.
+    fn parse_product(&mut self, factors: &mut Vec<(String, i32)>) -> Result<(), ParseUnitError> {
+        let mut sign = 1;
+        if self.peek() == Some('/') {
+            self.bump();
+            self.skip_spaces();
+            sign = -1;
+        }
+        loop {
+            self.parse_term(factors, sign)?;
+            let spaces = self.skip_spaces();
+            match self.peek() {
+                None | Some(')') => return Ok(()),
+                Some('/') => sign = -1,
+                Some('.') | Some('*') => sign = 1,
+                Some(_) if spaces => {
+                    sign = 1;
+                    continue;
+                }
+                Some(_) => return Err(self.error("Unexpected character")),
+            }
+            self.bump();
+            self.skip_spaces();
+        }
+    }
+
+    fn parse_term(
+        &mut self,
+        factors: &mut Vec<(String, i32)>,
+        sign: i32,
+    ) -> Result<(), ParseUnitError> {
+        if self.peek() == Some('(') {
+            self.bump();
+            self.skip_spaces();
+            let mut inner = vec![];
+            self.parse_product(&mut inner)?;
+            if self.bump() != Some(')') {
+                return Err(self.error("Expected ')'"));
+            }
+            let exp = self.parse_exponent()?.unwrap_or(1);
+            for (symbol, inner_exp) in inner {
+                factors.push((symbol, inner_exp * exp * sign));
+            }
+        } else {
+            let start = self.pos;
+            while let Some(c) = self.peek() {
+                if c.is_alphabetic() {
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+            if self.pos == start {
+                return Err(self.error("Expected unit"));
+            }
+            let symbol = &self.input[start..self.pos];
+            if lookup(symbol).is_none() {
+                return Err(ParseUnitError(format!("Unknown unit '{}'", symbol)));
+            }
+            let exp = self.parse_exponent()?.unwrap_or(1);
+            factors.push((symbol.to_owned(), exp * sign));
+        }
+        Ok(())
+    }
+
+    /// Parse optional integer exponent, e.g. `2`, `-1`, `**-1`, `^(-1)`.
+    fn parse_exponent(&mut self) -> Result<Option<i32>, ParseUnitError> {
+        let has_operator = if self.peek_str("**") {
+            self.pos += 2;
+            true
+        } else if self.peek_str("^") {
+            self.pos += 1;
+            true
+        } else {
+            false
+        };
+        let parenthesized = self.peek() == Some('(');
+        if parenthesized {
+            if !has_operator {
+                // Parentheses start a new term
+                return Ok(None);
+            }
+            self.bump();
+        }
+
+        let start = self.pos;
+        if let Some('+') | Some('-') = self.peek() {
+            self.bump();
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        let exp = &self.input[start..self.pos];
+        if exp.is_empty() && !has_operator {
+            return Ok(None);
+        }
+        let exp = exp.parse().map_err(|_| self.error("Invalid exponent"))?;
+
+        if parenthesized && self.bump() != Some(')') {
+            return Err(self.error("Expected ')'"));
+        }
+        Ok(Some(exp))
+    }
+}
+
+impl PartialEq for PhysicalUnit {
+    fn eq(&self, other: &Self) -> bool {
+        self.repr == other.repr
+    }
+}
+
+impl Eq for PhysicalUnit {}
+
+impl<'a, 'b> ops::Mul<&'b PhysicalUnit> for &'a PhysicalUnit {
+    type Output = PhysicalUnit;
+
+    fn mul(self, rhs: &'b PhysicalUnit) -> PhysicalUnit {
+        let factors = self.factors.iter().chain(&rhs.factors).cloned().collect();
+        PhysicalUnit::from_factors(self.multiplier * rhs.multiplier, factors)
+            .expect("Symbols were already checked")
+    }
+}
+
+impl<'a, 'b> ops::Div<&'b PhysicalUnit> for &'a PhysicalUnit {
+    type Output = PhysicalUnit;
+
+    fn div(self, rhs: &'b PhysicalUnit) -> PhysicalUnit {
+        self * &rhs.powi(-1)
+    }
+}
+
+impl fmt::Display for PhysicalUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.repr)
+    }
+}
+
+impl fmt::Display for ParseUnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for ParseUnitError {
+    fn description(&self) -> &str {
+        "ParseUnitError"
+    }
+}
+
+/// Physical units are serialized as strings, e.g. `"km/s"`.
+impl Serialize for PhysicalUnit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.repr)
+    }
+}
+
+impl<'de> Deserialize<'de> for PhysicalUnit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let repr = String::deserialize(deserializer)?;
+        repr.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ParseUnitError, PhysicalUnit};
+    use test_util::assert_close;
+
+    fn unit(s: &str) -> PhysicalUnit {
+        s.parse().unwrap()
+    }
+
+    fn assert_factor(from: &str, to: &str, expected: f64) {
+        let factor = unit(from).conversion_factor(&unit(to)).unwrap();
+        assert_close(&[factor], &[expected], 1e-9);
+    }
+
+    #[test]
+    fn test_parse() {
+        let flux = unit("1E-17 erg/s/cm^2/Angstrom");
+        assert_eq!(flux.repr(), "1e-17 erg/s/cm2/Angstrom");
+        assert_eq!(unit(flux.repr()), flux);
+
+        // All notations of the same unit have the same representation
+        for s in &[
+            "km/s",
+            "km s-1",
+            "km.s**-1",
+            "km*s^-1",
+            "km s^(-1)",
+            " km / s ",
+        ] {
+            assert_eq!(unit(s).repr(), "km/s", "Parsing '{}'", s);
+        }
+        assert_eq!(unit("erg/(s cm2)").repr(), "erg/s/cm2");
+        assert_eq!(unit("(m/s)2").repr(), "m2/s2");
+        assert_eq!(unit("10**-3 m").repr(), "1e-3 m");
+        assert_eq!(unit("s-1").repr(), "s-1");
+        assert_eq!(unit("m s/s"), unit("m"));
+        assert!(unit("").is_dimensionless());
+        assert!(unit("count").is_dimensionless());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = [
+            ("parsec", "Unknown unit 'parsec'"),
+            ("kerg", "Unknown unit 'kerg'"),
+            ("km/", "Expected unit at position 3 in unit 'km/'"),
+            ("(km", "Expected ')' at position 3 in unit '(km'"),
+            ("km^x", "Invalid exponent at position 3 in unit 'km^x'"),
+            ("km)", "Unexpected character ')' in unit 'km)'"),
+            ("km,s", "Unexpected character at position 2 in unit 'km,s'"),
+        ];
+        for &(s, message) in &errors {
+            assert_eq!(
+                s.parse::<PhysicalUnit>(),
+                Err(ParseUnitError(message.to_owned())),
+                "Parsing '{}'",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_conversion() {
+        assert_factor("km", "m", 1e3);
+        assert_factor("Angstrom", "nm", 0.1);
+        assert_factor("\u{c5}", "um", 1e-4);
+        assert_factor("erg s-1 cm-2 Hz-1", "Jy", 1e23);
+        assert_factor("mJy", "Jy", 1e-3);
+        assert_factor("arcsec", "deg", 1.0 / 3600.0);
+        assert_factor("kpc", "pc", 1e3);
+        assert_factor(
+            "1e-17 erg/s/cm2/Angstrom",
+            "W m-2 nm-1",
+            1e-17 * 1e-7 * 1e4 * 10.0,
+        );
+
+        // Dimensions must match
+        assert!(unit("km").conversion_factor(&unit("s")).is_none());
+        assert!(unit("Jy")
+            .conversion_factor(&unit("erg/s/cm2/Angstrom"))
+            .is_none());
+        // Angles are not dimensionless
+        assert!(unit("rad").conversion_factor(&unit("")).is_none());
+        assert!(unit("sr").is_compatible(&unit("deg2")));
+    }
+
+    #[test]
+    fn test_algebra() {
+        let velocity = &unit("km") / &unit("s");
+        assert_eq!(velocity, unit("km/s"));
+        assert_eq!(&velocity * &unit("s"), unit("km"));
+        assert!((&velocity / &velocity).is_dimensionless());
+        assert_eq!(velocity.powi(2), unit("km2/s2"));
+        assert_eq!(velocity.powi(2).root(2), Some(velocity));
+        assert_eq!(unit("1e-4 Jy2").root(2), Some(unit("1e-2 Jy")));
+        assert!(unit("km/s").root(2).is_none());
+    }
+}
@@ -40,11 +40,11 @@ macro_rules! precheck {
     };
 }
 
-/// Check that a WcsArray has more than 0 dimensions.
+/// Check that a WcsArray (or LazyWcsArray) has more than 0 dimensions.
 /// If so, return the number of frames along the first dimension.
 macro_rules! has_gt_0_dim {
     ($wcs_array: ident) => {
-        if let Some(frame_cnt) = $wcs_array.scalar().dim().as_array_view().first() {
+        if let Some(frame_cnt) = $wcs_array.shape().first() {
             Ok(*frame_cnt)
         } else {
             Err($crate::IOErr::UnexpectedInput(format!(
@@ -55,11 +55,11 @@ macro_rules! has_gt_0_dim {
     };
 }
 
-/// Check that a WcsArray has more than $dim dimensions.
+/// Check that a WcsArray (or LazyWcsArray) has $dim dimensions.
 macro_rules! dim_is {
     ($wcs_array: ident, $dim: expr) => {{
         let expected_dim = $dim;
-        let got_dim = $wcs_array.scalar().ndim();
+        let got_dim = $wcs_array.shape().len();
         if got_dim == expected_dim {
             Ok(())
         } else {
@@ -0,0 +1,696 @@
+//! Read and write region files of [SAOImage DS9](http://ds9.si.edu/doc/ref/region.html).
+//!
+//! Circles, ellipses, boxes, annuli and polygons are supported, in `image`
+//! (or `physical`) coordinates and in `fk5` (or `icrs`) world coordinates.
+//! World coordinates are resolved to pixels with the world coordinate system
+//! of the image on which the region is used.
+//!
+//! A region file is the union of all its included shapes, minus the union of
+//! all its excluded shapes (prefixed with `-`). Annotations that do not select
+//! any pixel (points, lines, texts...) are ignored.
+
+use std::error::Error;
+use std::fmt;
+
+use roi::ROI;
+use wcs::Wcs;
+
+#[derive(Debug)]
+pub enum RegionError {
+    /// Error on line `line` (starting from 1) of the region file
+    Parse { line: usize, message: String },
+    /// The region file does not include any region
+    Empty,
+    /// World coordinates are used, but the image has no celestial axes
+    NoCelestialAxes,
+    /// The region of interest cannot be written as a region file
+    Unsupported(String),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegionError::Parse { line, ref message } => {
+                write!(f, "Invalid region on line {}: {}", line, message)
+            }
+            RegionError::Empty => write!(f, "No region found in region file."),
+            RegionError::NoCelestialAxes => write!(
+                f,
+                "World coordinates need an image with celestial axes on its spatial plane."
+            ),
+            RegionError::Unsupported(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for RegionError {
+    fn description(&self) -> &str {
+        "RegionError"
+    }
+}
+
+/// Coordinate system of a region file.
+enum Frame<'a> {
+    /// Pixel coordinates, starting from 1
+    Image,
+    /// Celestial coordinates in degrees
+    Sky(Sky<'a>),
+}
+
+/// Celestial axes of the spatial plane of an image.
+struct Sky<'a> {
+    wcs: &'a Wcs,
+    lng: usize,
+    lat: usize,
+    /// World coordinates of the first pixel, used for non-spatial axes
+    origin: [f32; 4],
+}
+
+impl<'a> Sky<'a> {
+    fn new(wcs: Option<&'a Wcs>) -> Result<Self, RegionError> {
+        let wcs = wcs.ok_or(RegionError::NoCelestialAxes)?;
+        match wcs.celestial_axes() {
+            Some((lng, lat)) if lng < 2 && lat < 2 => Ok(Sky {
+                wcs,
+                lng,
+                lat,
+                origin: wcs.pix2world([0.0; 4]),
+            }),
+            _ => Err(RegionError::NoCelestialAxes),
+        }
+    }
+
+    fn to_pixel(&self, lng: f64, lat: f64) -> Option<(f32, f32)> {
+        let mut world = self.origin;
+        world[self.lng] = lng as f32;
+        world[self.lat] = lat as f32;
+        self.wcs.world2pix(world).map(|pixel| (pixel[0], pixel[1]))
+    }
+
+    fn to_world(&self, (x, y): (f32, f32)) -> (f64, f64) {
+        let world = self.wcs.pix2world([x, y, 0.0, 0.0]);
+        (f64::from(world[self.lng]), f64::from(world[self.lat]))
+    }
+
+    /// Compute the scale (in degrees per pixel) and the orientation of the
+    /// sky around `pixel`. The orientation is the angle in degrees of the
+    /// direction of the west in pixel coordinates, so that angles in world
+    /// coordinates are counter-clockwise from the west.
+    fn local_frame(&self, pixel: (f32, f32)) -> (f64, f64) {
+        // World coordinates are single-precision floats, so offsets are
+        // computed over about 0.1 degree to be accurate
+        let (a, b, c, d) = self.jacobian(pixel, 1.0);
+        let scale = (a * d - b * c).abs().sqrt();
+        let (a, b, c, d) = self.jacobian(pixel, (0.1 / scale).max(1.0) as f32);
+        let det = a * d - b * c;
+        let west = (-d / det, c / det);
+        (det.abs().sqrt(), west.1.atan2(west.0).to_degrees())
+    }
+
+    /// Compute the offsets `(a, b, c, d)` in degrees towards east (`a`, `b`)
+    /// and north (`c`, `d`) for a step of one pixel along *x* and *y*,
+    /// averaged over `step` pixels.
+    fn jacobian(&self, (x, y): (f32, f32), step: f32) -> (f64, f64, f64, f64) {
+        let (lng0, lat0) = self.to_world((x, y));
+        let (lng_x, lat_x) = self.to_world((x + step, y));
+        let (lng_y, lat_y) = self.to_world((x, y + step));
+        let cos_lat = lat0.to_radians().cos();
+        let step = f64::from(step);
+        let east = |lng: f64| {
+            let d = lng - lng0;
+            (d - 360.0 * (d / 360.0).round()) * cos_lat / step
+        };
+        (
+            east(lng_x),
+            east(lng_y),
+            (lat_x - lat0) / step,
+            (lat_y - lat0) / step,
+        )
+    }
+}
+
+impl ROI {
+    /// Parse the content of a DS9 region file.
+    ///
+    /// `wcs` is the world coordinate system of the image, used to resolve
+    /// regions defined in world coordinates.
+    pub fn from_ds9(text: &str, wcs: Option<&Wcs>) -> Result<ROI, RegionError> {
+        let mut frame = Frame::Image;
+        let mut included: Option<ROI> = None;
+        let mut excluded: Option<ROI> = None;
+        for (n, line) in text.lines().enumerate() {
+            let error = |message: String| RegionError::Parse {
+                line: n + 1,
+                message,
+            };
+            // Everything after '#' is a comment or a list of properties
+            let line = line.split('#').next().unwrap_or("");
+            for command in line.split(';') {
+                let command = command.trim();
+                if command.is_empty() {
+                    continue;
+                }
+                let (include, command) = if command.starts_with('-') {
+                    (false, &command[1..])
+                } else if command.starts_with('+') {
+                    (true, &command[1..])
+                } else {
+                    (true, command)
+                };
+                let name_len = command
+                    .find(|c: char| !c.is_alphanumeric())
+                    .unwrap_or_else(|| command.len());
+                let name = command[..name_len].to_lowercase();
+                let args: Vec<_> = command[name_len..]
+                    .split(|c: char| c == '(' || c == ')' || c == ',' || c.is_whitespace())
+                    .filter(|arg| !arg.is_empty() && !arg.contains('='))
+                    .collect();
+                match name.as_str() {
+                    "global" => {}
+                    "image" | "physical" => frame = Frame::Image,
+                    "fk5" | "icrs" | "j2000" | "wcs" => frame = Frame::Sky(Sky::new(wcs)?),
+                    "fk4" | "b1950" | "galactic" | "ecliptic" | "linear" | "amplifier"
+                    | "detector" => {
+                        return Err(error(format!("Unsupported coordinate system '{}'.", name)))
+                    }
+                    "point" | "line" | "vector" | "text" | "ruler" | "compass" | "projection" => {}
+                    _ => {
+                        let shape = parse_shape(&name, &args, &frame).map_err(error)?;
+                        let regions = if include {
+                            &mut included
+                        } else {
+                            &mut excluded
+                        };
+                        *regions = Some(match regions.take() {
+                            Some(roi) => ROI::Union(Box::new(roi), Box::new(shape)),
+                            None => shape,
+                        });
+                    }
+                }
+            }
+        }
+        match (included, excluded) {
+            (Some(included), Some(excluded)) => {
+                Ok(ROI::Difference(Box::new(included), Box::new(excluded)))
+            }
+            (Some(included), None) => Ok(included),
+            (None, _) => Err(RegionError::Empty),
+        }
+    }
+
+    /// Write the region of interest as a DS9 region file.
+    ///
+    /// Regions are written in `fk5` world coordinates if `wcs` is given, and in
+    /// `image` coordinates otherwise. Only geometric regions, their unions
+    /// and the difference of two unions of geometric regions can be written.
+    pub fn to_ds9(&self, wcs: Option<&Wcs>) -> Result<String, RegionError> {
+        let frame = match wcs {
+            Some(wcs) => Frame::Sky(Sky::new(Some(wcs))?),
+            None => Frame::Image,
+        };
+        let mut shapes = vec![];
+        if let ROI::Difference(ref included, ref excluded) = *self {
+            flatten(included, true, &mut shapes)?;
+            flatten(excluded, false, &mut shapes)?;
+        } else {
+            flatten(self, true, &mut shapes)?;
+        }
+
+        let mut out = String::from("# Region file format: DS9 version 4.1\n");
+        out.push_str(match frame {
+            Frame::Image => "image\n",
+            Frame::Sky(_) => "fk5\n",
+        });
+        for (include, shape) in shapes {
+            if !include {
+                out.push('-');
+            }
+            out.push_str(&write_shape(shape, &frame));
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+/// Parse a shape named `name` with arguments `args`.
+fn parse_shape(name: &str, args: &[&str], frame: &Frame) -> Result<ROI, String> {
+    let arg_count_is = |valid: bool| {
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("Unexpected number of arguments for '{}'.", name))
+        }
+    };
+    match name {
+        "circle" => {
+            arg_count_is(args.len() == 3)?;
+            let (center, scale, _) = parse_center(args[0], args[1], frame)?;
+            Ok(ROI::Circle {
+                center,
+                radius: parse_length(args[2], scale)?,
+            })
+        }
+        "ellipse" => {
+            arg_count_is(args.len() == 4 || args.len() == 5)?;
+            let (center, scale, rotation) = parse_center(args[0], args[1], frame)?;
+            Ok(ROI::Ellipse {
+                center,
+                radii: (parse_length(args[2], scale)?, parse_length(args[3], scale)?),
+                angle: parse_rotation(args.get(4), rotation)?,
+            })
+        }
+        "box" => {
+            arg_count_is(args.len() == 4 || args.len() == 5)?;
+            let (center, scale, rotation) = parse_center(args[0], args[1], frame)?;
+            Ok(ROI::Rectangle {
+                center,
+                size: (parse_length(args[2], scale)?, parse_length(args[3], scale)?),
+                angle: parse_rotation(args.get(4), rotation)?,
+            })
+        }
+        "annulus" => {
+            // Concentric annuli make up a single annulus from the first to
+            // the last radius
+            arg_count_is(args.len() >= 4)?;
+            let (center, scale, _) = parse_center(args[0], args[1], frame)?;
+            Ok(ROI::Annulus {
+                center,
+                inner: parse_length(args[2], scale)?,
+                outer: parse_length(args[args.len() - 1], scale)?,
+            })
+        }
+        "polygon" => {
+            arg_count_is(args.len() >= 6 && args.len() % 2 == 0)?;
+            let mut vertices = Vec::with_capacity(args.len() / 2);
+            for vertex in args.chunks(2) {
+                let (pixel, _, _) = parse_center(vertex[0], vertex[1], frame)?;
+                vertices.push(pixel);
+            }
+            Ok(ROI::Polygon(vertices))
+        }
+        _ => Err(format!("Unsupported shape '{}'.", name)),
+    }
+}
+
+/// Parse a position and return it in pixel coordinates (starting from 0),
+/// along with the local scale (in degrees per pixel, `None` in image
+/// coordinates) and orientation of world coordinates.
+fn parse_center(x: &str, y: &str, frame: &Frame) -> Result<((f32, f32), Option<f64>, f64), String> {
+    let invalid = |s: &str| format!("Invalid coordinate '{}'.", s);
+    match *frame {
+        Frame::Image => {
+            let x = parse_number(x).ok_or_else(|| invalid(x))?;
+            let y = parse_number(y).ok_or_else(|| invalid(y))?;
+            Ok((((x - 1.0) as f32, (y - 1.0) as f32), None, 0.0))
+        }
+        Frame::Sky(ref sky) => {
+            let lng = parse_sky_angle(x, true).ok_or_else(|| invalid(x))?;
+            let lat = parse_sky_angle(y, false).ok_or_else(|| invalid(y))?;
+            let pixel = sky
+                .to_pixel(lng, lat)
+                .ok_or_else(|| format!("Position ({}, {}) cannot be projected.", x, y))?;
+            let (scale, rotation) = sky.local_frame(pixel);
+            Ok((pixel, Some(scale), rotation))
+        }
+    }
+}
+
+/// Parse a length and return it in pixels. `scale` is the size of a pixel
+/// in degrees, used for lengths in world coordinates. It is `None` in image
+/// coordinates, where angular lengths cannot be resolved.
+///
+/// Lengths are in degrees, unless they end with `"` (arcseconds), `'`
+/// (arcminutes), `d` (degrees), `r` (radians) or `i`/`p` (pixels). In image
+/// coordinates, lengths without any unit are in pixels.
+fn parse_length(s: &str, scale: Option<f64>) -> Result<f32, String> {
+    let invalid = || format!("Invalid length '{}'.", s);
+    let (value, degrees) = match s.chars().last() {
+        Some('"') => (&s[..s.len() - 1], Some(1.0 / 3600.0)),
+        Some('\'') => (&s[..s.len() - 1], Some(1.0 / 60.0)),
+        Some('d') => (&s[..s.len() - 1], Some(1.0)),
+        Some('r') => (&s[..s.len() - 1], Some(180.0 / ::std::f64::consts::PI)),
+        Some('i') | Some('p') => (&s[..s.len() - 1], None),
+        _ => (s, scale.and(Some(1.0))),
+    };
+    let value = parse_number(value).ok_or_else(invalid)?;
+    match (degrees, scale) {
+        (None, _) => Ok(value as f32),
+        (Some(degrees), Some(scale)) => Ok((value * degrees / scale) as f32),
+        (Some(_), None) => Err(format!(
+            "Angular length '{}' needs world coordinates (e.g. 'fk5').",
+            s
+        )),
+    }
+}
+
+/// Parse an optional angle in degrees and convert it to pixel coordinates.
+fn parse_rotation(s: Option<&&str>, rotation: f64) -> Result<f32, String> {
+    match s {
+        Some(s) => parse_number(s)
+            .map(|angle| (angle + rotation) as f32)
+            .ok_or_else(|| format!("Invalid angle '{}'.", s)),
+        None => Ok(rotation as f32),
+    }
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    s.parse().ok().filter(|v: &f64| v.is_finite())
+}
+
+/// Parse a celestial coordinate and return it in degrees.
+///
+/// Coordinates are either decimal degrees (`83.63`, `83.63d`) or sexagesimal
+/// (`05:34:31.9`, `5h34m31.9s`, `+22:00:52`, `22d00m52s`). Sexagesimal
+/// longitudes are in hours.
+fn parse_sky_angle(s: &str, is_longitude: bool) -> Option<f64> {
+    let sexagesimal = s.contains(':') || s.contains('h') || s.contains('m');
+    if !sexagesimal {
+        return parse_number(s.trim_end_matches('d'));
+    }
+    let (negative, unsigned) = if s.starts_with('-') {
+        (true, &s[1..])
+    } else {
+        (false, s.trim_start_matches('+'))
+    };
+    let mut value = 0.0;
+    let mut factor = 1.0;
+    for part in unsigned
+        .split(|c: char| c == ':' || c == 'h' || c == 'd' || c == 'm' || c == 's')
+        .filter(|part| !part.is_empty())
+    {
+        value += parse_number(part)? * factor;
+        factor /= 60.0;
+    }
+    if unsigned.contains('h') || (is_longitude && unsigned.contains(':')) {
+        value *= 15.0;
+    }
+    Some(if negative { -value } else { value })
+}
+
+/// List the geometric shapes making up `roi`, with `include` set to false for
+/// excluded shapes.
+fn flatten<'a>(
+    roi: &'a ROI,
+    include: bool,
+    shapes: &mut Vec<(bool, &'a ROI)>,
+) -> Result<(), RegionError> {
+    match *roi {
+        ROI::Union(ref a, ref b) => {
+            flatten(a, include, shapes)?;
+            flatten(b, include, shapes)
+        }
+        ROI::Circle { .. }
+        | ROI::Ellipse { .. }
+        | ROI::Rectangle { .. }
+        | ROI::Annulus { .. }
+        | ROI::Polygon(_) => {
+            shapes.push((include, roi));
+            Ok(())
+        }
+        _ => Err(RegionError::Unsupported(format!(
+            "Region of interest cannot be written as a region file: {:?}",
+            roi
+        ))),
+    }
+}
+
+/// Write a geometric shape in the coordinates of `frame`.
+fn write_shape(shape: &ROI, frame: &Frame) -> String {
+    let (name, center, lengths, angle): (_, _, Vec<f32>, _) = match *shape {
+        ROI::Circle { center, radius } => ("circle", center, vec![radius], None),
+        ROI::Ellipse {
+            center,
+            radii,
+            angle,
+        } => ("ellipse", center, vec![radii.0, radii.1], Some(angle)),
+        ROI::Rectangle {
+            center,
+            size,
+            angle,
+        } => ("box", center, vec![size.0, size.1], Some(angle)),
+        ROI::Annulus {
+            center,
+            inner,
+            outer,
+        } => ("annulus", center, vec![inner, outer], None),
+        ROI::Polygon(ref vertices) => {
+            let vertices: Vec<_> = vertices
+                .iter()
+                .map(|&vertex| write_position(vertex, frame))
+                .collect();
+            return format!("polygon({})", vertices.join(","));
+        }
+        _ => unreachable!("Only geometric shapes are written"),
+    };
+
+    let mut args = vec![write_position(center, frame)];
+    let (scale, rotation) = match *frame {
+        Frame::Image => (1.0, 0.0),
+        Frame::Sky(ref sky) => sky.local_frame(center),
+    };
+    for length in lengths {
+        args.push(match *frame {
+            Frame::Image => format!("{}", length),
+            Frame::Sky(_) => format!("{:.4}\"", f64::from(length) * scale * 3600.0),
+        });
+    }
+    if let Some(angle) = angle {
+        args.push(format!("{}", (f64::from(angle) - rotation) as f32));
+    }
+    format!("{}({})", name, args.join(","))
+}
+
+fn write_position(pixel: (f32, f32), frame: &Frame) -> String {
+    match *frame {
+        Frame::Image => format!("{},{}", pixel.0 + 1.0, pixel.1 + 1.0),
+        Frame::Sky(ref sky) => {
+            let (lng, lat) = sky.to_world(pixel);
+            format!("{:.7},{:.7}", lng, lat)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_length, parse_sky_angle, RegionError};
+    use fitrs::Hdu;
+    use roi::ROI;
+    use test_util::assert_close;
+    use wcs::Wcs;
+
+    /// TAN projection centered on pixel (5, 5) at (83, 22), with pixels of
+    /// one arcsecond and the east on the left.
+    fn sky_wcs() -> Wcs {
+        let mut hdu = Hdu::new(&[10, 10], vec![0.0; 100]);
+        hdu.insert("CTYPE1", "RA---TAN");
+        hdu.insert("CTYPE2", "DEC--TAN");
+        for i in 0..2 {
+            hdu.insert(format!("CRPIX{}", i + 1).as_str(), 6.0);
+            hdu.insert(format!("CDELT{}", i + 1).as_str(), [-1.0, 1.0][i] / 3600.0);
+        }
+        hdu.insert("CRVAL1", 83.0);
+        hdu.insert("CRVAL2", 22.0);
+        Wcs::from_hdu(&hdu)
+    }
+
+    #[test]
+    fn test_parse_sky_angle() {
+        let ra = (5.0 + 34.0 / 60.0 + 31.9 / 3600.0) * 15.0;
+        let dec = 22.0 + 52.0 / 3600.0;
+        let cases = [
+            ("83.63", true, 83.63),
+            ("83.63d", true, 83.63),
+            ("05:34:31.9", true, ra),
+            ("5h34m31.9s", true, ra),
+            ("+22:00:52", false, dec),
+            ("22d00m52s", false, dec),
+            ("-22:00:52", false, -dec),
+            ("-0:30:00", false, -0.5),
+        ];
+        for &(s, is_longitude, expected) in &cases {
+            let got = parse_sky_angle(s, is_longitude).unwrap();
+            assert_close(&[got], &[expected], 1e-9);
+        }
+        assert_eq!(parse_sky_angle("5h3xm", true), None);
+        assert_eq!(parse_sky_angle("ra", true), None);
+    }
+
+    #[test]
+    fn test_image_regions() {
+        let text = "# Region file format: DS9 version 4.1
+global color=green width=1
+image
+ellipse(3,4,2,1,45) # color=red
+box 5 5 2 4; annulus(5,5,1,2,3)
+polygon(1,1,4,1,4,4) # text={Not a point(1,1)}
+point(2,2)
+-circle(5,5,0.5)";
+        let expected = ROI::Difference(
+            Box::new(ROI::Union(
+                Box::new(ROI::Union(
+                    Box::new(ROI::Union(
+                        Box::new(ROI::Ellipse {
+                            center: (2.0, 3.0),
+                            radii: (2.0, 1.0),
+                            angle: 45.0,
+                        }),
+                        Box::new(ROI::Rectangle {
+                            center: (4.0, 4.0),
+                            size: (2.0, 4.0),
+                            angle: 0.0,
+                        }),
+                    )),
+                    Box::new(ROI::Annulus {
+                        center: (4.0, 4.0),
+                        inner: 1.0,
+                        outer: 3.0,
+                    }),
+                )),
+                Box::new(ROI::Polygon(vec![(0.0, 0.0), (3.0, 0.0), (3.0, 3.0)])),
+            )),
+            Box::new(ROI::Circle {
+                center: (4.0, 4.0),
+                radius: 0.5,
+            }),
+        );
+        let roi = ROI::from_ds9(text, None).unwrap();
+        assert_eq!(roi, expected);
+
+        // Round trip
+        let written = roi.to_ds9(None).unwrap();
+        assert!(written.contains("\nimage\n"));
+        assert_eq!(ROI::from_ds9(&written, None).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_fk5_regions() {
+        let wcs = sky_wcs();
+        for text in &[
+            "fk5;circle(83,22,2\")",
+            "fk5\ncircle 5:32:00 +22:00:00 0.0333333'",
+            "icrs;circle(83d,22d,0.000555556d)",
+        ] {
+            match ROI::from_ds9(text, Some(&wcs)).unwrap() {
+                ROI::Circle { center, radius } => {
+                    assert_close(&[center.0, center.1, radius], &[5.0, 5.0, 2.0], 1e-2);
+                }
+                roi => panic!("Unexpected ROI {:?}", roi),
+            }
+        }
+        // Lengths in pixels are not scaled
+        match ROI::from_ds9("fk5;circle(83,22,3i)", Some(&wcs)).unwrap() {
+            ROI::Circle { radius, .. } => assert_eq!(radius, 3.0),
+            roi => panic!("Unexpected ROI {:?}", roi),
+        }
+    }
+
+    #[test]
+    fn test_fk5_round_trip() {
+        let wcs = sky_wcs();
+        let roi = ROI::Difference(
+            Box::new(ROI::Ellipse {
+                center: (4.0, 6.0),
+                radii: (3.0, 1.0),
+                angle: 30.0,
+            }),
+            Box::new(ROI::Polygon(vec![(4.0, 6.0), (5.0, 6.0), (5.0, 7.0)])),
+        );
+        let written = roi.to_ds9(Some(&wcs)).unwrap();
+        assert!(written.contains("\nfk5\n"));
+        assert!(written.contains("\n-polygon("));
+        match ROI::from_ds9(&written, Some(&wcs)).unwrap() {
+            ROI::Difference(ellipse, polygon) => {
+                match *ellipse {
+                    ROI::Ellipse {
+                        center,
+                        radii,
+                        angle,
+                    } => {
+                        assert_close(
+                            &[center.0, center.1, radii.0, radii.1, angle],
+                            &[4.0, 6.0, 3.0, 1.0, 30.0],
+                            1e-2,
+                        );
+                    }
+                    ref roi => panic!("Unexpected ROI {:?}", roi),
+                }
+                match *polygon {
+                    ROI::Polygon(ref vertices) => {
+                        assert_eq!(vertices.len(), 3);
+                        assert_close(&[vertices[2].0, vertices[2].1], &[5.0, 7.0], 1e-2);
+                    }
+                    ref roi => panic!("Unexpected ROI {:?}", roi),
+                }
+            }
+            roi => panic!("Unexpected ROI {:?}", roi),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let wcs = sky_wcs();
+        let parse_error = |text: &str| match ROI::from_ds9(text, Some(&wcs)) {
+            Err(RegionError::Parse { line, .. }) => line,
+            result => panic!("Unexpected result {:?}", result),
+        };
+        assert_eq!(parse_error("image\ncircle(1,1)"), 2);
+        assert_eq!(parse_error("image\n\nellipse(1,1,2)"), 3);
+        assert_eq!(parse_error("image;polygon(1,1,2,2,3)"), 1);
+        assert_eq!(parse_error("image;star(1,1,2)"), 1);
+        assert_eq!(parse_error("image;circle(a,1,2)"), 1);
+        assert_eq!(parse_error("galactic;circle(1,1,2)"), 1);
+        assert_eq!(parse_error("fk5;circle(83,xx,2)"), 1);
+
+        match ROI::from_ds9("fk5;circle(83,22,2\")", None) {
+            Err(RegionError::NoCelestialAxes) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let linear = Wcs::from_hdu(&Hdu::new(&[10, 10], vec![0.0; 100]));
+        match ROI::from_ds9("fk5;circle(83,22,2\")", Some(&linear)) {
+            Err(RegionError::NoCelestialAxes) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        for text in &["# Empty region file", "image; point(1,1)", "-circle(1,1,1)"] {
+            match ROI::from_ds9(text, None) {
+                Err(RegionError::Empty) => {}
+                result => panic!("Unexpected result {:?}", result),
+            }
+        }
+        match ROI::All.to_ds9(None) {
+            Err(RegionError::Unsupported(_)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_length() {
+        // 1 pixel is 2 arcseconds
+        let scale = Some(2.0 / 3600.0);
+        assert_eq!(parse_length("4\"", scale), Ok(2.0));
+        assert_eq!(parse_length("0.1'", scale), Ok(3.0));
+        assert_eq!(parse_length("3i", scale), Ok(3.0));
+        assert_eq!(parse_length("3p", scale), Ok(3.0));
+        assert_close(&[parse_length("0.001d", scale).unwrap()], &[1.8], 1e-6);
+        assert_close(&[parse_length("0.001", scale).unwrap()], &[1.8], 1e-6);
+        assert!(parse_length("2x", scale).is_err());
+
+        // Lengths in image coordinates are in pixels
+        assert_eq!(parse_length("1.5", None), Ok(1.5));
+        assert_eq!(parse_length("1.5i", None), Ok(1.5));
+        for angle in &["2\"", "2'", "2d", "2r"] {
+            assert!(parse_length(angle, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_angular_length_in_image_coordinates() {
+        assert!(ROI::from_ds9("image;circle(3,3,1.5\")", None).is_err());
+        assert_eq!(
+            ROI::from_ds9("image;circle(3,3,1.5)", None).unwrap(),
+            ROI::Circle {
+                center: (2.0, 2.0),
+                radius: 1.5,
+            }
+        );
+    }
+}
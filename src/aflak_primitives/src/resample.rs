@@ -0,0 +1,274 @@
+//! Rebin and resample the spatial plane of n-dimensional arrays.
+//!
+//! The spatial plane is made of the two last axes in ndarray order. Each
+//! output pixel is a weighted sum of input pixels. Weights are computed
+//! separately along each axis, as lists of `(input index, weight)` for each
+//! output index.
+//!
+//! NaN values are ignored: the weighted sum is renormalized by the weights of
+//! the valid values.
+
+use ndarray::{Array2, ArrayD, Dimension};
+
+/// Weights of input pixels for each output pixel along one axis
+pub type Weights = Vec<Vec<(usize, f32)>>;
+
+/// Method used to resample an array onto a new grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resampling {
+    /// Value of the nearest pixel
+    Nearest,
+    /// Bilinear interpolation between the four nearest pixels
+    Bilinear,
+    /// Sum of input pixels weighted by their overlap with the output pixel,
+    /// so that the total flux is conserved
+    Flux,
+}
+
+/// Compute the weights to resample an axis of `old` pixels onto `new`
+/// pixels. Both grids span the same extent, so the center of output pixel
+/// `p` is at input pixel `(p + 0.5) * old / new - 0.5`.
+pub fn resample_weights(method: Resampling, old: usize, new: usize) -> Weights {
+    let scale = old as f32 / new as f32;
+    (0..new)
+        .map(|p| {
+            let center = (p as f32 + 0.5) * scale - 0.5;
+            match method {
+                Resampling::Nearest | Resampling::Bilinear => {
+                    interpolation_weights(method, center, old)
+                }
+                Resampling::Flux => {
+                    let (lo, hi) = (p as f32 * scale, (p + 1) as f32 * scale);
+                    let first = lo.floor() as usize;
+                    let end = (hi.ceil() as usize).min(old);
+                    (first..end)
+                        .map(|i| (i, hi.min((i + 1) as f32) - lo.max(i as f32)))
+                        .filter(|&(_, w)| w > 0.0)
+                        .collect()
+                }
+            }
+        })
+        .collect()
+}
+
+/// Compute the weights to interpolate an axis of `len` pixels at position
+/// `x` (in pixels). Positions beyond the edges take the value of the edge.
+/// Flux-conserving resampling is computed as a bilinear interpolation.
+fn interpolation_weights(method: Resampling, x: f32, len: usize) -> Vec<(usize, f32)> {
+    let x = x.max(0.0).min((len - 1) as f32);
+    match method {
+        Resampling::Nearest => vec![(x.round() as usize, 1.0)],
+        Resampling::Bilinear | Resampling::Flux => {
+            let i = x.floor() as usize;
+            let t = x - i as f32;
+            if t > 0.0 {
+                vec![(i, 1.0 - t), (i + 1, t)]
+            } else {
+                vec![(i, 1.0)]
+            }
+        }
+    }
+}
+
+/// Compute the weights to rebin an axis of `old` pixels by groups of
+/// `factor` pixels. Remaining pixels at the end of the axis are dropped.
+///
+/// Rebinned pixels are the sum of their input pixels, or their mean if
+/// `mean` is true.
+pub fn rebin_weights(old: usize, factor: usize, mean: bool) -> Weights {
+    let weight = if mean { 1.0 / factor as f32 } else { 1.0 };
+    (0..old / factor)
+        .map(|p| {
+            (p * factor..(p + 1) * factor)
+                .map(|i| (i, weight))
+                .collect()
+        })
+        .collect()
+}
+
+/// Compute each output pixel of the spatial plane of `data` as a weighted
+/// sum of input pixels. `weights_y` and `weights_x` are the weights along
+/// the two last axes (in ndarray order). All other axes are unchanged.
+///
+/// NaN values are ignored. An output pixel is NaN if all its input pixels
+/// are NaN.
+pub fn apply_weights(
+    data: &ArrayD<f32>,
+    weights_y: &[Vec<(usize, f32)>],
+    weights_x: &[Vec<(usize, f32)>],
+) -> ArrayD<f32> {
+    let ndim = data.ndim();
+    assert!(ndim >= 2, "Array has a spatial plane");
+
+    let mut shape = data.shape().to_vec();
+    shape[ndim - 2] = weights_y.len();
+    shape[ndim - 1] = weights_x.len();
+    let mut input = vec![0; ndim];
+    ArrayD::from_shape_fn(shape, |index| {
+        input.copy_from_slice(index.slice());
+        let wy = &weights_y[index[ndim - 2]];
+        let wx = &weights_x[index[ndim - 1]];
+        weighted_sum(data, &mut input, wy, wx)
+    })
+}
+
+/// Sample the spatial plane of `data` at `positions`, given as `[x, y]`
+/// pixel coordinates along the two last axes (in ndarray order). All other
+/// axes are unchanged, the spatial plane of the output has the shape of
+/// `positions`.
+///
+/// Positions more than half a pixel away from `data` are NaN. With
+/// `Resampling::Flux`, values are interpolated bilinearly and should be
+/// scaled by the area of output pixels.
+pub fn sample(data: &ArrayD<f32>, positions: &Array2<[f32; 2]>, method: Resampling) -> ArrayD<f32> {
+    let ndim = data.ndim();
+    assert!(ndim >= 2, "Array has a spatial plane");
+
+    let (ny, nx) = (data.shape()[ndim - 2], data.shape()[ndim - 1]);
+    let is_inside = |x: f32, len: usize| x >= -0.5 && x <= len as f32 - 0.5;
+    let weights = positions.map(|position| {
+        let (x, y) = (position[0], position[1]);
+        if is_inside(x, nx) && is_inside(y, ny) {
+            (
+                interpolation_weights(method, y, ny),
+                interpolation_weights(method, x, nx),
+            )
+        } else {
+            (vec![], vec![])
+        }
+    });
+
+    let mut shape = data.shape().to_vec();
+    shape[ndim - 2] = positions.rows();
+    shape[ndim - 1] = positions.cols();
+    let mut input = vec![0; ndim];
+    ArrayD::from_shape_fn(shape, |index| {
+        input.copy_from_slice(index.slice());
+        let (ref wy, ref wx) = weights[[index[ndim - 2], index[ndim - 1]]];
+        weighted_sum(data, &mut input, wy, wx)
+    })
+}
+
+/// Compute the weighted sum of the pixels of `data` in the spatial plane
+/// at `input`, ignoring NaN values. The sum is renormalized by the weights
+/// of the valid values.
+fn weighted_sum(
+    data: &ArrayD<f32>,
+    input: &mut [usize],
+    weights_y: &[(usize, f32)],
+    weights_x: &[(usize, f32)],
+) -> f32 {
+    let ndim = input.len();
+    let mut acc = 0.0;
+    let mut total = 0.0;
+    let mut valid = 0.0;
+    for &(y, wy) in weights_y {
+        input[ndim - 2] = y;
+        for &(x, wx) in weights_x {
+            input[ndim - 1] = x;
+            let w = wy * wx;
+            let v = data[&*input];
+            total += w;
+            if !v.is_nan() {
+                acc += w * v;
+                valid += w;
+            }
+        }
+    }
+    if valid > 0.0 {
+        acc * total / valid
+    } else {
+        ::std::f32::NAN
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_weights, rebin_weights, resample_weights, sample, Resampling};
+    use ndarray::{Array2, ArrayD};
+    use std::f32::NAN;
+
+    #[test]
+    fn test_resample_weights() {
+        assert_eq!(
+            resample_weights(Resampling::Nearest, 4, 2),
+            vec![vec![(1, 1.0)], vec![(3, 1.0)]]
+        );
+        // Positions beyond the edges take the value of the edge
+        assert_eq!(
+            resample_weights(Resampling::Nearest, 2, 4),
+            vec![
+                vec![(0, 1.0)],
+                vec![(0, 1.0)],
+                vec![(1, 1.0)],
+                vec![(1, 1.0)]
+            ]
+        );
+        assert_eq!(
+            resample_weights(Resampling::Bilinear, 2, 4),
+            vec![
+                vec![(0, 1.0)],
+                vec![(0, 0.75), (1, 0.25)],
+                vec![(0, 0.25), (1, 0.75)],
+                vec![(1, 1.0)],
+            ]
+        );
+        // Each input pixel is shared between output pixels
+        assert_eq!(
+            resample_weights(Resampling::Flux, 3, 2),
+            vec![vec![(0, 1.0), (1, 0.5)], vec![(1, 0.5), (2, 1.0)]]
+        );
+    }
+
+    #[test]
+    fn test_rebin_weights() {
+        // Last pixel is dropped
+        assert_eq!(
+            rebin_weights(5, 2, false),
+            vec![vec![(0, 1.0), (1, 1.0)], vec![(2, 1.0), (3, 1.0)]]
+        );
+        let w = 1.0 / 3.0;
+        assert_eq!(
+            rebin_weights(3, 3, true),
+            vec![vec![(0, w), (1, w), (2, w)]]
+        );
+        assert!(rebin_weights(1, 2, false).is_empty());
+    }
+
+    #[test]
+    fn test_apply_weights() {
+        // Two frames of 2x2 pixels
+        let data =
+            ArrayD::from_shape_vec(vec![2, 2, 2], vec![1.0, 2.0, 3.0, NAN, NAN, NAN, NAN, NAN])
+                .unwrap();
+        let weights = rebin_weights(2, 2, false);
+        let out = apply_weights(&data, &weights, &weights);
+        assert_eq!(out.shape(), &[2, 1, 1]);
+        // NaN is ignored, the sum is renormalized
+        assert_eq!(out[[0, 0, 0]], 8.0);
+        assert!(out[[1, 0, 0]].is_nan());
+    }
+
+    #[test]
+    fn test_sample() {
+        // v = x + 2 y
+        let data = ArrayD::from_shape_vec(vec![2, 2], vec![0.0, 1.0, 2.0, 3.0]).unwrap();
+        let positions = Array2::from_shape_vec(
+            (1, 4),
+            vec![[0.5, 0.5], [1.0, 0.0], [1.5, 1.5], [-0.6, 0.0]],
+        )
+        .unwrap();
+
+        let bilinear = sample(&data, &positions, Resampling::Bilinear);
+        assert_eq!(bilinear.shape(), &[1, 4]);
+        assert_eq!(bilinear[[0, 0]], 1.5);
+        assert_eq!(bilinear[[0, 1]], 1.0);
+        // Within half a pixel of the edge
+        assert_eq!(bilinear[[0, 2]], 3.0);
+        assert!(bilinear[[0, 3]].is_nan());
+
+        let nearest = sample(&data, &positions, Resampling::Nearest);
+        assert_eq!(nearest[[0, 0]], 3.0);
+        assert_eq!(nearest[[0, 1]], 1.0);
+    }
+}
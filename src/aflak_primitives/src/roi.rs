@@ -1,12 +1,49 @@
-use ndarray::ArrayView2;
+use ndarray::{Array2, ArrayView2, Zip};
 
 /// A region of interest in a 2D image.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Geometric regions are defined in pixel coordinates `(x, y)`, where `x` is
+/// the index of the column and `y` the index of the row of the image (i.e.
+/// axes 0 and 1 in FITS order, starting from 0). A pixel belongs to a
+/// geometric region if its center is inside the region. Angles are in
+/// degrees, counter-clockwise from the *x*-axis.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ROI {
     /// The whole image is a region of interest.
     All,
     /// The list of pixels selected by this region of interest.
     PixelList(Vec<(usize, usize)>),
+    /// A circle.
+    Circle { center: (f32, f32), radius: f32 },
+    /// An ellipse. `radii` are the semi-axes along the rotated *x* and *y*
+    /// axes.
+    Ellipse {
+        center: (f32, f32),
+        radii: (f32, f32),
+        angle: f32,
+    },
+    /// A box. `size` is the width and height along the rotated *x* and *y*
+    /// axes.
+    Rectangle {
+        center: (f32, f32),
+        size: (f32, f32),
+        angle: f32,
+    },
+    /// The area between two concentric circles. The inner circle is
+    /// excluded.
+    Annulus {
+        center: (f32, f32),
+        inner: f32,
+        outer: f32,
+    },
+    /// A polygon defined by its vertices.
+    Polygon(Vec<(f32, f32)>),
+    /// Pixels belonging to any of the two regions.
+    Union(Box<ROI>, Box<ROI>),
+    /// Pixels belonging to both regions.
+    Intersection(Box<ROI>, Box<ROI>),
+    /// Pixels belonging to the first region but not to the second one.
+    Difference(Box<ROI>, Box<ROI>),
 }
 
 impl ROI {
@@ -33,6 +70,105 @@ impl ROI {
                 }
                 out
             }
+            _ => self
+                .mask(data.dim())
+                .indexed_iter()
+                .filter(|&(_, selected)| *selected)
+                .map(|(index, _)| (index, data[index]))
+                .collect(),
         }
     }
+
+    /// Rasterize the region of interest on an image with `dim` rows and
+    /// columns. Pixels in the region are `true`.
+    pub fn mask(&self, dim: (usize, usize)) -> Array2<bool> {
+        match *self {
+            ROI::All => Array2::from_elem(dim, true),
+            ROI::PixelList(ref pixels) => {
+                let mut mask = Array2::from_elem(dim, false);
+                for &(i, j) in pixels {
+                    if let Some(selected) = mask.get_mut([i, j]) {
+                        *selected = true;
+                    }
+                }
+                mask
+            }
+            ROI::Union(ref a, ref b) => combine(a, b, dim, |a, b| a || b),
+            ROI::Intersection(ref a, ref b) => combine(a, b, dim, |a, b| a && b),
+            ROI::Difference(ref a, ref b) => combine(a, b, dim, |a, b| a && !b),
+            _ => Array2::from_shape_fn(dim, |(i, j)| self.contains_point(j as f32, i as f32)),
+        }
+    }
+
+    /// Check if point `(x, y)` is inside a geometric region.
+    ///
+    /// Return `false` for regions that are not geometric.
+    fn contains_point(&self, x: f32, y: f32) -> bool {
+        match *self {
+            ROI::Circle { center, radius } => {
+                let (dx, dy) = (x - center.0, y - center.1);
+                dx * dx + dy * dy <= radius * radius
+            }
+            ROI::Ellipse {
+                center,
+                radii,
+                angle,
+            } => {
+                let (u, v) = rotate(x - center.0, y - center.1, -angle);
+                (u / radii.0).powi(2) + (v / radii.1).powi(2) <= 1.0
+            }
+            ROI::Rectangle {
+                center,
+                size,
+                angle,
+            } => {
+                let (u, v) = rotate(x - center.0, y - center.1, -angle);
+                u.abs() <= size.0 / 2.0 && v.abs() <= size.1 / 2.0
+            }
+            ROI::Annulus {
+                center,
+                inner,
+                outer,
+            } => {
+                let (dx, dy) = (x - center.0, y - center.1);
+                let r2 = dx * dx + dy * dy;
+                r2 > inner * inner && r2 <= outer * outer
+            }
+            ROI::Polygon(ref vertices) => {
+                // Even-odd rule: count edges crossed by a ray cast towards +x
+                let mut inside = false;
+                let mut previous = match vertices.last() {
+                    Some(&vertex) => vertex,
+                    None => return false,
+                };
+                for &(xi, yi) in vertices {
+                    let (xj, yj) = previous;
+                    if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                    previous = (xi, yi);
+                }
+                inside
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Combine the masks of `a` and `b` pixel by pixel with `f`.
+fn combine<F>(a: &ROI, b: &ROI, dim: (usize, usize), f: F) -> Array2<bool>
+where
+    F: Fn(bool, bool) -> bool,
+{
+    let mut mask = a.mask(dim);
+    Zip::from(&mut mask)
+        .and(&b.mask(dim))
+        .apply(|a, &b| *a = f(*a, b));
+    mask
+}
+
+/// Rotate vector `(x, y)` by `angle` degrees counter-clockwise.
+fn rotate(x: f32, y: f32, angle: f32) -> (f32, f32) {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
 }
@@ -0,0 +1,272 @@
+//! NaN-aware smoothing and convolution of n-dimensional arrays.
+//!
+//! NaN values are ignored: each output value is computed from the valid
+//! values in its neighbourhood only. Positions of NaN values are kept, so
+//! that masked pixels stay masked.
+use ndarray::{ArrayD, Dimension, IxDyn};
+
+/// Make a normalized 1D Gaussian kernel with standard deviation `sigma` (in
+/// pixels), truncated at 4 sigma.
+pub fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (4.0 * sigma).ceil() as isize;
+    let kernel: Vec<_> = (-radius..=radius)
+        .map(|i| {
+            let x = i as f32 / sigma;
+            (-0.5 * x * x).exp()
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|w| w / sum).collect()
+}
+
+/// Make a normalized 1D boxcar kernel of `width` pixels.
+pub fn boxcar_kernel(width: usize) -> Vec<f32> {
+    vec![1.0 / width as f32; width]
+}
+
+/// Make an n-dimensional kernel out of a 1D kernel, to be applied along
+/// `axis` (in ndarray order) of an array with `ndim` dimensions.
+pub fn kernel_along_axis(kernel: &[f32], ndim: usize, axis: usize) -> ArrayD<f32> {
+    let mut shape = vec![1; ndim];
+    shape[axis] = kernel.len();
+    ArrayD::from_shape_vec(shape, kernel.to_vec()).expect("Shape matches kernel length")
+}
+
+/// Make an n-dimensional kernel out of a 1D kernel, to be applied on the
+/// plane made of the two last axes (in ndarray order) of an array with
+/// `ndim` dimensions. The kernel is the outer product of `kernel` with
+/// itself.
+pub fn kernel_on_plane(kernel: &[f32], ndim: usize) -> ArrayD<f32> {
+    let mut shape = vec![1; ndim];
+    shape[ndim - 2] = kernel.len();
+    shape[ndim - 1] = kernel.len();
+    let values = kernel
+        .iter()
+        .flat_map(|a| kernel.iter().map(move |b| a * b))
+        .collect();
+    ArrayD::from_shape_vec(shape, values).expect("Shape matches kernel length")
+}
+
+/// Convolve `data` with `kernel`. Both arrays must have the same number of
+/// dimensions. The kernel is centered on index `len / 2` along each axis.
+///
+/// If the sum of the kernel is not zero, the result is renormalized by the
+/// weights of the valid values (i.e. neither NaN, nor out of the array), so
+/// that smoothing is not biased by NaN values or on edges.
+pub fn convolve(data: &ArrayD<f32>, kernel: &ArrayD<f32>) -> ArrayD<f32> {
+    assert_eq!(data.ndim(), kernel.ndim(), "Dimensions must match");
+
+    let taps = kernel_taps(kernel);
+    let total: f32 = taps.iter().map(|&(_, w)| w).sum();
+    let renormalize = total.abs() > ::std::f32::EPSILON;
+
+    let mut neighbour = vec![0; data.ndim()];
+    ArrayD::from_shape_fn(data.raw_dim(), |index| {
+        if data[&index].is_nan() {
+            return ::std::f32::NAN;
+        }
+        let mut acc = 0.0;
+        let mut weights = 0.0;
+        for &(ref offset, w) in &taps {
+            if let Some(v) = neighbour_value(data, &index, offset, &mut neighbour) {
+                if !v.is_nan() {
+                    acc += w * v;
+                    weights += w;
+                }
+            }
+        }
+        if !renormalize {
+            acc
+        } else if weights.abs() > ::std::f32::EPSILON {
+            acc * total / weights
+        } else {
+            ::std::f32::NAN
+        }
+    })
+}
+
+/// Apply a median filter on `data`. `window` contains the width of the
+/// window along each axis. The window is centered on index `width / 2`.
+pub fn median_filter(data: &ArrayD<f32>, window: &[usize]) -> ArrayD<f32> {
+    assert_eq!(data.ndim(), window.len(), "Dimensions must match");
+
+    let taps = kernel_taps(&ArrayD::from_elem(IxDyn(window), 1.0));
+    let mut neighbour = vec![0; data.ndim()];
+    let mut values = Vec::with_capacity(taps.len());
+    ArrayD::from_shape_fn(data.raw_dim(), |index| {
+        if data[&index].is_nan() {
+            return ::std::f32::NAN;
+        }
+        values.clear();
+        for &(ref offset, _) in &taps {
+            if let Some(v) = neighbour_value(data, &index, offset, &mut neighbour) {
+                if !v.is_nan() {
+                    values.push(v);
+                }
+            }
+        }
+        median(&mut values)
+    })
+}
+
+/// Compute median of `values`. `values` must not contain NaN.
+pub fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return ::std::f32::NAN;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).expect("No NaN"));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Get non-zero values of `kernel` with the offset of the value they weight
+/// in the convolution, i.e. their offset from the center of the kernel,
+/// flipped.
+fn kernel_taps(kernel: &ArrayD<f32>) -> Vec<(Vec<isize>, f32)> {
+    let center: Vec<_> = kernel
+        .shape()
+        .iter()
+        .map(|len| (len / 2) as isize)
+        .collect();
+    kernel
+        .indexed_iter()
+        .filter(|&(_, w)| *w != 0.0)
+        .map(|(index, w)| {
+            let offset = index
+                .slice()
+                .iter()
+                .zip(&center)
+                .map(|(i, c)| c - *i as isize)
+                .collect();
+            (offset, *w)
+        })
+        .collect()
+}
+
+/// Get value at `index + offset` in `data`, or `None` if out of bounds.
+/// `buf` is used to store the index of the neighbour.
+fn neighbour_value(
+    data: &ArrayD<f32>,
+    index: &IxDyn,
+    offset: &[isize],
+    buf: &mut Vec<usize>,
+) -> Option<f32> {
+    for (k, (i, o)) in index.slice().iter().zip(offset).enumerate() {
+        let j = *i as isize + o;
+        if j < 0 || j as usize >= data.shape()[k] {
+            return None;
+        }
+        buf[k] = j as usize;
+    }
+    Some(data[buf.as_slice()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        boxcar_kernel, convolve, gaussian_kernel, kernel_along_axis, kernel_on_plane, median,
+        median_filter,
+    };
+    use ndarray::ArrayD;
+    use std::f32::NAN;
+    use test_util::assert_close;
+
+    fn array(values: &[f32]) -> ArrayD<f32> {
+        ArrayD::from_shape_vec(vec![values.len()], values.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_gaussian_kernel() {
+        let kernel = gaussian_kernel(1.0);
+        assert_eq!(kernel.len(), 9);
+        let sum: f32 = kernel.iter().sum();
+        assert_close(&[sum], &[1.0], 1e-6);
+        for i in 0..4 {
+            assert_close(&[kernel[i]], &[kernel[8 - i]], 1e-6);
+            assert!(kernel[i] < kernel[i + 1]);
+        }
+        assert_eq!(gaussian_kernel(0.3).len(), 5);
+    }
+
+    #[test]
+    fn test_boxcar_kernel() {
+        assert_eq!(boxcar_kernel(4), vec![0.25; 4]);
+    }
+
+    #[test]
+    fn test_kernel_shapes() {
+        let along = kernel_along_axis(&[1.0, 2.0, 3.0], 3, 1);
+        assert_eq!(along.shape(), &[1, 3, 1]);
+        assert_close(along.as_slice().unwrap(), &[1.0, 2.0, 3.0], 1e-5);
+
+        let plane = kernel_on_plane(&[1.0, 2.0], 3);
+        assert_eq!(plane.shape(), &[1, 2, 2]);
+        assert_close(plane.as_slice().unwrap(), &[1.0, 2.0, 2.0, 4.0], 1e-5);
+    }
+
+    #[test]
+    fn test_convolve_ignores_nan() {
+        let kernel = array(&boxcar_kernel(3));
+        let out = convolve(&array(&[0.0, 0.0, 3.0, 0.0, NAN]), &kernel);
+        // NaN is kept, its neighbour is computed from valid values only
+        assert_close(out.as_slice().unwrap(), &[0.0, 1.0, 1.0, 1.5, NAN], 1e-5);
+
+        let out = convolve(&array(&[NAN, 2.0, NAN]), &kernel);
+        assert_close(out.as_slice().unwrap(), &[NAN, 2.0, NAN], 1e-5);
+    }
+
+    #[test]
+    fn test_convolve_edges() {
+        let kernel = array(&boxcar_kernel(3));
+        let out = convolve(&array(&[3.0, 0.0, 0.0]), &kernel);
+        assert_close(out.as_slice().unwrap(), &[1.5, 1.0, 0.0], 1e-5);
+
+        // Kernel is flipped. Without any valid value under it, result is NaN.
+        let shift = array(&[0.0, 0.0, 1.0]);
+        let out = convolve(&array(&[1.0, 2.0, 3.0]), &shift);
+        assert_close(out.as_slice().unwrap(), &[NAN, 1.0, 2.0], 1e-5);
+    }
+
+    #[test]
+    fn test_convolve_zero_sum_kernel() {
+        // Zero-sum kernels are not renormalized, even on edges
+        let kernel = array(&[-1.0, 0.0, 1.0]);
+        let out = convolve(&array(&[1.0, 2.0, 3.0]), &kernel);
+        assert_close(out.as_slice().unwrap(), &[-2.0, -2.0, 2.0], 1e-5);
+    }
+
+    #[test]
+    fn test_convolve_plane() {
+        let data = ArrayD::from_shape_vec(
+            vec![3, 3],
+            vec![0.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, NAN],
+        )
+        .unwrap();
+        let out = convolve(&data, &kernel_on_plane(&[0.25, 0.5, 0.25], 2));
+        assert!(out[[2, 2]].is_nan());
+        // The NaN corner weights 0.0625 and only the 2x2 top-left part of
+        // the kernel is in the array on the first pixel
+        assert_close(
+            &[out[[1, 1]], out[[0, 0]]],
+            &[4.0 * 0.25 / 0.9375, 4.0 * 0.0625 / 0.5625],
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn test_median_filter() {
+        let out = median_filter(&array(&[1.0, NAN, 5.0, 2.0, 8.0]), &[3]);
+        assert_close(out.as_slice().unwrap(), &[1.0, NAN, 3.5, 5.0, 5.0], 1e-5);
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert!(median(&mut []).is_nan());
+    }
+}
@@ -0,0 +1,26 @@
+//! Conversions of wavelengths between air and vacuum.
+//!
+//! The refractive index of air is taken from Morton (2000), "Atomic Data for
+//! Resonance Absorption Lines. II.", ApJS 130, 403, as adopted by the IAU.
+//! Conversions are valid for wavelengths above 2000 Angstrom. Wavelengths are
+//! given in Angstrom.
+
+/// Convert vacuum wavelength `wavelength` into air wavelength.
+pub fn vacuum_to_air(wavelength: f64) -> f64 {
+    let s2 = (1e4 / wavelength).powi(2);
+    let n = 1.0 + 0.000_083_425_4 + 0.024_061_47 / (130.0 - s2) + 0.000_159_98 / (38.9 - s2);
+    wavelength / n
+}
+
+/// Convert air wavelength `wavelength` into vacuum wavelength.
+///
+/// Use the inverse of [`vacuum_to_air`](fn.vacuum_to_air.html) derived by
+/// N. Piskunov. A round trip is exact to about 1e-12 relative.
+pub fn air_to_vacuum(wavelength: f64) -> f64 {
+    let s2 = (1e4 / wavelength).powi(2);
+    let n = 1.0
+        + 0.000_083_366_242_120_83
+        + 0.024_089_268_699_68 / (130.106_592_452_2 - s2)
+        + 0.000_159_974_089_489_7 / (38.925_687_932_93 - s2);
+    wavelength * n
+}
@@ -0,0 +1,200 @@
+//! Robust statistics on sets of values.
+
+use smooth;
+
+/// Summary statistics of a set of values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Statistics {
+    /// Number of values used
+    pub count: usize,
+    pub mean: f32,
+    pub median: f32,
+    /// Standard deviation
+    pub std: f32,
+    /// Median absolute deviation from the median (not scaled to the
+    /// standard deviation of a normal distribution)
+    pub mad: f32,
+}
+
+impl Statistics {
+    /// Compute the statistics of `values`. NaN values are ignored.
+    ///
+    /// All statistics are NaN if there is no value.
+    pub fn new(values: &[f32]) -> Statistics {
+        let mut values: Vec<_> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+        let count = values.len();
+        let n = count as f32;
+        let mean = values.iter().sum::<f32>() / n;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n;
+        let median = smooth::median(&mut values);
+        let mut deviations: Vec<_> = values.iter().map(|v| (v - median).abs()).collect();
+        Statistics {
+            count,
+            mean,
+            median,
+            std: variance.sqrt(),
+            mad: smooth::median(&mut deviations),
+        }
+    }
+
+    /// Compute the statistics of `values` after sigma clipping. NaN values
+    /// are ignored.
+    ///
+    /// Values farther than `sigma` standard deviations from the median are
+    /// rejected, and the statistics are computed again on the remaining
+    /// values. This is repeated until no value is rejected, or at most
+    /// `iterations` times.
+    pub fn sigma_clipped(values: &[f32], sigma: f32, iterations: usize) -> Statistics {
+        let mut values: Vec<_> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+        let mut stats = Statistics::new(&values);
+        for _ in 0..iterations {
+            let threshold = sigma * stats.std;
+            let count = values.len();
+            values.retain(|v| (v - stats.median).abs() <= threshold);
+            if values.len() == count {
+                break;
+            }
+            stats = Statistics::new(&values);
+        }
+        stats
+    }
+}
+
+/// A statistic computed by [`Statistic::compute`](#method.compute).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Statistic {
+    Mean,
+    Median,
+    Std,
+    Mad,
+    ClippedMean,
+    ClippedMedian,
+    ClippedStd,
+}
+
+impl Statistic {
+    /// Names of the statistics, as accepted by
+    /// [`Statistic::from_name`](#method.from_name).
+    pub const NAMES: &'static [&'static str] = &[
+        "mean",
+        "median",
+        "std",
+        "mad",
+        "clipped_mean",
+        "clipped_median",
+        "clipped_std",
+    ];
+
+    /// Get the statistic with the provided name (case-insensitive).
+    pub fn from_name(name: &str) -> Option<Statistic> {
+        match name.trim().to_lowercase().as_str() {
+            "mean" => Some(Statistic::Mean),
+            "median" => Some(Statistic::Median),
+            "std" => Some(Statistic::Std),
+            "mad" => Some(Statistic::Mad),
+            "clipped_mean" => Some(Statistic::ClippedMean),
+            "clipped_median" => Some(Statistic::ClippedMedian),
+            "clipped_std" => Some(Statistic::ClippedStd),
+            _ => None,
+        }
+    }
+
+    /// Compute the statistic over `values`. `sigma` and `iterations` are
+    /// used for sigma-clipped statistics only.
+    pub fn compute(self, values: &[f32], sigma: f32, iterations: usize) -> f32 {
+        match self {
+            Statistic::Mean => Statistics::new(values).mean,
+            Statistic::Median => Statistics::new(values).median,
+            Statistic::Std => Statistics::new(values).std,
+            Statistic::Mad => Statistics::new(values).mad,
+            Statistic::ClippedMean => Statistics::sigma_clipped(values, sigma, iterations).mean,
+            Statistic::ClippedMedian => Statistics::sigma_clipped(values, sigma, iterations).median,
+            Statistic::ClippedStd => Statistics::sigma_clipped(values, sigma, iterations).std,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Statistic, Statistics};
+    use std::f32::NAN;
+    use test_util::assert_close;
+
+    /// 1 to 9, and an outlier
+    const VALUES: [f32; 10] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 100.0];
+
+    #[test]
+    fn test_statistics() {
+        let stats = Statistics::new(&VALUES);
+        assert_eq!(stats.count, 10);
+        assert_eq!(stats.mean, 14.5);
+        assert_eq!(stats.median, 5.5);
+        assert_close(&[stats.std], &[818.25f32.sqrt()], 1e-6);
+        assert_eq!(stats.mad, 2.5);
+    }
+
+    #[test]
+    fn test_nan_values() {
+        let stats = Statistics::new(&[NAN, 3.0, 1.0, NAN]);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.mean, 2.0);
+        assert_eq!(stats.median, 2.0);
+        assert_eq!(stats.std, 1.0);
+
+        for stats in &[Statistics::new(&[]), Statistics::new(&[NAN])] {
+            assert_eq!(stats.count, 0);
+            assert!(stats.mean.is_nan() && stats.median.is_nan());
+            assert!(stats.std.is_nan() && stats.mad.is_nan());
+        }
+    }
+
+    #[test]
+    fn test_sigma_clipped() {
+        let stats = Statistics::sigma_clipped(&VALUES, 2.0, 5);
+        assert_eq!(stats.count, 9);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.median, 5.0);
+        assert_close(&[stats.std], &[(20.0f32 / 3.0).sqrt()], 1e-6);
+
+        // Without iteration, nothing is clipped
+        assert_eq!(
+            Statistics::sigma_clipped(&VALUES, 2.0, 0),
+            Statistics::new(&VALUES)
+        );
+        // Clipping goes on while values are rejected
+        let once = Statistics::sigma_clipped(&VALUES, 1.0, 1);
+        let twice = Statistics::sigma_clipped(&VALUES, 1.0, 2);
+        assert_eq!(once.count, 9);
+        assert!(twice.count < once.count);
+    }
+
+    #[test]
+    fn test_statistic_names() {
+        for name in Statistic::NAMES {
+            assert!(Statistic::from_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(
+            Statistic::from_name(" Clipped_Median "),
+            Some(Statistic::ClippedMedian)
+        );
+        assert_eq!(Statistic::from_name("mode"), None);
+    }
+
+    #[test]
+    fn test_compute() {
+        let stats = Statistics::new(&VALUES);
+        let clipped = Statistics::sigma_clipped(&VALUES, 2.0, 5);
+        let cases = [
+            (Statistic::Mean, stats.mean),
+            (Statistic::Median, stats.median),
+            (Statistic::Std, stats.std),
+            (Statistic::Mad, stats.mad),
+            (Statistic::ClippedMean, clipped.mean),
+            (Statistic::ClippedMedian, clipped.median),
+            (Statistic::ClippedStd, clipped.std),
+        ];
+        for &(statistic, expected) in &cases {
+            assert_eq!(statistic.compute(&VALUES, 2.0, 5), expected);
+        }
+    }
+}
@@ -0,0 +1,660 @@
+use std::error;
+use std::fmt;
+
+use fitrs::{FitsData, Hdu, HeaderValue};
+
+use unit::{read_string, read_unit, Unit};
+use wcs::read_float;
+
+/// A table read from an ASCII (`TABLE`) or a binary (`BINTABLE`) FITS
+/// extension.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    rows: usize,
+    columns: Vec<Column>,
+}
+
+/// A named column of a [Table](struct.Table.html).
+///
+/// Each cell of a column contains `repeat` values. All values are stored in
+/// a flat vector, row after row.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Column {
+    name: String,
+    unit: Unit,
+    repeat: usize,
+    data: ColumnData,
+}
+
+/// Values contained in a column.
+///
+/// Integer columns with null values or with a scaling (`TSCALn`, `TZEROn`)
+/// are read as floats, null values being replaced with NaN.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColumnData {
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+    Str(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum TableReadError {
+    NotATable,
+    MissingKeyword(String),
+    UnsupportedFormat { column: String, format: String },
+    UnknownColumn(String),
+    UnexpectedDataSize { expected: usize, got: usize },
+}
+
+impl fmt::Display for TableReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TableReadError::NotATable => {
+                write!(f, "HDU is not an ASCII or a binary table extension.")
+            }
+            TableReadError::MissingKeyword(ref key) => {
+                write!(f, "Missing or invalid keyword '{}' in table header.", key)
+            }
+            TableReadError::UnsupportedFormat {
+                ref column,
+                ref format,
+            } => write!(
+                f,
+                "Unsupported format '{}' for column '{}'.",
+                format, column
+            ),
+            TableReadError::UnknownColumn(ref name) => {
+                write!(f, "No column named '{}' in table.", name)
+            }
+            TableReadError::UnexpectedDataSize { expected, got } => write!(
+                f,
+                "Expected {} bytes of table data, but got {} bytes.",
+                expected, got
+            ),
+        }
+    }
+}
+
+impl error::Error for TableReadError {
+    fn description(&self) -> &'static str {
+        "TableReadError"
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum TableKind {
+    Ascii,
+    Binary,
+}
+
+/// Layout of a column inside a row.
+struct Field {
+    /// Position of the first byte of the field in a row
+    offset: usize,
+    /// Number of values in the field
+    repeat: usize,
+    /// Type code of the field, as in TFORMn
+    code: char,
+    /// Width in bytes of one value (ASCII tables) or of the whole field
+    /// (binary tables)
+    width: usize,
+}
+
+/// Values of a column before null values and scaling are applied.
+enum RawData {
+    Integer(Vec<Option<i64>>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+    Str(Vec<String>),
+}
+
+impl Table {
+    /// Read the columns named `names` from the table contained in `hdu`.
+    /// Names are case-insensitive, as specified by the FITS standard. If
+    /// `names` is empty, all columns with a supported format are read.
+    pub fn from_hdu(hdu: &Hdu, names: &[&str]) -> Result<Table, TableReadError> {
+        let kind = match read_string(hdu, "XTENSION") {
+            Some(ref xtension) if xtension.trim() == "BINTABLE" => TableKind::Binary,
+            Some(ref xtension) if xtension.trim() == "TABLE" => TableKind::Ascii,
+            _ => return Err(TableReadError::NotATable),
+        };
+        let row_width = read_usize(hdu, "NAXIS1")?;
+        let rows = read_usize(hdu, "NAXIS2")?;
+        let tfields = read_usize(hdu, "TFIELDS")?;
+
+        let bytes: Vec<u8> = match hdu.read_data() {
+            FitsData::Characters(ref data) => data.data.iter().map(|c| *c as u32 as u8).collect(),
+            _ => return Err(TableReadError::NotATable),
+        };
+        if bytes.len() < row_width * rows {
+            return Err(TableReadError::UnexpectedDataSize {
+                expected: row_width * rows,
+                got: bytes.len(),
+            });
+        }
+
+        let mut offset = 0;
+        let mut all_columns = Vec::with_capacity(tfields);
+        for n in 1..=tfields {
+            let key = format!("TFORM{}", n);
+            let format =
+                read_string(hdu, &key).ok_or_else(|| TableReadError::MissingKeyword(key))?;
+            let name = read_string(hdu, &format!("TTYPE{}", n))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_else(|| format!("col{}", n));
+            let field = match kind {
+                TableKind::Binary => {
+                    let field = parse_binary_format(&format, offset);
+                    if let Some(ref field) = field {
+                        offset += field.width;
+                    } else {
+                        // Cannot know the width of the next fields
+                        all_columns.push((n, name, format, None));
+                        break;
+                    }
+                    field
+                }
+                TableKind::Ascii => {
+                    let key = format!("TBCOL{}", n);
+                    let tbcol = read_usize(hdu, &key)?;
+                    if tbcol == 0 {
+                        return Err(TableReadError::MissingKeyword(key));
+                    }
+                    parse_ascii_format(&format, tbcol - 1)
+                }
+            };
+            all_columns.push((n, name, format, field));
+        }
+
+        let selected: Vec<_> = if names.is_empty() {
+            all_columns
+                .into_iter()
+                .filter(|&(_, _, _, ref field)| field.as_ref().map_or(false, Field::is_supported))
+                .collect()
+        } else {
+            let mut selected = Vec::with_capacity(names.len());
+            for name in names {
+                let position = all_columns
+                    .iter()
+                    .position(|&(_, ref col_name, _, _)| col_name.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| TableReadError::UnknownColumn((*name).to_owned()))?;
+                selected.push(all_columns.remove(position));
+            }
+            selected
+        };
+
+        let mut columns = Vec::with_capacity(selected.len());
+        for (n, name, format, field) in selected {
+            let field = match field {
+                Some(ref field) if field.is_supported() => field,
+                _ => {
+                    return Err(TableReadError::UnsupportedFormat {
+                        column: name,
+                        format,
+                    })
+                }
+            };
+            if field.offset + field.byte_width(kind) > row_width {
+                return Err(TableReadError::UnexpectedDataSize {
+                    expected: field.offset + field.byte_width(kind),
+                    got: row_width,
+                });
+            }
+            let raw = match kind {
+                TableKind::Binary => field.read_binary(&bytes, row_width, rows),
+                TableKind::Ascii => field.read_ascii(&bytes, row_width, rows),
+            };
+            let tnull = match hdu.value(&format!("TNULL{}", n)) {
+                Some(HeaderValue::IntegerNumber(i)) => Some(i64::from(*i)),
+                Some(HeaderValue::CharacterString(s)) => s.trim().parse().ok(),
+                _ => None,
+            };
+            let tscal = read_float(hdu, &format!("TSCAL{}", n)).unwrap_or(1.0);
+            let tzero = read_float(hdu, &format!("TZERO{}", n)).unwrap_or(0.0);
+            columns.push(Column {
+                unit: read_unit(hdu, &format!("TUNIT{}", n)),
+                repeat: if field.code == 'A' { 1 } else { field.repeat },
+                data: raw.into_column_data(tnull, tscal, tzero),
+                name,
+            });
+        }
+
+        Ok(Table { rows, columns })
+    }
+
+    /// Number of rows in the table.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Columns of the table.
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Get column with the given name (case-insensitive).
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Column {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    /// Number of values in each cell.
+    pub fn repeat(&self) -> usize {
+        self.repeat
+    }
+
+    pub fn data(&self) -> &ColumnData {
+        &self.data
+    }
+
+    /// Get all values of the column as floats. Return `None` for string
+    /// columns.
+    pub fn floats(&self) -> Option<Vec<f64>> {
+        match self.data {
+            ColumnData::Integer(ref data) => Some(data.iter().map(|i| *i as f64).collect()),
+            ColumnData::Float(ref data) => Some(data.clone()),
+            ColumnData::Bool(ref data) => {
+                Some(data.iter().map(|b| if *b { 1.0 } else { 0.0 }).collect())
+            }
+            ColumnData::Str(_) => None,
+        }
+    }
+
+    /// Format the cell at the given `row`. Cells with several values are
+    /// formatted as `[v1 v2 ...]`.
+    pub fn format_cell(&self, row: usize) -> String {
+        fn join<T: fmt::Display>(values: &[T]) -> String {
+            if values.len() == 1 {
+                format!("{}", values[0])
+            } else {
+                let values: Vec<_> = values.iter().map(|v| format!("{}", v)).collect();
+                format!("[{}]", values.join(" "))
+            }
+        }
+
+        let start = row * self.repeat;
+        let end = start + self.repeat;
+        match self.data {
+            ColumnData::Integer(ref data) => join(&data[start..end]),
+            ColumnData::Float(ref data) => join(&data[start..end]),
+            ColumnData::Bool(ref data) => join(&data[start..end]),
+            ColumnData::Str(ref data) => join(&data[start..end]),
+        }
+    }
+}
+
+impl Field {
+    fn is_supported(&self) -> bool {
+        match self.code {
+            'L' | 'X' | 'B' | 'I' | 'J' | 'K' | 'A' | 'E' | 'D' | 'F' => true,
+            _ => false,
+        }
+    }
+
+    /// Number of bytes of the field in a row.
+    fn byte_width(&self, kind: TableKind) -> usize {
+        match kind {
+            TableKind::Binary => self.width,
+            TableKind::Ascii => self.width * self.repeat,
+        }
+    }
+
+    fn read_binary(&self, bytes: &[u8], row_width: usize, rows: usize) -> RawData {
+        let fields = (0..rows).map(|row| {
+            let start = row * row_width + self.offset;
+            &bytes[start..start + self.width]
+        });
+        match self.code {
+            'L' => RawData::Bool(
+                fields
+                    .flat_map(|field| field.iter().map(|b| *b == b'T'))
+                    .collect(),
+            ),
+            'X' => {
+                let bits = self.repeat;
+                RawData::Bool(
+                    fields
+                        .flat_map(|field| {
+                            (0..bits).map(move |i| field[i / 8] & (0x80 >> (i % 8)) != 0)
+                        })
+                        .collect(),
+                )
+            }
+            'B' => RawData::Integer(
+                fields
+                    .flat_map(|field| field.iter().map(|b| Some(i64::from(*b))))
+                    .collect(),
+            ),
+            'I' => RawData::Integer(
+                fields
+                    .flat_map(|field| field.chunks(2).map(|c| Some(i64::from(be_u16(c) as i16))))
+                    .collect(),
+            ),
+            'J' => RawData::Integer(
+                fields
+                    .flat_map(|field| field.chunks(4).map(|c| Some(i64::from(be_u32(c) as i32))))
+                    .collect(),
+            ),
+            'K' => RawData::Integer(
+                fields
+                    .flat_map(|field| field.chunks(8).map(|c| Some(be_u64(c) as i64)))
+                    .collect(),
+            ),
+            'E' => RawData::Float(
+                fields
+                    .flat_map(|field| {
+                        field
+                            .chunks(4)
+                            .map(|c| f64::from(f32::from_bits(be_u32(c))))
+                    })
+                    .collect(),
+            ),
+            'D' => RawData::Float(
+                fields
+                    .flat_map(|field| field.chunks(8).map(|c| f64::from_bits(be_u64(c))))
+                    .collect(),
+            ),
+            'A' => RawData::Str(fields.map(trimmed_string).collect()),
+            code => unreachable!("Unsupported binary table format '{}'", code),
+        }
+    }
+
+    fn read_ascii(&self, bytes: &[u8], row_width: usize, rows: usize) -> RawData {
+        let fields = (0..rows).map(|row| {
+            let start = row * row_width + self.offset;
+            trimmed_string(&bytes[start..start + self.width])
+        });
+        match self.code {
+            'A' => RawData::Str(fields.collect()),
+            'I' => RawData::Integer(fields.map(|field| field.trim().parse().ok()).collect()),
+            'F' | 'E' | 'D' => RawData::Float(
+                fields
+                    .map(|field| {
+                        field
+                            .trim()
+                            .replace('D', "E")
+                            .parse()
+                            .unwrap_or(::std::f64::NAN)
+                    })
+                    .collect(),
+            ),
+            code => unreachable!("Unsupported ASCII table format '{}'", code),
+        }
+    }
+}
+
+impl RawData {
+    fn into_column_data(self, tnull: Option<i64>, tscal: f64, tzero: f64) -> ColumnData {
+        let scaled = tscal != 1.0 || tzero != 0.0;
+        match self {
+            RawData::Integer(data) => {
+                let data: Vec<_> = data
+                    .into_iter()
+                    .map(|i| match (i, tnull) {
+                        (Some(i), Some(tnull)) if i == tnull => None,
+                        (i, _) => i,
+                    })
+                    .collect();
+                let has_null = data.iter().any(Option::is_none);
+                // Unsigned integers are stored with an integral offset
+                let integral_offset = tscal == 1.0 && tzero.fract() == 0.0 && tzero.abs() <= 2e9;
+                if !has_null && (!scaled || integral_offset) {
+                    ColumnData::Integer(
+                        data.into_iter()
+                            .map(|i| i.unwrap() + tzero as i64)
+                            .collect(),
+                    )
+                } else {
+                    ColumnData::Float(
+                        data.into_iter()
+                            .map(|i| match i {
+                                Some(i) => i as f64 * tscal + tzero,
+                                None => ::std::f64::NAN,
+                            })
+                            .collect(),
+                    )
+                }
+            }
+            RawData::Float(data) => {
+                if scaled {
+                    ColumnData::Float(data.into_iter().map(|f| f * tscal + tzero).collect())
+                } else {
+                    ColumnData::Float(data)
+                }
+            }
+            RawData::Bool(data) => ColumnData::Bool(data),
+            RawData::Str(data) => ColumnData::Str(data),
+        }
+    }
+}
+
+/// Parse TFORMn keyword of a binary table, e.g. "1E", "20A" or "D".
+fn parse_binary_format(format: &str, offset: usize) -> Option<Field> {
+    let format = format.trim();
+    let digits = format.chars().take_while(char::is_ascii_digit).count();
+    let repeat = if digits == 0 {
+        1
+    } else {
+        format[..digits].parse().ok()?
+    };
+    let code = format[digits..].chars().next()?;
+    let width = match code {
+        'L' | 'B' | 'A' => repeat,
+        'X' => (repeat + 7) / 8,
+        'I' => 2 * repeat,
+        'J' | 'E' => 4 * repeat,
+        'K' | 'D' | 'C' | 'P' => 8 * repeat,
+        'M' | 'Q' => 16 * repeat,
+        _ => return None,
+    };
+    Some(Field {
+        offset,
+        repeat,
+        code,
+        width,
+    })
+}
+
+/// Parse TFORMn keyword of an ASCII table, e.g. "A10", "I6" or "E15.7".
+fn parse_ascii_format(format: &str, offset: usize) -> Option<Field> {
+    let format = format.trim();
+    let code = format.chars().next()?;
+    let width = format[1..].split('.').next()?.parse().ok()?;
+    Some(Field {
+        offset,
+        repeat: 1,
+        code,
+        width,
+    })
+}
+
+fn read_usize(hdu: &Hdu, key: &str) -> Result<usize, TableReadError> {
+    match hdu.value(key) {
+        Some(HeaderValue::IntegerNumber(i)) if *i >= 0 => Ok(*i as usize),
+        _ => Err(TableReadError::MissingKeyword(key.to_owned())),
+    }
+}
+
+fn trimmed_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|b| *b == 0)
+        .unwrap_or_else(|| bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim_end().to_owned()
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    let mut buf = [0; 2];
+    buf.copy_from_slice(bytes);
+    u16::from_be_bytes(buf)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_ascii_format, parse_binary_format, Column, ColumnData, RawData};
+    use unit::Unit;
+
+    #[test]
+    fn test_parse_binary_format() {
+        let field = parse_binary_format("2E", 3).unwrap();
+        assert_eq!(
+            (field.offset, field.repeat, field.code, field.width),
+            (3, 2, 'E', 8)
+        );
+        let field = parse_binary_format(" D ", 0).unwrap();
+        assert_eq!((field.repeat, field.code, field.width), (1, 'D', 8));
+        let field = parse_binary_format("20A", 0).unwrap();
+        assert_eq!((field.repeat, field.width), (20, 20));
+        let field = parse_binary_format("10X", 0).unwrap();
+        assert_eq!((field.repeat, field.width), (10, 2));
+
+        // Width of complex and array descriptor columns is known, but their
+        // values cannot be read
+        let field = parse_binary_format("1PE(5)", 0).unwrap();
+        assert_eq!(field.width, 8);
+        assert!(!field.is_supported());
+        assert!(!parse_binary_format("1C", 0).unwrap().is_supported());
+
+        assert!(parse_binary_format("3Z", 0).is_none());
+        assert!(parse_binary_format("", 0).is_none());
+    }
+
+    #[test]
+    fn test_parse_ascii_format() {
+        let field = parse_ascii_format("E15.7", 4).unwrap();
+        assert_eq!(
+            (field.offset, field.repeat, field.code, field.width),
+            (4, 1, 'E', 15)
+        );
+        assert_eq!(parse_ascii_format("A10", 0).unwrap().width, 10);
+        assert_eq!(parse_ascii_format("I6", 0).unwrap().width, 6);
+        assert!(parse_ascii_format("I", 0).is_none());
+        assert!(parse_ascii_format("", 0).is_none());
+    }
+
+    #[test]
+    fn test_read_binary() {
+        // Two rows of a 1I field followed by a 2L field
+        let bytes = [0xff, 0xfe, b'T', b'F', 0x00, 0x07, b'F', b'T'];
+        let short = parse_binary_format("1I", 0).unwrap();
+        match short.read_binary(&bytes, 4, 2) {
+            RawData::Integer(data) => assert_eq!(data, vec![Some(-2), Some(7)]),
+            _ => panic!("Expected integers"),
+        }
+        let logical = parse_binary_format("2L", 2).unwrap();
+        match logical.read_binary(&bytes, 4, 2) {
+            RawData::Bool(data) => assert_eq!(data, vec![true, false, false, true]),
+            _ => panic!("Expected booleans"),
+        }
+        let bits = parse_binary_format("3X", 0).unwrap();
+        match bits.read_binary(&[0b1010_0000, 0b0110_0000], 1, 2) {
+            RawData::Bool(data) => assert_eq!(data, vec![true, false, true, false, true, true]),
+            _ => panic!("Expected booleans"),
+        }
+        let string = parse_binary_format("4A", 0).unwrap();
+        match string.read_binary(b"ab\0\0cd  ", 4, 2) {
+            RawData::Str(data) => assert_eq!(data, vec!["ab", "cd"]),
+            _ => panic!("Expected strings"),
+        }
+    }
+
+    #[test]
+    fn test_read_ascii() {
+        // Two rows of I3, D6.1 and A4 fields
+        let bytes = b" 121.5D2 abc  -3bad     xy";
+        let integer = parse_ascii_format("I3", 0).unwrap();
+        match integer.read_ascii(bytes, 13, 2) {
+            RawData::Integer(data) => assert_eq!(data, vec![Some(12), Some(-3)]),
+            _ => panic!("Expected integers"),
+        }
+        let float = parse_ascii_format("D6.1", 3).unwrap();
+        match float.read_ascii(bytes, 13, 2) {
+            RawData::Float(data) => {
+                assert_eq!(data[0], 150.0);
+                assert!(data[1].is_nan());
+            }
+            _ => panic!("Expected floats"),
+        }
+        let string = parse_ascii_format("A4", 9).unwrap();
+        match string.read_ascii(bytes, 13, 2) {
+            RawData::Str(data) => assert_eq!(data, vec!["abc", "  xy"]),
+            _ => panic!("Expected strings"),
+        }
+    }
+
+    #[test]
+    fn test_null_and_scaling() {
+        let raw = || RawData::Integer(vec![Some(1), Some(-99), None]);
+        match raw().into_column_data(Some(-99), 1.0, 0.0) {
+            ColumnData::Float(data) => {
+                assert_eq!(data[0], 1.0);
+                assert!(data[1].is_nan() && data[2].is_nan());
+            }
+            _ => panic!("Expected floats"),
+        }
+        match raw().into_column_data(None, 2.0, 0.5) {
+            ColumnData::Float(data) => {
+                assert_eq!(&data[..2], &[2.5, -197.5]);
+                assert!(data[2].is_nan());
+            }
+            _ => panic!("Expected floats"),
+        }
+
+        // Unsigned integers
+        let unsigned = RawData::Integer(vec![Some(-32768), Some(0)]);
+        assert_eq!(
+            unsigned.into_column_data(None, 1.0, 32768.0),
+            ColumnData::Integer(vec![0, 32768])
+        );
+        let float = RawData::Float(vec![1.0, 2.0]);
+        assert_eq!(
+            float.into_column_data(None, 10.0, 1.0),
+            ColumnData::Float(vec![11.0, 21.0])
+        );
+    }
+
+    #[test]
+    fn test_column() {
+        let column = Column {
+            name: "FLUX".to_owned(),
+            unit: Unit::parse("Jy"),
+            repeat: 2,
+            data: ColumnData::Integer(vec![1, 2, 3, 4]),
+        };
+        assert_eq!(column.format_cell(1), "[3 4]");
+        assert_eq!(column.floats(), Some(vec![1.0, 2.0, 3.0, 4.0]));
+
+        let column = Column {
+            name: "NAME".to_owned(),
+            unit: Unit::None,
+            repeat: 1,
+            data: ColumnData::Str(vec!["M31".to_owned(), "M33".to_owned()]),
+        };
+        assert_eq!(column.format_cell(1), "M33");
+        assert_eq!(column.floats(), None);
+    }
+}
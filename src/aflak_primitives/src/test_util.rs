@@ -0,0 +1,30 @@
+//! Helpers shared by the unit tests of this crate.
+use std::fmt::Debug;
+
+/// Assert that `got` and `expected` are equal up to the relative tolerance
+/// `rtol`, NaN being equal to NaN.
+///
+/// When zero is expected, `rtol` is used as an absolute tolerance instead.
+pub fn assert_close<T>(got: &[T], expected: &[T], rtol: f64)
+where
+    T: Copy + Debug + Into<f64>,
+{
+    assert_eq!(
+        got.len(),
+        expected.len(),
+        "Expected {:?}, got {:?}",
+        expected,
+        got
+    );
+    for (&g, &e) in got.iter().zip(expected) {
+        let (g, e): (f64, f64) = (g.into(), e.into());
+        let close = if e.is_nan() {
+            g.is_nan()
+        } else if e == 0.0 {
+            g.abs() <= rtol
+        } else {
+            (g - e).abs() <= rtol * e.abs()
+        };
+        assert!(close, "Expected {:?}, got {:?}", expected, got);
+    }
+}
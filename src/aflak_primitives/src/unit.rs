@@ -1,20 +1,25 @@
 use std::{fmt, ops};
 
-use fitrs::{FitsData, Hdu, HeaderValue, WCS};
+use fitrs::{FitsData, Hdu, HeaderValue};
 use ndarray::{ArrayD, ArrayView1, ArrayView2, IxDyn};
 
 use fits::{FitsArrayReadError, FitsDataToArray};
+use physical_unit::PhysicalUnit;
+use wcs::Wcs;
 
 /// A unit of measurement.
 ///
-/// Would like to extend this type to include SI units or frequent units in
-/// astrophysics.
+/// Use [`Unit::parse`](enum.Unit.html#method.parse) to make a unit out of a
+/// string.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Unit {
     /// Unit is unknown
     None,
-    /// Custom unit represented with a string
+    /// Custom unit represented with a string. Custom units are not understood
+    /// and cannot be converted.
     Custom(String),
+    /// Physical unit that can be multiplied, divided and converted.
+    Physical(PhysicalUnit),
 }
 
 impl Default for Unit {
@@ -29,18 +34,27 @@ impl Default for Unit {
 /// of the resulting value. Unit information is guaranteed to be correct as
 /// long as the inner flag `homogeneous` is true.
 ///
+/// Multiplication and division of two dimensioned values multiply and divide
+/// their units. For addition and subtraction, the right-hand side is first
+/// converted to the unit of the left-hand side. The result is not homogeneous
+/// if the units cannot be converted into one another.
+///
 /// # Examples
 ///
 /// ```rust
 /// extern crate aflak_primitives as primitives;
 /// use primitives::{Dimensioned, Unit};
 ///
-/// let meter = Unit::Custom("m".to_owned());
-/// let val1 = Dimensioned::new(1, meter.clone());
-/// let val2 = Dimensioned::new(2, meter.clone());
+/// let meter = Unit::parse("m");
+/// let val1 = Dimensioned::new(1.0, meter.clone());
+/// let val2 = Dimensioned::new(2.0, Unit::parse("km"));
 /// let sum = val1 + val2;
-/// assert!(*sum.scalar() == 3);
+/// assert!(*sum.scalar() == 2001.0);
 /// assert!(sum.unit() == &meter);
+///
+/// let time = Dimensioned::new(4.0, Unit::parse("s"));
+/// let velocity = sum / time;
+/// assert!(velocity.unit() == &Unit::parse("m/s"));
 /// ```
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Dimensioned<V> {
@@ -55,21 +69,39 @@ pub struct Dimensioned<V> {
 /// This is the main data structure used by `aflak_primitives` to represent
 /// multi-dimensional astrophysical data.
 ///
+/// The array may carry the variance of each of its values (in the square of
+/// the unit of the array). The variance is propagated by operations whose
+/// effect on uncertainties is known, and dropped by all others.
+///
+/// It may also carry a data-quality mask. Masked values (`true` in the mask)
+/// are excluded from reductions.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WcsArray {
     meta: Option<MetaWcsArray>,
     array: Dimensioned<ArrayD<f32>>,
+    #[serde(default)]
+    variance: Option<ArrayD<f32>>,
+    #[serde(default)]
+    mask: Option<ArrayD<bool>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct MetaWcsArray {
-    #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
-    // TODO: Handle serialization for WCS
-    wcs: WCS,
+    #[serde(default)]
+    wcs: Wcs,
     axes: [Axis; 4],
 }
 
+/// Unit and world coordinates of a [`WcsArray`](struct.WcsArray.html),
+/// without its data.
+///
+/// Used to edit the metadata of an image whose data is not loaded in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WcsMeta {
+    meta: Option<MetaWcsArray>,
+    unit: Unit,
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Axis {
     name: Option<String>,
@@ -90,15 +122,15 @@ impl Axis {
     }
 }
 
-fn read_unit(hdu: &Hdu, key: &str) -> Unit {
+pub(crate) fn read_unit(hdu: &Hdu, key: &str) -> Unit {
     if let Some(unit) = read_string(hdu, key) {
-        Unit::Custom(unit)
+        Unit::parse(&unit)
     } else {
         Unit::None
     }
 }
 
-fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
+pub(crate) fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
     if let Some(HeaderValue::CharacterString(string)) = hdu.value(key) {
         Some(string.to_owned())
     } else {
@@ -108,6 +140,11 @@ fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
 
 impl WcsArray {
     /// Make `WcsArray` from `Hdu` found in FITS file.
+    ///
+    /// Data is decoded by fitrs. Prefer
+    /// [FitsFile::read_image](struct.FitsFile.html#method.read_image) with
+    /// [from_hdu_and_image](#method.from_hdu_and_image) to read data from a
+    /// file, as it supports all BITPIX values and applies scaling.
     pub fn from_hdu(hdu: &Hdu) -> Result<WcsArray, FitsArrayReadError> {
         let data = hdu.read_data();
         let image = match *data {
@@ -119,45 +156,90 @@ impl WcsArray {
                 return Err(FitsArrayReadError::UnsupportedData("Characters"));
             }
         };
+        Ok(Self::from_hdu_and_image(hdu, image))
+    }
 
-        let vunit = read_unit(hdu, "BUNIT");
-        let cunit1 = read_unit(hdu, "CUNIT1");
-        let cunit2 = read_unit(hdu, "CUNIT2");
-        let cunit3 = read_unit(hdu, "CUNIT3");
-        let cunit4 = read_unit(hdu, "CUNIT4");
-        let ctype1 = read_string(hdu, "CTYPE1");
-        let ctype2 = read_string(hdu, "CTYPE2");
-        let ctype3 = read_string(hdu, "CTYPE3");
-        let ctype4 = read_string(hdu, "CTYPE4");
-        let wcs = WCS::new(hdu);
-        Ok(Self {
-            meta: Some(MetaWcsArray {
-                wcs,
-                axes: [
-                    Axis::new(ctype1, cunit1),
-                    Axis::new(ctype2, cunit2),
-                    Axis::new(ctype3, cunit3),
-                    Axis::new(ctype4, cunit4),
-                ],
-            }),
-            array: vunit.new(image),
-        })
+    /// Make `WcsArray` from the already decoded `image`, reading units and
+    /// world coordinates from the header of `hdu`.
+    pub fn from_hdu_and_image(hdu: &Hdu, image: ArrayD<f32>) -> WcsArray {
+        WcsMeta::from_hdu(hdu).with_image(image)
+    }
+
+    /// Get the unit and world coordinates of the array.
+    pub fn meta(&self) -> WcsMeta {
+        WcsMeta {
+            meta: self.meta.clone(),
+            unit: self.array.unit.clone(),
+        }
+    }
+
+    /// Replace the unit and world coordinates of the array with `meta`.
+    ///
+    /// The values, the variance and the mask are kept as they are.
+    pub fn with_meta(self, meta: WcsMeta) -> WcsArray {
+        WcsArray {
+            meta: meta.meta,
+            array: Dimensioned {
+                unit: meta.unit,
+                ..self.array
+            },
+            ..self
+        }
+    }
+
+    /// Make FITS `Hdu` out of `WcsArray`. Units and world coordinates are
+    /// written as FITS keywords.
+    pub fn to_hdu(&self) -> Hdu {
+        let arr = self.scalar();
+        // 0-dim ndarrays contain a single scalar value, however they should be
+        // treated as a 1-dimensional data array of length 1 when embedded as
+        // FITS file.
+        // FITS axes are ordered from the fastest to the slowest varying axis,
+        // i.e. in reverse order compared to ndarray.
+        let shape: Vec<_> = if arr.ndim() == 0 {
+            vec![1]
+        } else {
+            arr.shape().iter().rev().cloned().collect()
+        };
+        let mut hdu = Hdu::new(&shape, arr.iter().cloned().collect::<Vec<f32>>());
+
+        if self.array.unit != Unit::None {
+            hdu.insert("BUNIT", self.array.unit.repr());
+        }
+        if let Some(ref meta) = self.meta {
+            for (i, axis) in meta.axes.iter().enumerate().take(shape.len()) {
+                let n = i + 1;
+                if let Some(ref name) = axis.name {
+                    hdu.insert(format!("CTYPE{}", n).as_str(), name.as_str());
+                }
+                if axis.unit != Unit::None {
+                    hdu.insert(format!("CUNIT{}", n).as_str(), axis.unit.repr());
+                }
+            }
+            meta.wcs.write_to_hdu(&mut hdu, shape.len());
+        }
+        hdu
     }
 
     /// Convert position `pixel` (in pixel coordinates starting from 0) at axis
     /// number `axis` to world coordinates. Return `None` if necessary metadata
     /// is missing.
+    ///
+    /// Pixel coordinates on all the other axes are assumed to be 0. Use
+    /// [`Wcs::pix2world`](struct.Wcs.html#method.pix2world) for axes
+    /// depending on one another, e.g. rotated or celestial axes.
     pub fn pix2world(&self, axis: usize, pixel: f32) -> Option<f32> {
-        self.meta.as_ref().map(|meta| {
-            let mut input = [0.0; 4];
-            input[axis] = pixel;
-            meta.wcs.pix2world(input)[axis]
-        })
+        self.meta.as_ref().map(|meta| meta.pix2world(axis, pixel))
     }
 
     /// Make a new array missing all metadata about axes and world coordinates.
     pub fn from_array(array: Dimensioned<ArrayD<f32>>) -> Self {
-        Self { meta: None, array }
+        Self {
+            meta: None,
+            array,
+            variance: None,
+            mask: None,
+        }
     }
 
     /// Get reference to contained *n*-dimensional array.
@@ -165,6 +247,11 @@ impl WcsArray {
         self.array.scalar()
     }
 
+    /// Get the number of values on each axis of the array.
+    pub fn shape(&self) -> &[usize] {
+        self.scalar().shape()
+    }
+
     /// Get mutable reference to contained *n*-dimensional array.
     pub fn scalar_mut(&mut self) -> &mut ArrayD<f32> {
         self.array.scalar_mut()
@@ -196,43 +283,319 @@ impl WcsArray {
         self.meta.as_ref().map(|meta| meta.axes.as_ref())
     }
 
-    pub fn wcs(&self) -> Option<&WCS> {
+    pub fn wcs(&self) -> Option<&Wcs> {
         self.meta.as_ref().map(|meta| &meta.wcs)
     }
 
+    /// Get the variance of each value of the array, if known.
+    pub fn variance(&self) -> Option<&ArrayD<f32>> {
+        self.variance.as_ref()
+    }
+
+    /// Attach `variance` to the array, replacing the current variance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `variance` does not have the shape of the array.
+    pub fn with_variance(self, variance: Option<ArrayD<f32>>) -> WcsArray {
+        if let Some(ref variance) = variance {
+            assert_eq!(
+                variance.shape(),
+                self.shape(),
+                "Variance has the shape of the array"
+            );
+        }
+        WcsArray { variance, ..self }
+    }
+
+    /// Get the data-quality mask of the array, if any. Masked values are
+    /// `true`.
+    pub fn mask(&self) -> Option<&ArrayD<bool>> {
+        self.mask.as_ref()
+    }
+
+    /// Attach `mask` to the array, replacing the current mask.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mask` does not have the shape of the array.
+    pub fn with_mask(self, mask: Option<ArrayD<bool>>) -> WcsArray {
+        if let Some(ref mask) = mask {
+            assert_eq!(
+                mask.shape(),
+                self.shape(),
+                "Mask has the shape of the array"
+            );
+        }
+        WcsArray { mask, ..self }
+    }
+
+    /// Get unit of world coordinates along axis number `axis` (in FITS
+    /// order). Return `Unit::None` if necessary metadata is missing.
+    pub fn world_unit(&self, axis: usize) -> Unit {
+        world_unit(self.meta.as_ref(), axis)
+    }
+
+    /// Convert values of the array into `unit`. If the array has no unit,
+    /// `unit` is assigned to it.
+    ///
+    /// Return `None` if the current unit cannot be converted into `unit`.
+    pub fn convert_unit(&self, unit: &Unit) -> Option<WcsArray> {
+        let factor = if self.array.unit == Unit::None {
+            1.0
+        } else {
+            self.array.unit.conversion_factor(unit)?
+        };
+        Some(WcsArray {
+            meta: self.meta.clone(),
+            array: Dimensioned {
+                unit: unit.clone(),
+                ..self.array.with_new_value(self.scalar() * factor)
+            },
+            variance: self.variance.as_ref().map(|v| v * (factor * factor)),
+            mask: self.mask.clone(),
+        })
+    }
+
+    /// Convert world coordinates along axis number `axis` (in FITS order)
+    /// into `unit`. If the axis has no unit, `unit` is assigned to it.
+    ///
+    /// Return `None` if necessary metadata is missing, if the current unit
+    /// cannot be converted into `unit` or if the axis is celestial.
+    pub fn convert_world_unit(&self, axis: usize, unit: &Unit) -> Option<WcsArray> {
+        let meta = self.meta.as_ref()?;
+        let current = &meta.axes.get(axis)?.unit;
+        let factor = if *current == Unit::None {
+            1.0
+        } else {
+            current.conversion_factor(unit)?
+        };
+        let wcs = meta.wcs.scale_axis(axis, f64::from(factor))?;
+        let mut axes = meta.axes.clone();
+        axes[axis].unit = unit.clone();
+        Some(WcsArray {
+            meta: Some(MetaWcsArray { wcs, axes }),
+            array: self.array.clone(),
+            variance: self.variance.clone(),
+            mask: self.mask.clone(),
+        })
+    }
+
+    /// Transform world coordinates `w` along axis number `axis` (in FITS
+    /// order) into `factor * w + offset`. If `name` is provided, the axis is
+    /// renamed (i.e. its CTYPE is changed), e.g. when world coordinates are
+    /// converted to another kind of coordinates.
+    ///
+    /// Return `None` if necessary metadata is missing or if the axis is
+    /// celestial.
+    pub fn transform_world_axis(
+        &self,
+        axis: usize,
+        factor: f64,
+        offset: f64,
+        name: Option<&str>,
+    ) -> Option<WcsArray> {
+        let meta = self.meta.as_ref()?;
+        Some(WcsArray {
+            meta: Some(meta.transform_axis(axis, factor, offset, name)?),
+            array: self.array.clone(),
+            variance: self.variance.clone(),
+            mask: self.mask.clone(),
+        })
+    }
+
+    /// Make a new `WcsArray` with the same units and world coordinates
+    /// containing `image`. The variance and the mask are not kept.
+    pub(crate) fn with_new_image(&self, image: ArrayD<f32>) -> WcsArray {
+        WcsArray {
+            meta: self.meta.clone(),
+            array: self.array.with_new_value(image),
+            variance: None,
+            mask: None,
+        }
+    }
+
     /// Make a slice along the specific `indices` in the array.
     ///
     /// Create a new `WcsArray` containing the provided `array`.
     /// The objective is to have correct metadata for the new `array`. The
     /// new metadata is computed from `indices` and the previous metadata.
     ///
-    /// TODO: This method is hard to understand, and is potentional buggy
-    /// write-only code.
+    /// The *k*-th axis of the slice is the axis `indices[k].0` of the original
+    /// array. See [`Wcs::slice`](struct.Wcs.html#method.slice) for details.
+    /// The variance and the mask are not kept.
     pub(crate) fn make_slice(
         &self,
         indices: &[(usize, f32, f32)],
         array: Dimensioned<ArrayD<f32>>,
     ) -> WcsArray {
-        let slice_index: Vec<_> = indices.iter().map(|idx| idx.0).collect();
-        let new_meta = self.meta.as_ref().map(|meta| {
-            let mut wcs = meta.wcs.slice(&slice_index);
-            for (i, start, end) in indices {
-                wcs = wcs.transform(*i, *start, *end);
-            }
-            let mut axes = [
-                Axis::default(),
-                Axis::default(),
-                Axis::default(),
-                Axis::default(),
-            ];
-            for (i, _, _) in indices {
-                axes[*i] = meta.axes[*i].clone();
+        WcsArray {
+            meta: self.meta.as_ref().map(|meta| meta.slice(indices)),
+            array,
+            variance: None,
+            mask: None,
+        }
+    }
+
+    /// Create a new `WcsArray` containing the provided `array`, with the
+    /// spatial axes (axes 0 and 1 in FITS order) of `spatial` and the other
+    /// axes of `self`.
+    ///
+    /// See [`Wcs::with_spatial_axes_of`](struct.Wcs.html#method.with_spatial_axes_of)
+    /// for details. The variance and the mask are not kept.
+    pub(crate) fn with_spatial_axes_of(
+        &self,
+        spatial: &WcsArray,
+        array: Dimensioned<ArrayD<f32>>,
+    ) -> WcsArray {
+        let new_meta = match (self.meta.as_ref(), spatial.meta.as_ref()) {
+            (Some(meta), Some(spatial)) => {
+                let mut axes = meta.axes.clone();
+                axes[0] = spatial.axes[0].clone();
+                axes[1] = spatial.axes[1].clone();
+                Some(MetaWcsArray {
+                    wcs: meta.wcs.with_spatial_axes_of(&spatial.wcs),
+                    axes,
+                })
             }
-            MetaWcsArray { wcs, axes }
-        });
+            (_, spatial) => spatial.cloned(),
+        };
         WcsArray {
             meta: new_meta,
             array,
+            variance: None,
+            mask: None,
+        }
+    }
+}
+
+impl MetaWcsArray {
+    fn pix2world(&self, axis: usize, pixel: f32) -> f32 {
+        let mut input = [0.0; 4];
+        input[axis] = pixel;
+        self.wcs.pix2world(input)[axis]
+    }
+
+    fn transform_axis(
+        &self,
+        axis: usize,
+        factor: f64,
+        offset: f64,
+        name: Option<&str>,
+    ) -> Option<MetaWcsArray> {
+        let wcs = self.wcs.transform_axis(axis, factor, offset)?;
+        let mut axes = self.axes.clone();
+        if let Some(name) = name {
+            axes[axis].name = Some(name.to_owned());
+        }
+        Some(MetaWcsArray { wcs, axes })
+    }
+
+    fn slice(&self, indices: &[(usize, f32, f32)]) -> MetaWcsArray {
+        let wcs = self.wcs.slice(indices);
+        let mut axes = [
+            Axis::default(),
+            Axis::default(),
+            Axis::default(),
+            Axis::default(),
+        ];
+        for (k, (i, _, _)) in indices.iter().enumerate() {
+            axes[k] = self.axes[*i].clone();
+        }
+        MetaWcsArray { wcs, axes }
+    }
+}
+
+fn world_unit(meta: Option<&MetaWcsArray>, axis: usize) -> Unit {
+    meta.and_then(|meta| meta.axes.get(axis))
+        .map(|axis| axis.unit.clone())
+        .unwrap_or_default()
+}
+
+impl WcsMeta {
+    /// Read units and world coordinates from the header of `hdu`.
+    pub fn from_hdu(hdu: &Hdu) -> WcsMeta {
+        let cunit1 = read_unit(hdu, "CUNIT1");
+        let cunit2 = read_unit(hdu, "CUNIT2");
+        let cunit3 = read_unit(hdu, "CUNIT3");
+        let cunit4 = read_unit(hdu, "CUNIT4");
+        let ctype1 = read_string(hdu, "CTYPE1");
+        let ctype2 = read_string(hdu, "CTYPE2");
+        let ctype3 = read_string(hdu, "CTYPE3");
+        let ctype4 = read_string(hdu, "CTYPE4");
+        let wcs = Wcs::from_hdu(hdu);
+        WcsMeta {
+            meta: Some(MetaWcsArray {
+                wcs,
+                axes: [
+                    Axis::new(ctype1, cunit1),
+                    Axis::new(ctype2, cunit2),
+                    Axis::new(ctype3, cunit3),
+                    Axis::new(ctype4, cunit4),
+                ],
+            }),
+            unit: read_unit(hdu, "BUNIT"),
+        }
+    }
+
+    /// Make a new `WcsArray` with these units and world coordinates
+    /// containing `image`.
+    pub fn with_image(&self, image: ArrayD<f32>) -> WcsArray {
+        WcsArray {
+            meta: self.meta.clone(),
+            array: self.unit.clone().new(image),
+            variance: None,
+            mask: None,
+        }
+    }
+
+    /// Get the unit of the values of the array.
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    pub fn axes(&self) -> Option<&[Axis]> {
+        self.meta.as_ref().map(|meta| meta.axes.as_ref())
+    }
+
+    pub fn wcs(&self) -> Option<&Wcs> {
+        self.meta.as_ref().map(|meta| &meta.wcs)
+    }
+
+    /// Same as [`WcsArray::pix2world`](struct.WcsArray.html#method.pix2world).
+    pub fn pix2world(&self, axis: usize, pixel: f32) -> Option<f32> {
+        self.meta.as_ref().map(|meta| meta.pix2world(axis, pixel))
+    }
+
+    /// Same as [`WcsArray::world_unit`](struct.WcsArray.html#method.world_unit).
+    pub fn world_unit(&self, axis: usize) -> Unit {
+        world_unit(self.meta.as_ref(), axis)
+    }
+
+    /// Same as
+    /// [`WcsArray::transform_world_axis`](struct.WcsArray.html#method.transform_world_axis).
+    pub fn transform_world_axis(
+        &self,
+        axis: usize,
+        factor: f64,
+        offset: f64,
+        name: Option<&str>,
+    ) -> Option<WcsMeta> {
+        let meta = self.meta.as_ref()?;
+        Some(WcsMeta {
+            meta: Some(meta.transform_axis(axis, factor, offset, name)?),
+            unit: self.unit.clone(),
+        })
+    }
+
+    /// Same as [`WcsArray::make_slice`](struct.WcsArray.html#method.make_slice),
+    /// `array` being given the unit of the array.
+    pub(crate) fn make_slice(&self, indices: &[(usize, f32, f32)], array: ArrayD<f32>) -> WcsArray {
+        WcsArray {
+            meta: self.meta.as_ref().map(|meta| meta.slice(indices)),
+            array: self.unit.clone().new(array),
+            variance: None,
+            mask: None,
         }
     }
 }
@@ -246,14 +609,79 @@ impl Unit {
         }
     }
 
+    /// Parse unit from a string, as found in BUNIT and CUNITn FITS keywords.
+    ///
+    /// Return `Unit::None` for an empty string and a custom unit if the string
+    /// cannot be parsed as a physical unit.
+    pub fn parse(unit: &str) -> Unit {
+        let unit = unit.trim();
+        if unit.is_empty() {
+            Unit::None
+        } else if let Ok(physical) = unit.parse() {
+            Unit::Physical(physical)
+        } else {
+            Unit::Custom(unit.to_owned())
+        }
+    }
+
     pub fn repr(&self) -> &str {
         match *self {
             Unit::None => "",
             Unit::Custom(ref unit) => unit,
+            Unit::Physical(ref unit) => unit.repr(),
+        }
+    }
+
+    /// Get the factor by which a value in this unit must be multiplied to be
+    /// expressed in unit `to`. Return `None` if the units cannot be converted
+    /// into one another.
+    pub fn conversion_factor(&self, to: &Unit) -> Option<f32> {
+        match (self, to) {
+            (Unit::Physical(from), Unit::Physical(to)) => {
+                from.conversion_factor(to).map(|factor| factor as f32)
+            }
+            (from, to) if from == to => Some(1.0),
+            _ => None,
         }
     }
 }
 
+impl<'a, 'b> ops::Mul<&'b Unit> for &'a Unit {
+    type Output = Unit;
+
+    fn mul(self, rhs: &'b Unit) -> Unit {
+        match (self, rhs) {
+            (Unit::None, _) | (_, Unit::None) => Unit::None,
+            (Unit::Physical(u1), Unit::Physical(u2)) => Unit::Physical(u1 * u2),
+            (u1, u2) => Unit::Custom(format!("{} {}", u1, u2)),
+        }
+    }
+}
+
+impl<'a, 'b> ops::Div<&'b Unit> for &'a Unit {
+    type Output = Unit;
+
+    fn div(self, rhs: &'b Unit) -> Unit {
+        match (self, rhs) {
+            (Unit::None, _) | (_, Unit::None) => Unit::None,
+            (Unit::Physical(u1), Unit::Physical(u2)) => Unit::Physical(u1 / u2),
+            (u1, u2) if u1 == u2 => Unit::Physical(PhysicalUnit::dimensionless()),
+            (u1, u2) => {
+                let u2 = u2.repr();
+                if u2.contains(|c: char| c.is_whitespace() || c == '/') {
+                    Unit::Custom(format!("{}/({})", u1, u2))
+                } else {
+                    Unit::Custom(format!("{}/{}", u1, u2))
+                }
+            }
+        }
+    }
+}
+
+fn needs_conversion(factor: f32) -> bool {
+    (factor - 1.0).abs() > ::std::f32::EPSILON
+}
+
 impl<V> Dimensioned<V> {
     /// Make a new dimensioned value with given unit.
     pub fn new(value: V, unit: Unit) -> Self {
@@ -275,6 +703,23 @@ impl<V> Dimensioned<V> {
         &self.unit
     }
 
+    /// Get value converted into `unit`. A value without unit is assumed to be
+    /// already expressed in `unit`, and so is any value if `unit` is
+    /// `Unit::None`.
+    ///
+    /// Return `None` if the units cannot be converted into one another.
+    pub fn value_in(&self, unit: &Unit) -> Option<V>
+    where
+        V: Clone + ops::Mul<f32, Output = V>,
+    {
+        if self.unit == Unit::None || *unit == Unit::None {
+            Some(self.value.clone())
+        } else {
+            let factor = self.unit.conversion_factor(unit)?;
+            Some(self.value.clone() * factor)
+        }
+    }
+
     /// Make a new dimensioned value with the same unit containing the passed
     /// value.
     pub fn with_new_value<W>(&self, value: W) -> Dimensioned<W> {
@@ -286,13 +731,13 @@ impl<V> Dimensioned<V> {
     }
 }
 
-impl<V, W> ops::Mul<W> for Dimensioned<V>
+impl<V> ops::Mul<f32> for Dimensioned<V>
 where
-    V: ops::Mul<W>,
+    V: ops::Mul<f32>,
 {
-    type Output = Dimensioned<<V as ops::Mul<W>>::Output>;
+    type Output = Dimensioned<<V as ops::Mul<f32>>::Output>;
 
-    fn mul(self, rhs: W) -> Self::Output {
+    fn mul(self, rhs: f32) -> Self::Output {
         Dimensioned {
             value: self.value * rhs,
             unit: self.unit,
@@ -301,13 +746,13 @@ where
     }
 }
 
-impl<'a, V, W> ops::Mul<W> for &'a Dimensioned<V>
+impl<'a, V> ops::Mul<f32> for &'a Dimensioned<V>
 where
-    &'a V: ops::Mul<W>,
+    &'a V: ops::Mul<f32>,
 {
-    type Output = Dimensioned<<&'a V as ops::Mul<W>>::Output>;
+    type Output = Dimensioned<<&'a V as ops::Mul<f32>>::Output>;
 
-    fn mul(self, rhs: W) -> Self::Output {
+    fn mul(self, rhs: f32) -> Self::Output {
         Dimensioned {
             value: &self.value * rhs,
             unit: self.unit.clone(),
@@ -316,13 +761,13 @@ where
     }
 }
 
-impl<V, W> ops::Div<W> for Dimensioned<V>
+impl<V> ops::Div<f32> for Dimensioned<V>
 where
-    V: ops::Div<W>,
+    V: ops::Div<f32>,
 {
-    type Output = Dimensioned<<V as ops::Div<W>>::Output>;
+    type Output = Dimensioned<<V as ops::Div<f32>>::Output>;
 
-    fn div(self, rhs: W) -> Self::Output {
+    fn div(self, rhs: f32) -> Self::Output {
         Dimensioned {
             value: self.value / rhs,
             unit: self.unit,
@@ -331,13 +776,13 @@ where
     }
 }
 
-impl<'a, V, W> ops::Div<W> for &'a Dimensioned<V>
+impl<'a, V> ops::Div<f32> for &'a Dimensioned<V>
 where
-    &'a V: ops::Div<W>,
+    &'a V: ops::Div<f32>,
 {
-    type Output = Dimensioned<<&'a V as ops::Div<W>>::Output>;
+    type Output = Dimensioned<<&'a V as ops::Div<f32>>::Output>;
 
-    fn div(self, rhs: W) -> Self::Output {
+    fn div(self, rhs: f32) -> Self::Output {
         Dimensioned {
             value: &self.value / rhs,
             unit: self.unit.clone(),
@@ -346,18 +791,94 @@ where
     }
 }
 
+impl<V, W> ops::Mul<Dimensioned<W>> for Dimensioned<V>
+where
+    V: ops::Mul<W>,
+{
+    type Output = Dimensioned<<V as ops::Mul<W>>::Output>;
+
+    fn mul(self, rhs: Dimensioned<W>) -> Self::Output {
+        Dimensioned {
+            value: self.value * rhs.value,
+            unit: &self.unit * &rhs.unit,
+            homogeneous: self.homogeneous && rhs.homogeneous,
+        }
+    }
+}
+
+impl<'a, 'b, V, W> ops::Mul<&'b Dimensioned<W>> for &'a Dimensioned<V>
+where
+    &'a V: ops::Mul<&'b W>,
+{
+    type Output = Dimensioned<<&'a V as ops::Mul<&'b W>>::Output>;
+
+    fn mul(self, rhs: &'b Dimensioned<W>) -> Self::Output {
+        Dimensioned {
+            value: &self.value * &rhs.value,
+            unit: &self.unit * &rhs.unit,
+            homogeneous: self.homogeneous && rhs.homogeneous,
+        }
+    }
+}
+
+impl<V, W> ops::Div<Dimensioned<W>> for Dimensioned<V>
+where
+    V: ops::Div<W>,
+{
+    type Output = Dimensioned<<V as ops::Div<W>>::Output>;
+
+    fn div(self, rhs: Dimensioned<W>) -> Self::Output {
+        Dimensioned {
+            value: self.value / rhs.value,
+            unit: &self.unit / &rhs.unit,
+            homogeneous: self.homogeneous && rhs.homogeneous,
+        }
+    }
+}
+
+impl<'a, 'b, V, W> ops::Div<&'b Dimensioned<W>> for &'a Dimensioned<V>
+where
+    &'a V: ops::Div<&'b W>,
+{
+    type Output = Dimensioned<<&'a V as ops::Div<&'b W>>::Output>;
+
+    fn div(self, rhs: &'b Dimensioned<W>) -> Self::Output {
+        Dimensioned {
+            value: &self.value / &rhs.value,
+            unit: &self.unit / &rhs.unit,
+            homogeneous: self.homogeneous && rhs.homogeneous,
+        }
+    }
+}
+
+impl<W> Dimensioned<W>
+where
+    W: ops::Mul<f32, Output = W>,
+{
+    /// Convert value into `unit`, if possible. Return the converted value and
+    /// whether the conversion succeeded.
+    fn converted_into(self, unit: &Unit) -> (W, bool) {
+        match self.unit.conversion_factor(unit) {
+            Some(factor) if needs_conversion(factor) => (self.value * factor, self.homogeneous),
+            Some(_) => (self.value, self.homogeneous),
+            None => (self.value, false),
+        }
+    }
+}
+
 impl<V, W> ops::Add<Dimensioned<W>> for Dimensioned<V>
 where
     V: ops::Add<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<V as ops::Add<W>>::Output>;
 
     fn add(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let (rhs, rhs_homogeneous) = rhs.converted_into(&self.unit);
         Dimensioned {
-            value: self.value + rhs.value,
+            value: self.value + rhs,
             unit: self.unit,
-            homogeneous,
+            homogeneous: self.homogeneous && rhs_homogeneous,
         }
     }
 }
@@ -365,15 +886,16 @@ where
 impl<V, W> ops::Sub<Dimensioned<W>> for Dimensioned<V>
 where
     V: ops::Sub<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<V as ops::Sub<W>>::Output>;
 
     fn sub(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let (rhs, rhs_homogeneous) = rhs.converted_into(&self.unit);
         Dimensioned {
-            value: self.value - rhs.value,
+            value: self.value - rhs,
             unit: self.unit,
-            homogeneous,
+            homogeneous: self.homogeneous && rhs_homogeneous,
         }
     }
 }
@@ -381,45 +903,59 @@ where
 impl<'a, V, W> ops::Sub<Dimensioned<W>> for &'a Dimensioned<V>
 where
     &'a V: ops::Sub<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<&'a V as ops::Sub<W>>::Output>;
 
     fn sub(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let (rhs, rhs_homogeneous) = rhs.converted_into(&self.unit);
         Dimensioned {
-            value: &self.value - rhs.value,
+            value: &self.value - rhs,
             unit: self.unit.clone(),
-            homogeneous,
+            homogeneous: self.homogeneous && rhs_homogeneous,
         }
     }
 }
 
 impl<'a, 'b, V, W> ops::Sub<&'b Dimensioned<W>> for &'a Dimensioned<V>
 where
-    &'a V: ops::Sub<&'b W>,
+    &'a V: ops::Sub<&'b W> + ops::Mul<f32, Output = V>,
+    V: ops::Sub<&'b W, Output = <&'a V as ops::Sub<&'b W>>::Output>,
+    <&'a V as ops::Sub<&'b W>>::Output: ops::Mul<f32, Output = <&'a V as ops::Sub<&'b W>>::Output>,
 {
     type Output = Dimensioned<<&'a V as ops::Sub<&'b W>>::Output>;
 
     fn sub(self, rhs: &'b Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let (value, homogeneous) = match rhs.unit.conversion_factor(&self.unit) {
+            Some(factor) if needs_conversion(factor) => {
+                // Subtract in the unit of `rhs`, then convert back
+                let lhs = &self.value * (1.0 / factor);
+                ((lhs - &rhs.value) * factor, rhs.homogeneous)
+            }
+            Some(_) => (&self.value - &rhs.value, rhs.homogeneous),
+            None => (&self.value - &rhs.value, false),
+        };
         Dimensioned {
-            value: &self.value - &rhs.value,
+            value,
             unit: self.unit.clone(),
-            homogeneous,
+            homogeneous: self.homogeneous && homogeneous,
+        }
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for Dimensioned<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unit.repr().is_empty() {
+            write!(f, "{}", self.value)
+        } else {
+            write!(f, "{} {}", self.value, self.unit)
         }
     }
 }
 
 impl fmt::Display for Unit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Unit::None => "",
-                Unit::Custom(ref s) => s,
-            }
-        )
+        write!(f, "{}", self.repr())
     }
 }
 
@@ -430,6 +966,8 @@ impl ops::Mul<f32> for WcsArray {
         WcsArray {
             meta: self.meta,
             array: self.array * rhs,
+            variance: self.variance.map(|v| v * (rhs * rhs)),
+            mask: self.mask,
         }
     }
 }
@@ -441,6 +979,8 @@ impl<'a> ops::Mul<f32> for &'a WcsArray {
         WcsArray {
             meta: self.meta.clone(),
             array: self.array() * rhs,
+            variance: self.variance.as_ref().map(|v| v * (rhs * rhs)),
+            mask: self.mask.clone(),
         }
     }
 }
@@ -448,10 +988,14 @@ impl<'a> ops::Mul<f32> for &'a WcsArray {
 impl ops::Div<f32> for WcsArray {
     type Output = WcsArray;
 
+    // Variance scales with the square of the divisor
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, rhs: f32) -> Self::Output {
         WcsArray {
             meta: self.meta,
             array: self.array / rhs,
+            variance: self.variance.map(|v| v / (rhs * rhs)),
+            mask: self.mask,
         }
     }
 }
@@ -459,10 +1003,14 @@ impl ops::Div<f32> for WcsArray {
 impl<'a> ops::Div<f32> for &'a WcsArray {
     type Output = WcsArray;
 
+    // Variance scales with the square of the divisor
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, rhs: f32) -> Self::Output {
         WcsArray {
             meta: self.meta.clone(),
             array: self.array() / rhs,
+            variance: self.variance.as_ref().map(|v| v / (rhs * rhs)),
+            mask: self.mask.clone(),
         }
     }
 }
@@ -476,9 +1024,14 @@ impl ops::Add for WcsArray {
         } else {
             None
         };
+        let variance = sum_variance(&self.array.unit, &self.variance, &rhs);
+        let array = self.array + rhs.array;
+        let mask = union_mask(array.scalar().shape(), self.mask, rhs.mask);
         WcsArray {
             meta,
-            array: self.array + rhs.array,
+            array,
+            variance,
+            mask,
         }
     }
 }
@@ -492,9 +1045,14 @@ impl ops::Sub for WcsArray {
         } else {
             None
         };
+        let variance = sum_variance(&self.array.unit, &self.variance, &rhs);
+        let array = self.array - rhs.array;
+        let mask = union_mask(array.scalar().shape(), self.mask, rhs.mask);
         WcsArray {
             meta,
-            array: self.array - rhs.array,
+            array,
+            variance,
+            mask,
         }
     }
 }
@@ -503,9 +1061,150 @@ impl<'a, 'b> ops::Sub<&'b WcsArray> for &'a WcsArray {
     type Output = WcsArray;
 
     fn sub(self, rhs: &'b WcsArray) -> Self::Output {
+        let array = &self.array - &rhs.array;
+        let mask = union_mask(array.scalar().shape(), self.mask.clone(), rhs.mask.clone());
         WcsArray {
             meta: self.meta.clone(),
-            array: &self.array - &rhs.array,
+            array,
+            variance: sum_variance(&self.array.unit, &self.variance, rhs),
+            mask,
+        }
+    }
+}
+
+/// Compute the variance of the sum or difference of an array with unit
+/// `unit` and variance `variance`, and of `rhs`. The variance of `rhs` is
+/// converted to `unit`.
+///
+/// Return `None` if the variance of any of the two arrays is unknown.
+fn sum_variance(
+    unit: &Unit,
+    variance: &Option<ArrayD<f32>>,
+    rhs: &WcsArray,
+) -> Option<ArrayD<f32>> {
+    match (variance, &rhs.variance) {
+        (Some(lhs), Some(rhs_variance)) => {
+            let factor = rhs.array.unit.conversion_factor(unit).unwrap_or(1.0);
+            Some(lhs + &(rhs_variance * (factor * factor)))
+        }
+        _ => None,
+    }
+}
+
+/// Combine the masks of the two operands of an operation whose result has
+/// shape `shape`. A value is masked if it is masked in any of the operands.
+fn union_mask(
+    shape: &[usize],
+    lhs: Option<ArrayD<bool>>,
+    rhs: Option<ArrayD<bool>>,
+) -> Option<ArrayD<bool>> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(lhs | &rhs),
+        (Some(mask), None) | (None, Some(mask)) => {
+            mask.broadcast(shape).map(|mask| mask.to_owned())
         }
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dimensioned, Unit, WcsArray};
+    use fitrs::Hdu;
+    use physical_unit::PhysicalUnit;
+    use ron;
+    use wcs::Wcs;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Unit::parse("km/s"), Unit::parse("km.s**-1"));
+        assert_eq!(Unit::parse("km/s"), Unit::Physical("km/s".parse().unwrap()));
+        assert_eq!(
+            Unit::parse("not a unit"),
+            Unit::Custom("not a unit".to_owned())
+        );
+        assert_eq!(Unit::parse(" "), Unit::None);
+    }
+
+    #[test]
+    fn test_conversion_factor() {
+        let km = Unit::parse("km");
+        assert_eq!(km.conversion_factor(&Unit::parse("m")), Some(1000.0));
+        assert_eq!(km.conversion_factor(&Unit::parse("s")), None);
+        assert_eq!(km.conversion_factor(&Unit::None), None);
+        assert_eq!(Unit::None.conversion_factor(&Unit::None), Some(1.0));
+        let custom = Unit::parse("not a unit");
+        assert_eq!(custom.conversion_factor(&custom), Some(1.0));
+        assert_eq!(custom.conversion_factor(&km), None);
+    }
+
+    #[test]
+    fn test_algebra() {
+        let km = Unit::parse("km");
+        let s = Unit::parse("s");
+        assert_eq!(&km / &s, Unit::parse("km/s"));
+        assert_eq!(&km * &Unit::None, Unit::None);
+        let custom = Unit::parse("not a unit");
+        assert_eq!(
+            &custom / &custom,
+            Unit::Physical(PhysicalUnit::dimensionless())
+        );
+        assert_eq!(&km / &custom, Unit::Custom("km/(not a unit)".to_owned()));
+    }
+
+    #[test]
+    fn test_dimensioned() {
+        let length = Dimensioned::new(2.0, Unit::parse("km"));
+        assert_eq!(length.value_in(&Unit::parse("m")), Some(2000.0));
+        assert_eq!(length.value_in(&Unit::None), Some(2.0));
+        assert_eq!(length.value_in(&Unit::parse("s")), None);
+
+        // Right-hand side is converted to the unit of the left-hand side
+        let sum = length.clone() + Dimensioned::new(500.0, Unit::parse("m"));
+        assert_eq!(*sum.scalar(), 2.5);
+        assert_eq!(sum.unit(), &Unit::parse("km"));
+        assert!(sum.homogeneous);
+        let difference = length.clone() - Dimensioned::new(1.0, Unit::parse("s"));
+        assert_eq!(*difference.scalar(), 1.0);
+        assert!(!difference.homogeneous);
+        let velocity = length / Dimensioned::new(4.0, Unit::parse("s"));
+        assert_eq!(*velocity.scalar(), 0.5);
+        assert_eq!(velocity.unit(), &Unit::parse("km/s"));
+    }
+
+    #[test]
+    fn test_serde_wcs_array() {
+        let mut hdu = Hdu::new(&[2], vec![1.0, 2.0]);
+        hdu.insert("CTYPE1", "WAVE");
+        hdu.insert("CUNIT1", "Angstrom");
+        hdu.insert("CRPIX1", 1.0);
+        hdu.insert("CRVAL1", 5000.0);
+        hdu.insert("CDELT1", 2.5);
+        let array = WcsArray::from_hdu(&hdu).unwrap();
+        let serialized = ron::ser::to_string(&array).unwrap();
+        let deserialized: WcsArray = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, array);
+        assert_eq!(deserialized.pix2world(0, 2.0), Some(5005.0));
+
+        // Saved before world coordinates were serialized
+        let legacy = "(
+            meta: Some((
+                axes: (
+                    (name: Some(\"WAVE\"), unit: None),
+                    (name: None, unit: None),
+                    (name: None, unit: None),
+                    (name: None, unit: None),
+                ),
+            )),
+            array: (
+                value: (v: 1, dim: [2], data: [1, 2]),
+                unit: None,
+                homogeneous: true,
+            ),
+        )";
+        let legacy: WcsArray = ron::de::from_str(legacy).unwrap();
+        assert_eq!(legacy.wcs(), Some(&Wcs::default()));
+        assert_eq!(legacy.axes().unwrap()[0].name(), "WAVE");
+        assert_eq!(legacy.scalar().as_slice(), Some(&[1.0, 2.0][..]));
     }
 }
@@ -0,0 +1,766 @@
+use std::cmp::Ordering;
+use std::f64::consts::FRAC_PI_2;
+
+use fitrs::{Hdu, HeaderValue};
+
+use unit::read_string;
+
+/// World coordinate system of an array of up to 4 dimensions, as defined by
+/// the CRPIXn, CRVALn, CDELTn and PCi_j (or CDi_j) FITS keywords.
+///
+/// Axes are numbered in FITS order, i.e. the first axis is the fastest
+/// varying one.
+///
+/// A pair of celestial axes (e.g. `RA---TAN` and `DEC--TAN`) is supported
+/// for the zenithal projections listed in [`Projection`](enum.Projection.html).
+/// All other axes, as well as celestial axes with an unsupported projection,
+/// are linear.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wcs {
+    crpix: [f64; 4],
+    crval: [f64; 4],
+    cdelt: [f64; 4],
+    pc: [[f64; 4]; 4],
+    #[serde(default)]
+    celestial: Option<Celestial>,
+}
+
+/// Celestial axes of a world coordinate system.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Celestial {
+    /// Index of the longitude axis
+    lng: usize,
+    /// Index of the latitude axis
+    lat: usize,
+    projection: Projection,
+    /// Native longitude of the celestial pole in degrees (LONPOLE)
+    lonpole: f64,
+}
+
+/// Zenithal projections of the celestial sphere, as defined in Calabretta &
+/// Greisen (2002), "Representations of celestial coordinates in FITS".
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Gnomonic
+    Tan,
+    /// Slant orthographic
+    Sin,
+    /// Zenithal equidistant
+    Arc,
+    /// Stereographic
+    Stg,
+    /// Zenithal equal-area
+    Zea,
+}
+
+impl Default for Wcs {
+    fn default() -> Self {
+        Self {
+            crpix: [0.0; 4],
+            crval: [0.0; 4],
+            cdelt: [1.0; 4],
+            pc: IDENTITY,
+            celestial: None,
+        }
+    }
+}
+
+const IDENTITY: [[f64; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+impl Wcs {
+    /// Read world coordinate system from the header of `hdu`.
+    ///
+    /// Missing keywords take the default values defined by the FITS standard.
+    /// If any CDi_j keyword is present, the CDi_j matrix takes precedence over
+    /// CDELTn and PCi_j.
+    pub fn from_hdu(hdu: &Hdu) -> Self {
+        let mut wcs = Wcs::default();
+        for i in 0..4 {
+            let n = i + 1;
+            if let Some(crpix) = read_float(hdu, &format!("CRPIX{}", n)) {
+                wcs.crpix[i] = crpix;
+            }
+            if let Some(crval) = read_float(hdu, &format!("CRVAL{}", n)) {
+                wcs.crval[i] = crval;
+            }
+            if let Some(cdelt) = read_float(hdu, &format!("CDELT{}", n)) {
+                wcs.cdelt[i] = cdelt;
+            }
+            for j in 0..4 {
+                if let Some(pc) = read_float(hdu, &format!("PC{}_{}", n, j + 1)) {
+                    wcs.pc[i][j] = pc;
+                }
+            }
+        }
+
+        let mut cd = [[0.0; 4]; 4];
+        let mut has_cd = false;
+        for (i, row) in cd.iter_mut().enumerate() {
+            for (j, cd_ij) in row.iter_mut().enumerate() {
+                if let Some(value) = read_float(hdu, &format!("CD{}_{}", i + 1, j + 1)) {
+                    *cd_ij = value;
+                    has_cd = true;
+                }
+            }
+        }
+        if has_cd {
+            // Axes without any CDi_j keyword keep a unit scale
+            for (i, row) in cd.iter_mut().enumerate() {
+                if row.iter().all(|cd_ij| *cd_ij == 0.0) {
+                    row[i] = 1.0;
+                }
+            }
+            wcs.cdelt = [1.0; 4];
+            wcs.pc = cd;
+        }
+
+        wcs.celestial = Celestial::from_hdu(hdu, &wcs.crval);
+        wcs
+    }
+
+    /// Write world coordinate system as keywords in the header of `hdu`,
+    /// for the first `naxis` axes.
+    ///
+    /// CTYPEn keywords are not written, as they are part of the axis
+    /// description.
+    pub fn write_to_hdu(&self, hdu: &mut Hdu, naxis: usize) {
+        let naxis = naxis.min(4);
+        for i in 0..naxis {
+            let n = i + 1;
+            insert_float(hdu, &format!("CRPIX{}", n), self.crpix[i]);
+            insert_float(hdu, &format!("CRVAL{}", n), self.crval[i]);
+            insert_float(hdu, &format!("CDELT{}", n), self.cdelt[i]);
+        }
+        if self.pc != IDENTITY {
+            for i in 0..naxis {
+                for j in 0..naxis {
+                    insert_float(hdu, &format!("PC{}_{}", i + 1, j + 1), self.pc[i][j]);
+                }
+            }
+        }
+        if let Some(ref celestial) = self.celestial {
+            insert_float(hdu, "LONPOLE", celestial.lonpole);
+        }
+    }
+
+    /// Get projection used for the celestial axes, if any.
+    pub fn projection(&self) -> Option<Projection> {
+        self.celestial.map(|celestial| celestial.projection)
+    }
+
+    /// Get the indices of the longitude and latitude axes, if any.
+    pub fn celestial_axes(&self) -> Option<(usize, usize)> {
+        self.celestial
+            .map(|celestial| (celestial.lng, celestial.lat))
+    }
+
+    /// Convert position `pixel` (in pixel coordinates starting from 0) to
+    /// world coordinates.
+    ///
+    /// Celestial coordinates are given in degrees. They are NaN if `pixel`
+    /// is outside of the domain of the projection.
+    pub fn pix2world(&self, pixel: [f32; 4]) -> [f32; 4] {
+        let mut intermediate = [0.0; 4];
+        for ((x, pc), cdelt) in intermediate.iter_mut().zip(&self.pc).zip(&self.cdelt) {
+            for ((p, pc_ij), crpix) in pixel.iter().zip(pc).zip(&self.crpix) {
+                // FITS pixel coordinates start from 1
+                *x += pc_ij * (f64::from(*p) + 1.0 - crpix);
+            }
+            *x *= cdelt;
+        }
+
+        let mut world = [0.0; 4];
+        for ((w, x), crval) in world.iter_mut().zip(&intermediate).zip(&self.crval) {
+            *w = (crval + x) as f32;
+        }
+        if let Some(ref celestial) = self.celestial {
+            let (x, y) = (intermediate[celestial.lng], intermediate[celestial.lat]);
+            let (lng, lat) = celestial.deproject(x, y, &self.crval);
+            world[celestial.lng] = lng as f32;
+            world[celestial.lat] = lat as f32;
+        }
+        world
+    }
+
+    /// Convert position `world` in world coordinates to pixel coordinates
+    /// (starting from 0).
+    ///
+    /// Return `None` if `world` cannot be projected or if the transformation
+    /// matrix is singular.
+    pub fn world2pix(&self, world: [f32; 4]) -> Option<[f32; 4]> {
+        let mut intermediate = [0.0; 4];
+        for ((x, w), crval) in intermediate.iter_mut().zip(&world).zip(&self.crval) {
+            *x = f64::from(*w) - crval;
+        }
+        if let Some(ref celestial) = self.celestial {
+            let (lng, lat) = (world[celestial.lng], world[celestial.lat]);
+            let (x, y) = celestial.project(f64::from(lng), f64::from(lat), &self.crval);
+            intermediate[celestial.lng] = x;
+            intermediate[celestial.lat] = y;
+        }
+
+        let mut matrix = self.pc;
+        for (row, cdelt) in matrix.iter_mut().zip(&self.cdelt) {
+            for m_ij in row.iter_mut() {
+                *m_ij *= cdelt;
+            }
+        }
+        let offset = solve(matrix, intermediate)?;
+
+        let mut pixel = [0.0; 4];
+        for ((p, d), crpix) in pixel.iter_mut().zip(&offset).zip(&self.crpix) {
+            // FITS pixel coordinates start from 1
+            *p = (d + crpix - 1.0) as f32;
+        }
+        if pixel.iter().all(|p| p.is_finite()) {
+            Some(pixel)
+        } else {
+            None
+        }
+    }
+
+    /// Multiply world coordinates along axis `axis` by `factor`, e.g. to
+    /// express them in another unit. Return `None` for celestial axes.
+    pub fn scale_axis(&self, axis: usize, factor: f64) -> Option<Self> {
+        self.transform_axis(axis, factor, 0.0)
+    }
+
+    /// Transform world coordinates `w` along axis `axis` into
+    /// `factor * w + offset`. Return `None` for celestial axes.
+    pub fn transform_axis(&self, axis: usize, factor: f64, offset: f64) -> Option<Self> {
+        let is_celestial = self.celestial.map_or(false, |celestial| {
+            axis == celestial.lng || axis == celestial.lat
+        });
+        if is_celestial || axis >= 4 {
+            return None;
+        }
+        let mut wcs = self.clone();
+        wcs.crval[axis] = factor * wcs.crval[axis] + offset;
+        wcs.cdelt[axis] *= factor;
+        Some(wcs)
+    }
+
+    /// Compute the world coordinate system of a slice.
+    ///
+    /// `indices` contains, for each axis of the slice, a tuple `(i, start,
+    /// factor)` where `i` is the index of the original axis. A pixel `p` on
+    /// the new axis is located at pixel `start + factor * p` on the original
+    /// axis. Axes that are not kept are assumed to be sliced at their first
+    /// pixel.
+    ///
+    /// Celestial axes remain projected only if both of them are kept.
+    pub fn slice(&self, indices: &[(usize, f32, f32)]) -> Self {
+        let mut wcs = Wcs::default();
+        wcs.celestial = self.celestial.and_then(|celestial| {
+            let lng = indices.iter().position(|&(i, _, _)| i == celestial.lng)?;
+            let lat = indices.iter().position(|&(i, _, _)| i == celestial.lat)?;
+            Some(Celestial {
+                lng,
+                lat,
+                ..celestial
+            })
+        });
+        for (k, &(i, _, _)) in indices.iter().enumerate() {
+            let is_celestial = wcs
+                .celestial
+                .map_or(false, |celestial| k == celestial.lng || k == celestial.lat);
+            if is_celestial {
+                // The reference point of a projection cannot be shifted
+                wcs.crval[k] = self.crval[i];
+            } else {
+                // Contribution of axes that are not kept anymore
+                let mut offset = 0.0;
+                for j in 0..4 {
+                    if indices.iter().all(|&(kept, _, _)| kept != j) {
+                        offset += self.pc[i][j] * (1.0 - self.crpix[j]);
+                    }
+                }
+                wcs.crval[k] = self.crval[i] + self.cdelt[i] * offset;
+            }
+            wcs.cdelt[k] = self.cdelt[i];
+            for (l, &(j, start, factor)) in indices.iter().enumerate() {
+                let (start, factor) = (f64::from(start), f64::from(factor));
+                wcs.pc[k][l] = self.pc[i][j] * factor;
+                wcs.crpix[l] = (self.crpix[j] - 1.0 - start) / factor + 1.0;
+            }
+        }
+        wcs
+    }
+
+    /// Compute the world coordinate system made of the spatial axes (axes 0
+    /// and 1) of `spatial` and of the other axes of `self`.
+    ///
+    /// Terms of the transformation matrix linking spatial axes to the other
+    /// axes are dropped.
+    pub fn with_spatial_axes_of(&self, spatial: &Wcs) -> Self {
+        let is_spatial = |axis: usize| axis < 2;
+        let mut wcs = self.clone();
+        for i in 0..4 {
+            if is_spatial(i) {
+                wcs.crpix[i] = spatial.crpix[i];
+                wcs.crval[i] = spatial.crval[i];
+                wcs.cdelt[i] = spatial.cdelt[i];
+            }
+            for j in 0..4 {
+                wcs.pc[i][j] = match (is_spatial(i), is_spatial(j)) {
+                    (true, true) => spatial.pc[i][j],
+                    (false, false) => self.pc[i][j],
+                    _ => 0.0,
+                };
+            }
+        }
+        wcs.celestial = spatial
+            .celestial
+            .filter(|celestial| is_spatial(celestial.lng) && is_spatial(celestial.lat));
+        wcs
+    }
+}
+
+impl Celestial {
+    /// Find celestial axes from the CTYPEn keywords of `hdu`.
+    ///
+    /// Return `None` if there are no celestial axes or if their projection
+    /// is not supported.
+    fn from_hdu(hdu: &Hdu, crval: &[f64; 4]) -> Option<Self> {
+        let mut lng = None;
+        let mut lat = None;
+        for i in 0..4 {
+            if let Some(ctype) = read_string(hdu, &format!("CTYPE{}", i + 1)) {
+                match parse_celestial_ctype(&ctype) {
+                    Some((true, projection)) => lng = Some((i, projection)),
+                    Some((false, projection)) => lat = Some((i, projection)),
+                    None => (),
+                }
+            }
+        }
+        let ((lng, lng_projection), (lat, lat_projection)) = (lng?, lat?);
+        if lng_projection != lat_projection {
+            return None;
+        }
+        // Default LONPOLE for zenithal projections
+        let default_lonpole = if crval[lat] >= 90.0 { 0.0 } else { 180.0 };
+        Some(Celestial {
+            lng,
+            lat,
+            projection: lng_projection?,
+            lonpole: read_float(hdu, "LONPOLE").unwrap_or(default_lonpole),
+        })
+    }
+
+    /// Convert projection plane coordinates `(x, y)` to celestial coordinates
+    /// `(lng, lat)`. All values are in degrees.
+    fn deproject(&self, x: f64, y: f64, crval: &[f64; 4]) -> (f64, f64) {
+        let r = x.hypot(y).to_radians();
+        let phi = if r == 0.0 { 0.0 } else { x.atan2(-y) };
+        let theta = self.projection.theta(r);
+
+        let (alpha_p, delta_p) = (crval[self.lng].to_radians(), crval[self.lat].to_radians());
+        let dphi = phi - self.lonpole.to_radians();
+        let alpha = alpha_p
+            + (-theta.cos() * dphi.sin())
+                .atan2(theta.sin() * delta_p.cos() - theta.cos() * delta_p.sin() * dphi.cos());
+        let delta = (theta.sin() * delta_p.sin() + theta.cos() * delta_p.cos() * dphi.cos()).asin();
+        let lng = alpha.to_degrees() % 360.0;
+        let lng = if lng < 0.0 { lng + 360.0 } else { lng };
+        (lng, delta.to_degrees())
+    }
+
+    /// Convert celestial coordinates `(lng, lat)` to projection plane
+    /// coordinates `(x, y)`. All values are in degrees.
+    fn project(&self, lng: f64, lat: f64, crval: &[f64; 4]) -> (f64, f64) {
+        let (alpha, delta) = (lng.to_radians(), lat.to_radians());
+        let (alpha_p, delta_p) = (crval[self.lng].to_radians(), crval[self.lat].to_radians());
+        let dalpha = alpha - alpha_p;
+        let phi = self.lonpole.to_radians()
+            + (-delta.cos() * dalpha.sin())
+                .atan2(delta.sin() * delta_p.cos() - delta.cos() * delta_p.sin() * dalpha.cos());
+        let theta =
+            (delta.sin() * delta_p.sin() + delta.cos() * delta_p.cos() * dalpha.cos()).asin();
+
+        let r = self.projection.radius(theta).to_degrees();
+        (r * phi.sin(), -r * phi.cos())
+    }
+}
+
+impl Projection {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "TAN" => Some(Projection::Tan),
+            "SIN" => Some(Projection::Sin),
+            "ARC" => Some(Projection::Arc),
+            "STG" => Some(Projection::Stg),
+            "ZEA" => Some(Projection::Zea),
+            _ => None,
+        }
+    }
+
+    /// Three-letter code of the projection used in CTYPEn keywords.
+    pub fn code(self) -> &'static str {
+        match self {
+            Projection::Tan => "TAN",
+            Projection::Sin => "SIN",
+            Projection::Arc => "ARC",
+            Projection::Stg => "STG",
+            Projection::Zea => "ZEA",
+        }
+    }
+
+    /// Native latitude for a radius `r` on the projection plane. Angles are
+    /// in radians. Return NaN if `r` is out of the domain of the projection.
+    fn theta(self, r: f64) -> f64 {
+        match self {
+            Projection::Tan => 1.0_f64.atan2(r),
+            Projection::Sin => r.acos(),
+            Projection::Arc => FRAC_PI_2 - r,
+            Projection::Stg => FRAC_PI_2 - 2.0 * (r / 2.0).atan(),
+            Projection::Zea => FRAC_PI_2 - 2.0 * (r / 2.0).asin(),
+        }
+    }
+
+    /// Radius on the projection plane for a native latitude `theta`. Angles
+    /// are in radians. Return NaN if `theta` cannot be projected.
+    fn radius(self, theta: f64) -> f64 {
+        match self {
+            Projection::Tan if theta > 0.0 => theta.cos() / theta.sin(),
+            Projection::Sin if theta >= 0.0 => theta.cos(),
+            Projection::Arc => FRAC_PI_2 - theta,
+            Projection::Stg => 2.0 * ((FRAC_PI_2 - theta) / 2.0).tan(),
+            Projection::Zea => 2.0 * ((FRAC_PI_2 - theta) / 2.0).sin(),
+            _ => ::std::f64::NAN,
+        }
+    }
+}
+
+/// Parse a celestial CTYPEn value such as `RA---TAN` or `GLAT-SIN`.
+///
+/// Return whether the axis is a longitude axis, along with its projection
+/// (`None` if unsupported). Return `None` if the axis is not celestial.
+fn parse_celestial_ctype(ctype: &str) -> Option<(bool, Option<Projection>)> {
+    let ctype = ctype.trim();
+    if ctype.get(4..5)? != "-" {
+        return None;
+    }
+    let coordinate = ctype.get(..4)?.trim_end_matches('-');
+    let projection = Projection::from_code(ctype.get(5..)?);
+    if coordinate == "RA" || coordinate.ends_with("LON") || coordinate.ends_with("LN") {
+        Some((true, projection))
+    } else if coordinate == "DEC" || coordinate.ends_with("LAT") || coordinate.ends_with("LT") {
+        Some((false, projection))
+    } else {
+        None
+    }
+}
+
+/// Solve linear system `a * x = b` with Gaussian elimination.
+///
+/// Return `None` if `a` is singular.
+fn solve(mut a: [[f64; 4]; 4], mut b: [f64; 4]) -> Option<[f64; 4]> {
+    for col in 0..4 {
+        let pivot = (col..4)
+            .max_by(|&i, &j| {
+                a[i][col]
+                    .abs()
+                    .partial_cmp(&a[j][col].abs())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+        if a[pivot][col] == 0.0 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..4 {
+            let factor = a[row][col] / a[col][col];
+            for k in col..4 {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; 4];
+    for row in (0..4).rev() {
+        let mut sum = b[row];
+        for (a_rk, x_k) in a[row].iter().zip(&x).skip(row + 1) {
+            sum -= a_rk * x_k;
+        }
+        x[row] = sum / a[row][row];
+    }
+    Some(x)
+}
+
+pub(crate) fn read_float(hdu: &Hdu, key: &str) -> Option<f64> {
+    match hdu.value(key) {
+        Some(HeaderValue::RealFloatingNumber(f)) => Some(*f),
+        Some(HeaderValue::IntegerNumber(i)) => Some(*i as f64),
+        _ => None,
+    }
+}
+
+/// Insert `value` as keyword `key` of `hdu`, so that fitrs can write it.
+///
+/// fitrs writes all the digits of a float, and panics if they do not fit in
+/// the 20 characters of a FITS value. Integral values are written as
+/// integers, others are rounded to the closest value that fits. Values that
+/// cannot be written, e.g. NaN, are skipped.
+fn insert_float(hdu: &mut Hdu, key: &str, value: f64) {
+    if value.fract() == 0.0 && value.abs() <= f64::from(::std::i32::MAX) {
+        hdu.insert(key, value as i32);
+    } else if let Some(value) = writable_float(value) {
+        hdu.insert(key, value);
+    }
+}
+
+/// Round `value` to a decimal number of at most 15 significant digits that
+/// fitrs writes on 20 characters.
+///
+/// fitrs scales the value by repeated multiplications or divisions by 10,
+/// adding rounding errors to some values. When a rounded value is affected,
+/// its closest neighbours with the same number of digits are tried.
+fn writable_float(value: f64) -> Option<f64> {
+    if !value.is_finite() || value == 0.0 {
+        return None;
+    }
+    for digits in (8..16).rev() {
+        let rounded = format!("{:.*e}", digits - 1, value);
+        let mut parts = rounded.split('e');
+        let mantissa: f64 = parts.next()?.parse().ok()?;
+        let exponent = parts.next()?;
+        let step = 10f64.powi(1 - digits as i32);
+        for &offset in &[0.0, 1.0, -1.0, 2.0, -2.0, 3.0, -3.0, 4.0, -4.0, 5.0, -5.0] {
+            let mantissa = mantissa + offset * step;
+            let candidate: f64 = format!("{:.*}e{}", digits - 1, mantissa, exponent)
+                .parse()
+                .ok()?;
+            if fitrs_repr(candidate).len() <= 20 {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Representation of non-zero `value` written by fitrs.
+fn fitrs_repr(value: f64) -> String {
+    let mut exponent = 0;
+    let mut mantissa = value;
+    while mantissa.abs() >= 10.0 {
+        mantissa /= 10.0;
+        exponent += 1;
+    }
+    while mantissa.abs() < 1.0 {
+        mantissa *= 10.0;
+        exponent -= 1;
+    }
+    if exponent >= 0 {
+        format!("{}E+{:02}", mantissa, exponent)
+    } else {
+        format!("{}E-{:02}", mantissa, -exponent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fitrs::{Fits, Hdu};
+    use ron;
+
+    use super::{fitrs_repr, writable_float, Projection, Wcs};
+    use test_util::assert_close;
+
+    /// Celestial axes with projection `code`, 1 degree per pixel and the
+    /// reference point at (0, 0) on the first pixel.
+    fn equatorial_wcs(code: &str) -> Wcs {
+        let mut hdu = Hdu::new(&[20, 20], vec![0.0; 400]);
+        hdu.insert("CTYPE1", format!("RA---{}", code));
+        hdu.insert("CTYPE2", format!("DEC--{}", code));
+        hdu.insert("CRPIX1", 1.0);
+        hdu.insert("CRPIX2", 1.0);
+        Wcs::from_hdu(&hdu)
+    }
+
+    /// Celestial axes with TAN projection centered on (150, 30), rotated by
+    /// 30 degrees with a PCi_j matrix.
+    fn rotated_hdu() -> Hdu {
+        let mut hdu = Hdu::new(&[60, 100], vec![0.0; 6000]);
+        hdu.insert("CTYPE1", "RA---TAN");
+        hdu.insert("CTYPE2", "DEC--TAN");
+        hdu.insert("CRPIX1", 10.0);
+        hdu.insert("CRPIX2", 15.0);
+        hdu.insert("CRVAL1", 150.0);
+        hdu.insert("CRVAL2", 30.0);
+        hdu
+    }
+
+    #[test]
+    fn test_projections() {
+        // At the equator, 10 degrees away from the reference point on the
+        // projection plane is at the native latitude 90 - theta(10)
+        let projections = [
+            ("TAN", Projection::Tan, 9.900277),
+            ("SIN", Projection::Sin, 10.051478),
+            ("ARC", Projection::Arc, 10.0),
+            ("STG", Projection::Stg, 9.974731),
+            ("ZEA", Projection::Zea, 10.012736),
+        ];
+        for &(code, projection, expected) in &projections {
+            let wcs = equatorial_wcs(code);
+            assert_eq!(wcs.projection(), Some(projection));
+            assert_eq!(wcs.celestial_axes(), Some((0, 1)));
+
+            assert_close(&wcs.pix2world([0.0; 4])[..2], &[0.0, 0.0], 1e-5);
+            let along_ra = wcs.pix2world([10.0, 0.0, 0.0, 0.0]);
+            assert_close(&along_ra[..2], &[expected, 0.0], 1e-5);
+            let along_dec = wcs.pix2world([0.0, 10.0, 0.0, 0.0]);
+            assert_close(&along_dec[..2], &[0.0, expected], 1e-5);
+
+            let pixel = [3.0, 7.0, 0.0, 0.0];
+            let round_trip = wcs.world2pix(wcs.pix2world(pixel)).unwrap();
+            assert_close(&round_trip, &pixel, 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_projection_is_linear() {
+        let wcs = equatorial_wcs("CAR");
+        assert_eq!(wcs.projection(), None);
+        assert_close(
+            &wcs.pix2world([10.0, 5.0, 0.0, 0.0])[..2],
+            &[10.0, 5.0],
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn test_pc_rotation() {
+        let mut hdu = rotated_hdu();
+        let angle = 30.0_f64.to_radians();
+        hdu.insert("CDELT1", -0.01);
+        hdu.insert("CDELT2", 0.01);
+        hdu.insert("PC1_1", angle.cos());
+        hdu.insert("PC1_2", -angle.sin());
+        hdu.insert("PC2_1", angle.sin());
+        hdu.insert("PC2_2", angle.cos());
+        let wcs = Wcs::from_hdu(&hdu);
+
+        // Reference pixel, FITS pixels start from 1
+        assert_close(
+            &wcs.pix2world([9.0, 14.0, 0.0, 0.0])[..2],
+            &[150.0, 30.0],
+            1e-6,
+        );
+        let pixel = [29.0, 4.0, 0.0, 0.0];
+        let world = wcs.pix2world(pixel);
+        assert_close(&world[..2], &[149.742_23, 30.013146], 1e-6);
+        assert_close(&wcs.world2pix(world).unwrap(), &pixel, 1e-3);
+    }
+
+    #[test]
+    fn test_cd_matrix() {
+        let angle = 30.0_f64.to_radians();
+        let mut hdu = rotated_hdu();
+        // Ignored, as the CDi_j matrix takes precedence
+        hdu.insert("CDELT1", 2.0);
+        hdu.insert("PC1_2", 1.0);
+        hdu.insert("CD1_1", -0.01 * angle.cos());
+        hdu.insert("CD1_2", 0.01 * angle.sin());
+        hdu.insert("CD2_1", 0.01 * angle.sin());
+        hdu.insert("CD2_2", 0.01 * angle.cos());
+        let wcs = Wcs::from_hdu(&hdu);
+
+        let pixel = [29.0, 4.0, 0.0, 0.0];
+        let world = wcs.pix2world(pixel);
+        assert_close(&world[..2], &[149.742_23, 30.013146], 1e-6);
+        assert_close(&wcs.world2pix(world).unwrap(), &pixel, 1e-3);
+
+        // Without rotation
+        let mut hdu = rotated_hdu();
+        hdu.insert("CD1_1", -0.01);
+        hdu.insert("CD2_2", 0.01);
+        let wcs = Wcs::from_hdu(&hdu);
+        let pixel = [59.0, 94.0, 0.0, 0.0];
+        let world = wcs.pix2world(pixel);
+        assert_close(&world[..2], &[149.417_98, 30.798648], 1e-6);
+        assert_close(&wcs.world2pix(world).unwrap(), &pixel, 1e-3);
+    }
+
+    #[test]
+    fn test_write_to_hdu() {
+        let mut hdu = rotated_hdu();
+        let angle = 30.0_f64.to_radians();
+        hdu.insert("CDELT1", -1.0 / 3600.0);
+        hdu.insert("CDELT2", 1.0 / 3600.0);
+        hdu.insert("PC1_1", angle.cos());
+        hdu.insert("PC1_2", -angle.sin());
+        hdu.insert("PC2_1", angle.sin());
+        hdu.insert("PC2_2", angle.cos());
+        let wcs = Wcs::from_hdu(&hdu);
+
+        let mut written = Hdu::new(&[60, 100], vec![0.0; 6000]);
+        written.insert("CTYPE1", "RA---TAN");
+        written.insert("CTYPE2", "DEC--TAN");
+        wcs.write_to_hdu(&mut written, 2);
+        let path = ::std::env::temp_dir().join("aflak_test_write_wcs.fits");
+        Fits::create(&path, written).unwrap();
+        let fits = Fits::open(&path).unwrap();
+        let read = Wcs::from_hdu(&fits.get(0).unwrap());
+
+        let pixel = [29.0, 4.0, 0.0, 0.0];
+        assert_close(&read.pix2world(pixel), &wcs.pix2world(pixel), 1e-6);
+    }
+
+    #[test]
+    fn test_writable_float() {
+        // fitrs writes 1.2300000000000002E-08 for 1.23e-8
+        for &value in &[1.23e-8, 1.1e-5, 1.0 / 3600.0, -1.25e-10, 0.1 + 0.2, 6.02e23] {
+            let written = writable_float(value).unwrap();
+            assert!(fitrs_repr(written).len() <= 20, "{}", fitrs_repr(written));
+            assert_close(&[written], &[value], 1e-12);
+        }
+        assert_eq!(writable_float(::std::f64::NAN), None);
+        assert_eq!(writable_float(0.0), None);
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut hdu = rotated_hdu();
+        hdu.insert("CD1_1", -0.01);
+        hdu.insert("CD1_2", 0.005);
+        hdu.insert("CD2_2", 0.01);
+        hdu.insert("LONPOLE", 170.0);
+        let wcs = Wcs::from_hdu(&hdu);
+        let serialized = ron::ser::to_string(&wcs).unwrap();
+        let deserialized: Wcs = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, wcs);
+        assert_eq!(deserialized.projection(), Some(Projection::Tan));
+
+        let linear = Wcs::default();
+        let serialized = ron::ser::to_string(&linear).unwrap();
+        assert_eq!(ron::de::from_str::<Wcs>(&serialized).unwrap(), linear);
+    }
+
+    #[test]
+    fn test_world2pix_failure() {
+        // Opposite side of the sphere is not projected by TAN nor SIN
+        let tan = equatorial_wcs("TAN");
+        assert!(tan.world2pix([180.0, 0.0, 0.0, 0.0]).is_none());
+        let sin = equatorial_wcs("SIN");
+        assert!(sin.world2pix([100.0, 0.0, 0.0, 0.0]).is_none());
+        // ... but it is by ARC
+        let arc = equatorial_wcs("ARC");
+        assert!(arc.world2pix([100.0, 0.0, 0.0, 0.0]).is_some());
+
+        // Singular transformation matrix
+        let mut hdu = Hdu::new(&[20, 20], vec![0.0; 400]);
+        hdu.insert("PC1_1", 1.0);
+        hdu.insert("PC1_2", 1.0);
+        hdu.insert("PC2_1", 1.0);
+        hdu.insert("PC2_2", 1.0);
+        let wcs = Wcs::from_hdu(&hdu);
+        assert!(wcs.world2pix([1.0, 1.0, 0.0, 0.0]).is_none());
+    }
+}
@@ -21,7 +21,7 @@ mod node_state;
 mod scrolling;
 mod vec2;
 
-use std::{collections, error, fmt, fs, io, path};
+use std::{collections, error, fmt, fs, io, path, sync};
 
 use cake::Future;
 use imgui::ImString;
@@ -119,6 +119,26 @@ where
     }
 }
 
+impl<T, E> NodeEditor<T, E>
+where
+    T: Clone + cake::VariantName + cake::ConvertibleVariants,
+{
+    /// Compute output's result synchronously, in the current thread.
+    ///
+    /// Intermediate results are stored in `cache`, so that they can be re-used
+    /// when several outputs are computed.
+    pub fn compute_output_sync(
+        &self,
+        id: cake::OutputId,
+        cache: &mut collections::HashMap<
+            cake::Output,
+            Result<T, sync::Arc<cake::compute::ComputeError<E>>>,
+        >,
+    ) -> Result<T, sync::Arc<cake::compute::ComputeError<E>>> {
+        self.dst.compute_sync(id, cache)
+    }
+}
+
 impl<T, E> NodeEditor<T, E>
 where
     T: Clone + cake::VariantName,
@@ -130,6 +150,35 @@ where
         self.dst
             .add_owned_transform(cake::Transform::new_constant(t))
     }
+
+    /// Get IDs of the constant nodes linked to an input named `name`, e.g.
+    /// "path" for the constant node giving the path of the file opened by
+    /// `open_fits`. Returned IDs are sorted and unique.
+    pub fn constant_nodes_named(&self, name: &str) -> Vec<cake::TransformIdx> {
+        let mut ids: Vec<_> = self
+            .dst
+            .links_iter()
+            .filter_map(|(output, input)| match input {
+                cake::InputSlot::Transform(input) => {
+                    let is_constant = self.constant_node_value(output.t_idx).is_some();
+                    let is_named = self.dst.get_transform(input.t_idx).map_or(false, |t| {
+                        t.inputs()
+                            .get(input.index())
+                            .map_or(false, |slot| slot.name == name)
+                    });
+                    if is_constant && is_named {
+                        Some(output.t_idx)
+                    } else {
+                        None
+                    }
+                }
+                cake::InputSlot::Output(_) => None,
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
 }
 
 impl<T, E> NodeEditor<T, E>
@@ -162,6 +211,30 @@ impl<T, E> NodeEditor<T, E> {
             }
         })
     }
+
+    /// Get ID of the transform node numbered `id` in the node editor (i.e.
+    /// the number displayed in the node's name, such as "#5").
+    pub fn transform_idx(&self, id: usize) -> Option<cake::TransformIdx> {
+        self.dst
+            .nodes_iter()
+            .find_map(|(node_id, _)| match node_id {
+                cake::NodeId::Transform(t_idx) if t_idx.id() == id => Some(t_idx),
+                _ => None,
+            })
+    }
+
+    /// Get reference to transform of node identified by `id`.
+    pub fn transform(&self, id: cake::TransformIdx) -> Option<&cake::Transform<'static, T, E>> {
+        self.dst.get_transform(id)
+    }
+
+    /// Update the default value of the `index`-th input of the node with given
+    /// `id` with given value `val`.
+    pub fn update_default_input(&mut self, id: cake::TransformIdx, index: usize, val: T) {
+        if let Some(mut inputs) = self.dst.get_default_inputs_mut(id) {
+            inputs.write(index, val);
+        }
+    }
 }
 
 impl<T, E> NodeEditor<T, E>
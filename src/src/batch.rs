@@ -0,0 +1,50 @@
+//! Headless mode. Compute all outputs of a node graph and save them to disk
+//! without opening any window.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use cake::VariantName;
+
+use aflak::AflakNodeEditor;
+use output_window;
+
+/// Compute all the outputs attached in `node_editor` and save each of them in
+/// directory `out_dir`. `out_dir` is created if it does not exist.
+///
+/// Errors are reported on stderr. Return the number of outputs that could not
+/// be computed or saved.
+pub fn run<P: AsRef<Path>>(node_editor: &AflakNodeEditor, out_dir: P) -> usize {
+    let out_dir = out_dir.as_ref();
+    if let Err(e) = fs::create_dir_all(out_dir) {
+        eprintln!("Could not create output directory {:?}: {}", out_dir, e);
+        // Nothing can be saved
+        return node_editor.outputs().len().max(1);
+    }
+    let mut cache = HashMap::new();
+    let mut failed = 0;
+    for output in node_editor.outputs() {
+        match node_editor.compute_output_sync(output, &mut cache) {
+            Ok(value) => match output_window::save_output(&value, output, out_dir) {
+                Ok(Some(path)) => println!("Output #{} saved to {:?}", output.id(), path),
+                Ok(None) => {
+                    eprintln!(
+                        "Output #{} of type '{}' cannot be saved",
+                        output.id(),
+                        value.variant_name()
+                    );
+                    failed += 1;
+                }
+                Err(e) => {
+                    eprintln!("Error on saving output #{}: {}", output.id(), e);
+                    failed += 1;
+                }
+            },
+            Err(e) => {
+                eprintln!("Error on computing output #{}: {}", output.id(), e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
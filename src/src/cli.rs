@@ -33,4 +33,32 @@ pub fn build_cli() -> App<'static, 'static> {
                 .conflicts_with("template")
                 .help("Import editor from .ron file"),
         )
+        .arg(
+            Arg::with_name("set")
+                .short("s")
+                .long("set")
+                .value_name("ID=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Override the value of constant node #ID, or of input INPUT of node #ID \
+                     with 'ID.INPUT=VALUE'. A constant node may also be designated by the name \
+                     of the input it is linked to (e.g. 'path=VALUE'). VALUE is written in RON \
+                     format (e.g. 'Integer(120)')",
+                ),
+        )
+        .arg(
+            Arg::with_name("batch")
+                .short("b")
+                .long("batch")
+                .help("Compute all outputs and save them to disk without opening any window"),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .short("o")
+                .long("output-dir")
+                .value_name("DIRECTORY")
+                .requires("batch")
+                .help("Directory where outputs are saved in batch mode [default: .]"),
+        )
 }
@@ -79,6 +79,28 @@ fn inner_editor(ui: &Ui, constant: &IOValue, read_only: bool) -> Option<IOValue>
                 None
             }
         }
+        IOValue::Quantity(ref quantity) => {
+            let mut f = *quantity.scalar();
+            let mut unit = ImString::with_capacity(1024);
+            unit.push_str(quantity.unit().repr());
+            let value_changed = ui
+                .input_float(im_str!("Value"), &mut f)
+                .read_only(read_only)
+                .build();
+            let unit_changed = ui
+                .input_text(im_str!("Unit"), &mut unit)
+                .read_only(read_only)
+                .build();
+            if let primitives::Unit::Custom(_) = quantity.unit() {
+                ui.text("Unknown unit, cannot be converted!");
+            }
+            if value_changed || unit_changed {
+                let unit = primitives::Unit::parse(unit.to_str());
+                Some(IOValue::Quantity(primitives::Dimensioned::new(f, unit)))
+            } else {
+                None
+            }
+        }
         IOValue::Float2(ref floats) => {
             let mut f2 = *floats;
             if ui
@@ -138,7 +160,7 @@ fn inner_editor(ui: &Ui, constant: &IOValue, read_only: bool) -> Option<IOValue>
         IOValue::Roi(ref roi) => {
             match roi {
                 primitives::ROI::All => ui.text("Whole image"),
-                primitives::ROI::PixelList(_) => {
+                _ => {
                     ui.text("Non-writable");
                     if ui.is_item_hovered() {
                         ui.tooltip(|| {
@@ -6,6 +6,7 @@ extern crate glium;
 extern crate imgui;
 extern crate imgui_glium_renderer;
 extern crate owning_ref;
+extern crate ron;
 
 extern crate aflak_cake as cake;
 extern crate aflak_imgui_glium_support as support;
@@ -15,10 +16,12 @@ extern crate imgui_file_explorer;
 extern crate node_editor;
 
 mod aflak;
+mod batch;
 mod cli;
 mod constant_editor;
 mod layout;
 mod output_window;
+mod overrides;
 mod templates;
 
 use std::env;
@@ -55,14 +58,37 @@ fn main() -> support::Result<()> {
         }
     };
 
-    let node_editor = match NodeEditor::from_export_buf(import_data) {
+    let batch = matches.is_present("batch");
+    let mut node_editor = match NodeEditor::from_export_buf(import_data) {
         Ok(editor) => editor,
         Err(e) => {
+            if batch {
+                eprintln!("Import failed! Nothing to compute.\n{}", e);
+                process::exit(1)
+            }
             eprintln!("Import failed! Initialize empty node editor.\n{}", e);
             NodeEditor::default()
         }
     };
 
+    if let Some(values) = matches.values_of("set") {
+        for value in values {
+            if let Err(e) = value
+                .parse::<overrides::Override>()
+                .and_then(|o| o.apply(&mut node_editor))
+            {
+                eprintln!("Could not set '{}'. {}", value, e);
+                process::exit(1)
+            }
+        }
+    }
+
+    if batch {
+        let out_dir = path_clean_up(matches.value_of("output-dir"), ".");
+        let failed = batch::run(&node_editor, out_dir);
+        process::exit(if failed == 0 { 0 } else { 1 })
+    }
+
     let mut aflak = Aflak::init(node_editor);
 
     let config = support::AppConfig {
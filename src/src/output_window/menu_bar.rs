@@ -2,7 +2,7 @@ use std::error;
 use std::fmt;
 use std::fs;
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use glium;
@@ -13,14 +13,10 @@ use owning_ref::ArcRef;
 use aflak_plot::{
     imshow::{Textures, UiImage2d},
     plot::UiImage1d,
-    AxisTransform, InteractionIterMut, ValueIter,
+    AxisTransform, InteractionIterMut, RoiShape, ValueIter,
 };
 use cake::OutputId;
-use primitives::{
-    self,
-    fitrs::{Fits, Hdu},
-    IOValue, ROI,
-};
+use primitives::{self, fitrs::Fits, IOValue, ROI};
 
 use super::{AflakNodeEditor, EditableValues, OutputWindow};
 
@@ -65,7 +61,16 @@ pub trait MenuBar {
         errors
     }
 
-    fn file_submenu(&self, _: &Ui, _: &mut OutputWindow) {}
+    /// Additional items of the File menu. Return the path of the exported
+    /// file, if any.
+    fn file_submenu(
+        &self,
+        _: &Ui,
+        _: OutputId,
+        _: &mut OutputWindow,
+    ) -> Result<Option<PathBuf>, ExportError> {
+        Ok(None)
+    }
 
     fn file_name(&self, output: OutputId) -> String {
         format!("output-{}.{}", output.id(), Self::EXTENSION)
@@ -79,30 +84,37 @@ pub trait MenuBar {
     ) -> Vec<Box<dyn error::Error>> {
         let mut errors: Vec<Box<dyn error::Error>> = vec![];
 
-        let mut output_saved_success_popup = false;
+        let mut saved_path = None;
         ui.menu_bar(|| {
             ui.menu(im_str!("File")).build(|| {
                 if ui.menu_item(im_str!("Save")).build() {
                     let path = self.file_name(output);
-                    if let Err(e) = self.save(path) {
+                    if let Err(e) = self.save(&path) {
                         eprintln!("Error on saving output: '{}'", e);
                         errors.push(Box::new(e));
                     } else {
-                        output_saved_success_popup = true;
+                        saved_path = Some(PathBuf::from(path));
+                    }
+                }
+                match self.file_submenu(ui, output, window) {
+                    Ok(Some(path)) => saved_path = Some(path),
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("Error on exporting output: '{}'", e);
+                        errors.push(Box::new(e));
                     }
                 }
-                self.file_submenu(ui, window);
             });
         });
 
-        if output_saved_success_popup {
+        if saved_path.is_some() {
+            window.saved_path = saved_path;
             ui.open_popup(im_str!("Export completed!"));
         }
         ui.popup_modal(im_str!("Export completed!")).build(|| {
-            ui.text(format!(
-                "File saved with success to '{}'.",
-                self.file_name(output)
-            ));
+            if let Some(ref path) = window.saved_path {
+                ui.text(format!("File saved with success to '{}'.", path.display()));
+            }
             if ui.button(im_str!("Close"), (0.0, 0.0)) {
                 ui.close_current_popup();
             }
@@ -126,7 +138,10 @@ fn update_state_from_editor(
                     IOValue::Float(f) => interaction.set_value(*f),
                     IOValue::Float2(f) => interaction.set_value(*f),
                     IOValue::Float3(f) => interaction.set_value(*f),
-                    IOValue::Roi(_) => Ok(()),
+                    IOValue::Roi(roi) => match roi_to_shape(roi) {
+                        Some(shape) => interaction.set_value(shape),
+                        None => Ok(()),
+                    },
                     value => Err(format!("Cannot convert value '{:?}'", value)),
                 } {
                     eprintln!("Could not update state from editor: {}", e);
@@ -153,6 +168,7 @@ fn update_editor_from_state(
             Value::Float2(f) => IOValue::Float2(f),
             Value::Float3(f) => IOValue::Float3(f),
             Value::FinedGrainedROI(pixels) => IOValue::Roi(ROI::PixelList(pixels)),
+            Value::ShapeROI(shape) => IOValue::Roi(shape_to_roi(shape)),
         };
         if store.contains_key(id) {
             let t_idx = *store.get(id).unwrap();
@@ -164,6 +180,120 @@ fn update_editor_from_state(
     }
 }
 
+/// Convert a shape drawn in the image viewer into a region of interest.
+fn shape_to_roi(shape: RoiShape) -> ROI {
+    match shape {
+        RoiShape::Circle { center, radius } => ROI::Circle { center, radius },
+        RoiShape::Ellipse {
+            center,
+            radii,
+            angle,
+        } => ROI::Ellipse {
+            center,
+            radii,
+            angle,
+        },
+        RoiShape::Rectangle {
+            center,
+            size,
+            angle,
+        } => ROI::Rectangle {
+            center,
+            size,
+            angle,
+        },
+        RoiShape::Annulus {
+            center,
+            inner,
+            outer,
+        } => ROI::Annulus {
+            center,
+            inner,
+            outer,
+        },
+        RoiShape::Polygon(vertices) => ROI::Polygon(vertices),
+    }
+}
+
+/// Convert a geometric `roi` into a shape that can be edited in the image
+/// viewer. Return `None` if `roi` is not a single shape.
+fn roi_to_shape(roi: &ROI) -> Option<RoiShape> {
+    match *roi {
+        ROI::Circle { center, radius } => Some(RoiShape::Circle { center, radius }),
+        ROI::Ellipse {
+            center,
+            radii,
+            angle,
+        } => Some(RoiShape::Ellipse {
+            center,
+            radii,
+            angle,
+        }),
+        ROI::Rectangle {
+            center,
+            size,
+            angle,
+        } => Some(RoiShape::Rectangle {
+            center,
+            size,
+            angle,
+        }),
+        ROI::Annulus {
+            center,
+            inner,
+            outer,
+        } => Some(RoiShape::Annulus {
+            center,
+            inner,
+            outer,
+        }),
+        ROI::Polygon(ref vertices) => Some(RoiShape::Polygon(vertices.clone())),
+        _ => None,
+    }
+}
+
+/// Save `value` computed for `output` in directory `dir`, with the same file
+/// name as the one used by the "Save" item of the output window.
+///
+/// Return the path of the saved file, or `None` if `value` cannot be exported.
+pub fn save_output<P: AsRef<Path>>(
+    value: &IOValue,
+    output: OutputId,
+    dir: P,
+) -> Result<Option<PathBuf>, ExportError> {
+    fn save_to_dir<M: MenuBar + ?Sized>(
+        m: &M,
+        output: OutputId,
+        dir: &Path,
+    ) -> Result<Option<PathBuf>, ExportError> {
+        let path = dir.join(m.file_name(output));
+        m.save(&path).map_err(|e| match e {
+            ExportError::IOError(e) => ExportError::IOError(io::Error::new(
+                e.kind(),
+                format!("Could not write {:?}: {}", path, e),
+            )),
+            e => e,
+        })?;
+        Ok(Some(path))
+    }
+
+    let dir = dir.as_ref();
+    match value {
+        IOValue::Str(ref string) => save_to_dir(string, output, dir),
+        IOValue::Integer(integer) => save_to_dir(integer, output, dir),
+        IOValue::Float(float) => save_to_dir(float, output, dir),
+        IOValue::Quantity(quantity) => save_to_dir(quantity, output, dir),
+        IOValue::Float2(floats) => save_to_dir(floats, output, dir),
+        IOValue::Float3(floats) => save_to_dir(floats, output, dir),
+        IOValue::Bool(b) => save_to_dir(b, output, dir),
+        IOValue::Path(ref path) => save_to_dir(path.as_path(), output, dir),
+        IOValue::Image(ref image) => save_to_dir(image, output, dir),
+        IOValue::LazyImage(ref image) => save_to_dir(&image.load(), output, dir),
+        IOValue::Table(ref table) => save_to_dir(table, output, dir),
+        _ => Ok(None),
+    }
+}
+
 /// Used to compute the ID of a texture
 fn hash_outputid(id: OutputId) -> usize {
     use std::collections::hash_map::DefaultHasher;
@@ -222,6 +352,22 @@ impl MenuBar for f32 {
     const EXTENSION: &'static str = "txt";
 }
 
+impl MenuBar for primitives::Dimensioned<f32> {
+    fn visualize<F>(&self, ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, F>)
+    where
+        F: glium::backend::Facade,
+    {
+        ctx.ui.text(format!("{}", self));
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
+        write_to_file_as_display(path, self)?;
+        Ok(())
+    }
+
+    const EXTENSION: &'static str = "txt";
+}
+
 impl MenuBar for [f32; 2] {
     fn visualize<F>(&self, ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, F>)
     where
@@ -287,20 +433,57 @@ impl MenuBar for Path {
 }
 
 impl MenuBar for primitives::WcsArray {
-    fn file_submenu(&self, ui: &Ui, window: &mut OutputWindow) {
-        match self.scalar().ndim() {
-            1 | 2 => {
-                let has_wcs_data = self.wcs().is_some();
-                ui.menu_item(im_str!("Show pixels"))
-                    .enabled(has_wcs_data)
-                    .selected(&mut window.show_pixels)
-                    .build();
-                if !has_wcs_data && ui.is_item_hovered() {
-                    ui.tooltip_text("Data has no WCS metadata attached.");
+    fn file_submenu(
+        &self,
+        ui: &Ui,
+        output: OutputId,
+        window: &mut OutputWindow,
+    ) -> Result<Option<PathBuf>, ExportError> {
+        let ndim = self.scalar().ndim();
+        if ndim == 1 || ndim == 2 {
+            let has_wcs_data = self.wcs().is_some();
+            ui.menu_item(im_str!("Show pixels"))
+                .enabled(has_wcs_data)
+                .selected(&mut window.show_pixels)
+                .build();
+            if !has_wcs_data && ui.is_item_hovered() {
+                ui.tooltip_text("Data has no WCS metadata attached.");
+            }
+        }
+        if ndim == 2 {
+            use aflak_plot::Value;
+            let roi = window
+                .image2d_state
+                .stored_values()
+                .filter_map(|(_, value)| match value {
+                    Value::ShapeROI(shape) => Some(shape_to_roi(shape)),
+                    _ => None,
+                })
+                .fold(None, |union, roi| {
+                    Some(match union {
+                        Some(union) => ROI::Union(Box::new(union), Box::new(roi)),
+                        None => roi,
+                    })
+                });
+            if ui
+                .menu_item(im_str!("Export ROI"))
+                .enabled(roi.is_some())
+                .build()
+            {
+                if let Some(roi) = roi {
+                    // Regions are written in world coordinates if possible
+                    let wcs = self.wcs().filter(|wcs| wcs.celestial_axes().is_some());
+                    let path = PathBuf::from(format!("output-{}.reg", output.id()));
+                    let region = roi.to_ds9(wcs)?;
+                    fs::write(&path, region)?;
+                    return Ok(Some(path));
                 }
             }
-            _ => {}
+            if roi.is_none() && ui.is_item_hovered() {
+                ui.tooltip_text("Draw a region of interest on the image to export it.");
+            }
         }
+        Ok(None)
     }
 
     fn visualize<F>(&self, ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, F>)
@@ -361,18 +544,19 @@ impl MenuBar for primitives::WcsArray {
                         (Some(axes), Some(wcs)) => {
                             let axis0 = &axes[0];
                             let axis1 = &axes[1];
+                            let max_height =
+                                (self.scalar().dim().as_array_view().first().unwrap() - 1) as f32;
                             (
-                                Some({
-                                    AxisTransform::new(axis0.name(), axis0.unit(), move |t| {
-                                        wcs.pix2world([t, 0.0, 0.0, 0.0])[0]
-                                    })
-                                }),
-                                Some(AxisTransform::new(axis0.name(), axis1.unit(), {
-                                    let max_height =
-                                        (self.scalar().dim().as_array_view().first().unwrap() - 1)
-                                            as f32;
-                                    move |t| wcs.pix2world([0.0, max_height - t, 0.0, 0.0])[1]
-                                })),
+                                Some(AxisTransform::new(
+                                    axis0.name(),
+                                    axis0.unit(),
+                                    move |x, y| wcs.pix2world([x, max_height - y, 0.0, 0.0])[0],
+                                )),
+                                Some(AxisTransform::new(
+                                    axis1.name(),
+                                    axis1.unit(),
+                                    move |x, y| wcs.pix2world([x, max_height - y, 0.0, 0.0])[1],
+                                )),
                             )
                         }
                         _ => (None, None),
@@ -429,26 +613,106 @@ impl MenuBar for primitives::WcsArray {
     }
 
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
-        let arr = self.scalar();
-        // 0-dim ndarrays contain a single scalar value, however they should be
-        // treated as a 1-dimensional data array of length 1 when embedded as
-        // FITS file.
-        let shape = if arr.ndim() == 0 { &[1] } else { arr.shape() };
-        Fits::create(
-            path,
-            Hdu::new(
-                shape,
-                arr.as_slice()
-                    .expect("Could not get slice out of array")
-                    .to_owned(),
-            ),
-        )?;
+        Fits::create(path, self.to_hdu())?;
         Ok(())
     }
 
     const EXTENSION: &'static str = "fits";
 }
 
+impl MenuBar for primitives::Table {
+    fn visualize<F>(&self, ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, F>)
+    where
+        F: glium::backend::Facade,
+    {
+        /// Maximum number of rows shown in the output window
+        const MAX_ROWS: usize = 1000;
+        /// Cells with more values than this are summarized
+        const MAX_VALUES: usize = 8;
+
+        let ui = ctx.ui;
+        let columns = self.columns();
+        if columns.is_empty() {
+            ui.text("Empty table.");
+            return;
+        }
+
+        let shown_rows = self.rows().min(MAX_ROWS);
+        if shown_rows < self.rows() {
+            ui.text(format!(
+                "Showing {} of {} rows. Save the table to see all rows.",
+                shown_rows,
+                self.rows()
+            ));
+        } else {
+            ui.text(format!("{} rows", self.rows()));
+        }
+        ui.separator();
+
+        ui.columns(columns.len() as i32, im_str!("table"), true);
+        for column in columns {
+            let unit = column.unit().repr();
+            if unit.is_empty() {
+                ui.text(column.name());
+            } else {
+                ui.text(format!("{} ({})", column.name(), unit));
+            }
+            ui.next_column();
+        }
+        ui.separator();
+        for row in 0..shown_rows {
+            for column in columns {
+                if column.repeat() > MAX_VALUES {
+                    ui.text(format!("[{} values]", column.repeat()));
+                } else {
+                    ui.text(column.format_cell(row));
+                }
+                ui.next_column();
+            }
+        }
+        ui.columns(1, im_str!(""), false);
+    }
+
+    /// Save table as comma-separated values.
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
+        fn escape(cell: &str) -> String {
+            if cell.contains(|c: char| c == ',' || c == '"' || c == '\n') {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_owned()
+            }
+        }
+
+        let columns = self.columns();
+        let mut buf = String::new();
+        let header: Vec<_> = columns
+            .iter()
+            .map(|column| {
+                let unit = column.unit().repr();
+                if unit.is_empty() {
+                    escape(column.name())
+                } else {
+                    escape(&format!("{} ({})", column.name(), unit))
+                }
+            })
+            .collect();
+        buf.push_str(&header.join(","));
+        buf.push('\n');
+        for row in 0..self.rows() {
+            let cells: Vec<_> = columns
+                .iter()
+                .map(|column| escape(&column.format_cell(row)))
+                .collect();
+            buf.push_str(&cells.join(","));
+            buf.push('\n');
+        }
+        write_to_file_as_bytes(path, buf.as_bytes())?;
+        Ok(())
+    }
+
+    const EXTENSION: &'static str = "csv";
+}
+
 fn write_to_file_as_display<P: AsRef<Path>, T: fmt::Display>(path: P, t: &T) -> io::Result<()> {
     let buf = format!("{}\n", t);
     write_to_file_as_bytes(path, buf.as_bytes())
@@ -465,12 +729,14 @@ fn write_to_file_as_bytes<P: AsRef<Path>>(path: P, buf: &[u8]) -> io::Result<()>
 #[derive(Debug)]
 pub enum ExportError {
     IOError(io::Error),
+    RegionError(primitives::RegionError),
 }
 
 impl fmt::Display for ExportError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ExportError::IOError(e) => write!(fmt, "{}", e),
+            ExportError::RegionError(e) => write!(fmt, "{}", e),
         }
     }
 }
@@ -488,3 +754,9 @@ impl From<io::Error> for ExportError {
         ExportError::IOError(e)
     }
 }
+
+impl From<primitives::RegionError> for ExportError {
+    fn from(e: primitives::RegionError) -> Self {
+        ExportError::RegionError(e)
+    }
+}
@@ -1,5 +1,8 @@
 use std::collections::HashMap;
 use std::error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 mod menu_bar;
 mod visualizable;
@@ -15,6 +18,7 @@ use aflak_plot::{
 use cake::{OutputId, TransformIdx};
 use primitives::{ndarray, IOValue, SuccessOut};
 
+pub use self::menu_bar::save_output;
 use self::menu_bar::MenuBar;
 use self::visualizable::{Initializing, Unimplemented, Visualizable};
 use aflak::AflakNodeEditor;
@@ -25,6 +29,11 @@ pub struct OutputWindow {
     image2d_state: imshow::State<ArcRef<IOValue, ndarray::ArrayD<f32>>>,
     editable_values: EditableValues,
     show_pixels: bool,
+    /// Path of the last file saved from the menu bar
+    saved_path: Option<PathBuf>,
+    /// Lazy image loaded in memory to be visualized, with the time it was
+    /// computed on
+    loaded_image: Option<(Instant, Arc<IOValue>)>,
 }
 
 type EditableValues = HashMap<InteractionId, TransformIdx>;
@@ -55,6 +64,7 @@ impl OutputWindow {
             Some(Ok(result)) => {
                 let created_on = SuccessOut::created_on(&result);
                 let value = SuccessOut::take(result);
+                let value = self.load_lazy_image(&value, created_on);
                 let ctx = menu_bar::OutputWindowCtx {
                     ui,
                     output,
@@ -69,10 +79,12 @@ impl OutputWindow {
                     IOValue::Str(ref string) => string.draw(ctx, window),
                     IOValue::Integer(integer) => integer.draw(ctx, window),
                     IOValue::Float(float) => float.draw(ctx, window),
+                    IOValue::Quantity(quantity) => quantity.draw(ctx, window),
                     IOValue::Float2(floats) => floats.draw(ctx, window),
                     IOValue::Float3(floats) => floats.draw(ctx, window),
                     IOValue::Bool(b) => b.draw(ctx, window),
                     IOValue::Image(ref image) => image.draw(ctx, window),
+                    IOValue::Table(ref table) => table.draw(ctx, window),
                     IOValue::Fits(ref fits) => {
                         fits.draw(ui, window);
                         vec![]
@@ -85,4 +97,21 @@ impl OutputWindow {
             }
         }
     }
+
+    /// Load lazy image in memory to visualize it. The loaded image is kept
+    /// until a new value is computed.
+    fn load_lazy_image(&mut self, value: &Arc<IOValue>, created_on: Instant) -> Arc<IOValue> {
+        let image = match *value {
+            IOValue::LazyImage(ref image) => image,
+            _ => return value.clone(),
+        };
+        if let Some((loaded_on, ref loaded)) = self.loaded_image {
+            if loaded_on == created_on {
+                return loaded.clone();
+            }
+        }
+        let loaded = Arc::new(IOValue::Image(image.load()));
+        self.loaded_image = Some((created_on, loaded.clone()));
+        loaded
+    }
 }
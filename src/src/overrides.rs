@@ -0,0 +1,304 @@
+//! Override values of an imported node graph from the command line.
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use ron;
+
+use cake::{Bow, ConvertibleVariants, VariantName};
+use primitives::IOValue;
+
+use aflak::AflakNodeEditor;
+
+/// A value to set in the node graph.
+///
+/// Parsed from `ID=VALUE` to replace the value of the constant node `#ID`,
+/// or from `ID.INPUT=VALUE` to replace the default value of the input named
+/// `INPUT` of node `#ID`. `VALUE` is written in RON format, e.g.
+/// `Integer(120)` or `Path("/data/frame.fits")`.
+///
+/// A constant node can also be named after the input it is linked to, e.g.
+/// `path=Path("/data/frame.fits")` replaces the value of the constant node
+/// linked to the `path` input of `open_fits`.
+#[derive(Debug)]
+pub struct Override {
+    target: Target,
+    input_name: Option<String>,
+    value: IOValue,
+}
+
+/// Node targeted by an override.
+#[derive(Debug)]
+enum Target {
+    /// Node number, as displayed in the node editor
+    Id(usize),
+    /// Name of the input the constant node is linked to
+    Name(String),
+}
+
+#[derive(Debug)]
+pub enum OverrideError {
+    ParseError(String),
+    NodeNotFound(usize),
+    NotAConstant(usize),
+    NameNotFound(String),
+    AmbiguousName {
+        name: String,
+        node_ids: Vec<usize>,
+    },
+    InputNotFound {
+        node_id: usize,
+        input_name: String,
+    },
+    UnexpectedType {
+        node_id: usize,
+        expected: &'static str,
+        got: &'static str,
+    },
+}
+
+impl FromStr for Override {
+    type Err = OverrideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '=');
+        let target = split.next().unwrap_or("");
+        let value = split.next().ok_or_else(|| {
+            OverrideError::ParseError(format!("Expected 'ID=VALUE', but got '{}'", s))
+        })?;
+
+        let target = target.trim();
+        let is_name = target.starts_with(|c: char| c.is_alphabetic() || c == '_');
+        let (target, input_name) = if is_name {
+            (Target::Name(target.to_owned()), None)
+        } else {
+            let mut target = target.trim_start_matches('#').splitn(2, '.');
+            let node_id = target.next().unwrap_or("").parse().map_err(|e| {
+                OverrideError::ParseError(format!("Invalid node ID in '{}': {}", s, e))
+            })?;
+            (Target::Id(node_id), target.next().map(str::to_owned))
+        };
+        let value = ron::de::from_str(value.trim())
+            .map_err(|e| OverrideError::ParseError(format!("Invalid value in '{}': {}", s, e)))?;
+
+        Ok(Self {
+            target,
+            input_name,
+            value,
+        })
+    }
+}
+
+impl Override {
+    /// Write the overriding value into the node graph of `node_editor`.
+    ///
+    /// The value is converted to the expected type if possible (e.g. an
+    /// `Integer` can be set to a `Float` constant).
+    pub fn apply(&self, node_editor: &mut AflakNodeEditor) -> Result<(), OverrideError> {
+        let t_idx = match self.target {
+            Target::Id(node_id) => node_editor
+                .transform_idx(node_id)
+                .ok_or(OverrideError::NodeNotFound(node_id))?,
+            Target::Name(ref name) => {
+                let t_indices = node_editor.constant_nodes_named(name);
+                match t_indices.len() {
+                    0 => return Err(OverrideError::NameNotFound(name.clone())),
+                    1 => t_indices[0],
+                    _ => {
+                        return Err(OverrideError::AmbiguousName {
+                            name: name.clone(),
+                            node_ids: t_indices.iter().map(|t_idx| t_idx.id()).collect(),
+                        })
+                    }
+                }
+            }
+        };
+        let node_id = t_idx.id();
+        if let Some(ref input_name) = self.input_name {
+            let (index, expected) = node_editor
+                .transform(t_idx)
+                .and_then(|t| {
+                    t.inputs()
+                        .iter()
+                        .enumerate()
+                        .find(|(_, slot)| slot.name == input_name.as_str())
+                        .map(|(i, slot)| (i, slot.type_id.name()))
+                })
+                .ok_or_else(|| OverrideError::InputNotFound {
+                    node_id,
+                    input_name: input_name.clone(),
+                })?;
+            let value = self.convert_into(node_id, expected)?;
+            node_editor.update_default_input(t_idx, index, value);
+        } else {
+            let expected = node_editor
+                .constant_node_value(t_idx)
+                .map(VariantName::variant_name)
+                .ok_or(OverrideError::NotAConstant(node_id))?;
+            let value = self.convert_into(node_id, expected)?;
+            node_editor.update_constant_node(t_idx, value);
+        }
+        Ok(())
+    }
+
+    fn convert_into(
+        &self,
+        node_id: usize,
+        expected: &'static str,
+    ) -> Result<IOValue, OverrideError> {
+        let got = self.value.variant_name();
+        match IOValue::convert(got, expected, &self.value) {
+            Some(Bow::Borrowed(value)) => Ok(value.clone()),
+            Some(Bow::Owned(value)) => Ok(value),
+            None => Err(OverrideError::UnexpectedType {
+                node_id,
+                expected,
+                got,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OverrideError::*;
+
+        match self {
+            ParseError(s) => write!(f, "Could not parse override! {}", s),
+            NodeNotFound(id) => write!(f, "Node #{} not found", id),
+            NotAConstant(id) => write!(
+                f,
+                "Node #{} is not a constant node. Use 'ID.INPUT=VALUE' to set one of its inputs",
+                id
+            ),
+            NameNotFound(name) => {
+                write!(f, "No constant node is linked to an input named '{}'", name)
+            }
+            AmbiguousName { name, node_ids } => {
+                let node_ids: Vec<_> = node_ids.iter().map(|id| format!("#{}", id)).collect();
+                write!(
+                    f,
+                    "Several constant nodes are linked to an input named '{}' ({}). Use 'ID=VALUE' instead",
+                    name,
+                    node_ids.join(", ")
+                )
+            }
+            InputNotFound {
+                node_id,
+                input_name,
+            } => write!(f, "Node #{} has no input named '{}'", node_id, input_name),
+            UnexpectedType {
+                node_id,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Unexpected type for node #{}! Expected '{}' but got '{}'",
+                node_id, expected, got
+            ),
+        }
+    }
+}
+
+impl error::Error for OverrideError {
+    fn description(&self) -> &str {
+        "OverrideError"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use primitives::{Dimensioned, IOValue, Unit};
+
+    use super::{Override, OverrideError, Target};
+
+    fn parse(s: &str) -> Override {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_constant_node() {
+        let o = parse("12=Integer(120)");
+        match o.target {
+            Target::Id(12) => {}
+            ref target => panic!("Unexpected target {:?}", target),
+        }
+        assert_eq!(o.input_name, None);
+        assert_eq!(o.value, IOValue::Integer(120));
+
+        // Node numbers may be written as displayed in the node editor
+        let o = parse(" #12 = Float(1.5) ");
+        match o.target {
+            Target::Id(12) => {}
+            ref target => panic!("Unexpected target {:?}", target),
+        }
+        assert_eq!(o.value, IOValue::Float(1.5));
+    }
+
+    #[test]
+    fn parse_node_input() {
+        let o = parse("3.path=Path(\"/data/frame.fits\")");
+        match o.target {
+            Target::Id(3) => {}
+            ref target => panic!("Unexpected target {:?}", target),
+        }
+        assert_eq!(o.input_name, Some("path".to_owned()));
+        assert_eq!(o.value, IOValue::Path("/data/frame.fits".into()));
+    }
+
+    #[test]
+    fn parse_input_name() {
+        for &name in &["path", "w_0", "_z"] {
+            let o = parse(&format!("{}=Float(2)", name));
+            match o.target {
+                Target::Name(ref target) => assert_eq!(target, name),
+                ref target => panic!("Unexpected target {:?}", target),
+            }
+            assert_eq!(o.input_name, None);
+            assert_eq!(o.value, IOValue::Float(2.0));
+        }
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for s in &[
+            "12",
+            "",
+            "=Integer(1)",
+            "12=",
+            "12=120",
+            "12=Integer(",
+            "1x=Integer(1)",
+            "-1=Integer(1)",
+            ".path=Integer(1)",
+            "path=",
+        ] {
+            match s.parse::<Override>() {
+                Err(OverrideError::ParseError(_)) => {}
+                result => panic!("Expected parse error for '{}', got {:?}", s, result),
+            }
+        }
+    }
+
+    #[test]
+    fn convert_value() {
+        let o = parse("4=Integer(2)");
+        assert_eq!(o.convert_into(4, "Integer").unwrap(), IOValue::Integer(2));
+        assert_eq!(o.convert_into(4, "Float").unwrap(), IOValue::Float(2.0));
+        match o.convert_into(4, "Path") {
+            Err(OverrideError::UnexpectedType {
+                node_id: 4,
+                expected: "Path",
+                got: "Integer",
+            }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        // Floats are quantities without unit
+        let o = parse("w_0=Float(6765.6)");
+        assert_eq!(
+            o.convert_into(1, "Quantity").unwrap(),
+            IOValue::Quantity(Dimensioned::new(6765.6, Unit::None))
+        );
+    }
+}
@@ -21,22 +21,24 @@ pub fn show_frame_and_wave<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((3), (
-                    t: Function("fits_to_image", 1, 0, 0),
+                    t: Function("fits_to_image", 1, 3, 0),
                     input_defaults: [
                         None,
                         Some(Integer(0)),
                         Some(Str("FLUX")),
+                        Some(Str("")),
+                        Some(Str("")),
                     ],
                 )),
                 ((4), (
-                    t: Function("extract_wave", 1, 0, 0),
+                    t: Function("extract_wave", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Roi(All)),
                     ],
                 )),
                 ((5), (
-                    t: Function("slice_one_frame", 1, 0, 0),
+                    t: Function("slice_one_frame", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Integer(100)),
@@ -147,15 +149,17 @@ pub fn show_equivalent_width<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((3), (
-                    t: Function("fits_to_image", 1, 0, 0),
+                    t: Function("fits_to_image", 1, 3, 0),
                     input_defaults: [
                         None,
                         Some(Integer(0)),
                         Some(Str("FLUX")),
+                        Some(Str("")),
+                        Some(Str("")),
                     ],
                 )),
                 ((19), (
-                    t: Function("average", 1, 0, 0),
+                    t: Function("average", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Integer(3134)),
@@ -163,7 +167,7 @@ pub fn show_equivalent_width<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((20), (
-                    t: Function("average", 1, 0, 0),
+                    t: Function("average", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Integer(3099)),
@@ -171,7 +175,7 @@ pub fn show_equivalent_width<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((21), (
-                    t: Function("average", 1, 0, 0),
+                    t: Function("average", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Integer(3174)),
@@ -188,11 +192,11 @@ pub fn show_equivalent_width<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((23), (
-                    t: Function("create_equivalent_width", 0, 1, 0),
+                    t: Function("create_equivalent_width", 0, 2, 0),
                     input_defaults: [
                         None,
                         None,
-                        Some(Float(20)),
+                        Some(Quantity((value: 20, unit: None, homogeneous: true))),
                         Some(Float(10000000000)),
                         Some(Bool(true)),
                     ],
@@ -469,15 +473,17 @@ pub fn show_velocity_field<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((3), (
-                    t: Function("fits_to_image", 1, 0, 0),
+                    t: Function("fits_to_image", 1, 3, 0),
                     input_defaults: [
                         None,
                         Some(Integer(0)),
                         Some(Str("FLUX")),
+                        Some(Str("")),
+                        Some(Str("")),
                     ],
                 )),
                 ((4), (
-                    t: Function("extract_argmin_max_wavelength", 0, 1, 0),
+                    t: Function("extract_argmin_max_wavelength", 0, 2, 0),
                     input_defaults: [
                         None,
                         Some(Integer(3135)),
@@ -486,7 +492,7 @@ pub fn show_velocity_field<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((5), (
-                    t: Function("extract_centrobaric_wavelength", 0, 1, 0),
+                    t: Function("extract_centrobaric_wavelength", 0, 2, 0),
                     input_defaults: [
                         None,
                         Some(Integer(3135)),
@@ -494,17 +500,17 @@ pub fn show_velocity_field<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((6), (
-                    t: Function("create_velocity_field_map", 1, 0, 0),
+                    t: Function("create_velocity_field_map", 1, 1, 0),
                     input_defaults: [
                         None,
-                        Some(Float(6765.6)),
+                        Some(Quantity((value: 6765.6, unit: None, homogeneous: true))),
                     ],
                 )),
                 ((7), (
-                    t: Function("create_velocity_field_map", 1, 0, 0),
+                    t: Function("create_velocity_field_map", 1, 1, 0),
                     input_defaults: [
                         None,
-                        Some(Float(6765.6)),
+                        Some(Quantity((value: 6765.6, unit: None, homogeneous: true))),
                     ],
                 )),
             ],
@@ -634,33 +640,36 @@ pub fn show_fits_cleaning<P: AsRef<Path>>(path: P) -> Cursor<String> {
                     ],
                 )),
                 ((3), (
-                    t: Function("fits_to_image", 1, 0, 0),
+                    t: Function("fits_to_image", 1, 3, 0),
                     input_defaults: [
                         None,
                         Some(Integer(0)),
                         Some(Str("")),
+                        Some(Str("")),
+                        Some(Str("")),
                     ],
                 )),
                 ((4), (
-                    t: Function("extract_wave", 1, 0, 0),
+                    t: Function("extract_wave", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Roi(All)),
                     ],
                 )),
                 ((6), (
-                    t: Function("slice_one_frame", 1, 0, 0),
+                    t: Function("slice_one_frame", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Integer(0)),
                     ],
                 )),
                 ((8), (
-                    t: Function("clip_image", 1, 0, 0),
+                    t: Function("clip_image", 1, 1, 0),
                     input_defaults: [
                         None,
                         Some(Float(0)),
                         Some(Bool(false)),
+                        Some(Bool(false)),
                     ],
                 )),
                 ((9), (
@@ -807,6 +816,8 @@ pub fn show_fits_cleaning<P: AsRef<Path>>(path: P) -> Cursor<String> {
 
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
     use aflak::AflakNodeEditor;
 
     use super::{
@@ -847,4 +858,33 @@ mod test {
         let editor = AflakNodeEditor::from_export_buf(buf);
         assert!(editor.is_ok());
     }
+
+    /// Files saved before `fl` and `w_0` became quantities have Float
+    /// defaults. They must still be importable.
+    #[test]
+    fn import_legacy_float_defaults() {
+        let legacy = |buf: Cursor<String>| {
+            Cursor::new(
+                buf.into_inner()
+                    .replace(
+                        "Quantity((value: 20, unit: None, homogeneous: true))",
+                        "Float(20)",
+                    )
+                    .replace(
+                        "Quantity((value: 6765.6, unit: None, homogeneous: true))",
+                        "Float(6765.6)",
+                    ),
+            )
+        };
+
+        let buf = legacy(show_equivalent_width("file.fits"));
+        assert!(buf.get_ref().contains("Some(Float(20))"));
+        let editor = AflakNodeEditor::from_export_buf(buf);
+        assert!(editor.is_ok());
+
+        let buf = legacy(show_velocity_field("file.fits"));
+        assert!(buf.get_ref().contains("Some(Float(6765.6))"));
+        let editor = AflakNodeEditor::from_export_buf(buf);
+        assert!(editor.is_ok());
+    }
 }